@@ -1,7 +1,5 @@
-#[cfg(target_arch = "wasm32")]
-mod systems;
-#[cfg(target_arch = "wasm32")]
-mod utils;
+pub mod systems;
+pub mod utils;
 
 // Entry point for wasm
 #[cfg(target_arch = "wasm32")]
@@ -19,3 +17,21 @@ pub async fn xpbd_neohookean(canvas_id: &str) -> Result<(), JsValue> {
     systems::constraints::xpbd::neohookean::interface::run(canvas_id).await;
     Ok(())
 }
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub async fn vbd_neohookean(canvas_id: &str) -> Result<(), JsValue> {
+    systems::constraints::vbd::interface::run(canvas_id).await;
+    Ok(())
+}
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub async fn projective_dynamics(canvas_id: &str) -> Result<(), JsValue> {
+    systems::constraints::projective_dynamics::interface::run(canvas_id).await;
+    Ok(())
+}
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub async fn xpbd_rigid_bodies(canvas_id: &str) -> Result<(), JsValue> {
+    systems::constraints::xpbd::rigid_bodies::interface::run(canvas_id);
+    Ok(())
+}
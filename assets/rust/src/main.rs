@@ -1,6 +1,4 @@
-mod systems;
-mod utils;
-use tokio;
+use game_physics_simulation::systems;
 
 #[cfg(not(target_arch = "wasm32"))]
 #[tokio::main]
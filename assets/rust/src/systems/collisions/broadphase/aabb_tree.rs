@@ -0,0 +1,501 @@
+use nalgebra::SVector;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb<const D: usize> {
+    pub min: SVector<f64, D>,
+    pub max: SVector<f64, D>,
+}
+
+impl<const D: usize> Aabb<D> {
+    pub fn new(min: SVector<f64, D>, max: SVector<f64, D>) -> Self {
+        Aabb { min, max }
+    }
+
+    pub fn from_sphere(center: &SVector<f64, D>, radius: f64) -> Self {
+        let r = SVector::<f64, D>::repeat(radius);
+        Aabb::new(center - r, center + r)
+    }
+
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a SVector<f64, D>>) -> Self {
+        let mut aabb: Aabb<D> = Aabb::new(
+            SVector::repeat(f64::INFINITY),
+            SVector::repeat(f64::NEG_INFINITY),
+        );
+        for p in points {
+            aabb.min = aabb.min.inf(p);
+            aabb.max = aabb.max.sup(p);
+        }
+        aabb
+    }
+
+    pub fn union(&self, other: &Aabb<D>) -> Aabb<D> {
+        Aabb::new(self.min.inf(&other.min), self.max.sup(&other.max))
+    }
+
+    pub fn fattened(&self, margin: f64) -> Aabb<D> {
+        let m = SVector::<f64, D>::repeat(margin);
+        Aabb::new(self.min - m, self.max + m)
+    }
+
+    pub fn contains(&self, other: &Aabb<D>) -> bool {
+        (0..D).all(|i| self.min[i] <= other.min[i] && other.max[i] <= self.max[i])
+    }
+
+    pub fn overlaps(&self, other: &Aabb<D>) -> bool {
+        (0..D).all(|i| self.min[i] <= other.max[i] && other.min[i] <= self.max[i])
+    }
+
+    // perimeter in 2D, surface area in 3D (up to a constant factor)
+    pub fn area(&self) -> f64 {
+        let e = self.max - self.min;
+        (0..D)
+            .map(|i| (0..D).filter(|&j| j != i).map(|j| e[j]).product::<f64>())
+            .sum()
+    }
+
+    pub fn distance_squared(&self, p: &SVector<f64, D>) -> f64 {
+        (0..D)
+            .map(|i| {
+                let d = (self.min[i] - p[i]).max(p[i] - self.max[i]).max(0.0);
+                d * d
+            })
+            .sum()
+    }
+
+    // slab test, returns the entry parameter t in [0, max_t]
+    pub fn ray_intersect(
+        &self,
+        origin: &SVector<f64, D>,
+        dir: &SVector<f64, D>,
+        max_t: f64,
+    ) -> Option<f64> {
+        let mut t_min: f64 = 0.0;
+        let mut t_max = max_t;
+        for i in 0..D {
+            if dir[i].abs() < 1e-12 {
+                if origin[i] < self.min[i] || origin[i] > self.max[i] {
+                    return None;
+                }
+                continue;
+            }
+            let inv = 1.0 / dir[i];
+            let mut t0 = (self.min[i] - origin[i]) * inv;
+            let mut t1 = (self.max[i] - origin[i]) * inv;
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+}
+
+struct Node<const D: usize> {
+    aabb: Aabb<D>,
+    parent: Option<usize>,
+    children: Option<[usize; 2]>,
+    id: usize,
+}
+
+// Dynamic AABB tree. Leaves store fattened boxes, so small motions do not
+// touch the tree; a leaf is reinserted only when it leaves its fat box.
+pub struct AabbTree<const D: usize> {
+    nodes: Vec<Node<D>>,
+    free: Vec<usize>,
+    root: Option<usize>,
+    margin: f64,
+    stack: Vec<usize>,
+}
+
+impl<const D: usize> AabbTree<D> {
+    pub fn new(margin: f64) -> Self {
+        AabbTree {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: None,
+            margin,
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn fat_aabb(&self, proxy: usize) -> &Aabb<D> {
+        &self.nodes[proxy].aabb
+    }
+
+    fn allocate(&mut self, aabb: Aabb<D>, id: usize) -> usize {
+        let node = Node {
+            aabb,
+            parent: None,
+            children: None,
+            id,
+        };
+        match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = node;
+                i
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    // Inserts a leaf carrying the user id, returns the proxy used by update/remove.
+    pub fn insert(&mut self, aabb: Aabb<D>, id: usize) -> usize {
+        let leaf = self.allocate(aabb.fattened(self.margin), id);
+        self.insert_leaf(leaf);
+        leaf
+    }
+
+    pub fn remove(&mut self, proxy: usize) {
+        self.remove_leaf(proxy);
+        self.free.push(proxy);
+    }
+
+    // Incremental update: returns true if the leaf had to be reinserted.
+    pub fn update(&mut self, proxy: usize, aabb: Aabb<D>) -> bool {
+        if self.nodes[proxy].aabb.contains(&aabb) {
+            return false;
+        }
+        self.remove_leaf(proxy);
+        self.nodes[proxy].aabb = aabb.fattened(self.margin);
+        self.insert_leaf(proxy);
+        true
+    }
+
+    // Replaces the leaf box and enlarges ancestors without restructuring.
+    pub fn refit(&mut self, proxy: usize, aabb: Aabb<D>) {
+        self.nodes[proxy].aabb = aabb.fattened(self.margin);
+        if let Some(parent) = self.nodes[proxy].parent {
+            self.refit_ancestors(parent);
+        }
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        let Some(root) = self.root else {
+            self.nodes[leaf].parent = None;
+            self.root = Some(leaf);
+            return;
+        };
+
+        // descend choosing the child with the smaller area increase
+        let leaf_aabb = self.nodes[leaf].aabb;
+        let mut index = root;
+        while let Some([c0, c1]) = self.nodes[index].children {
+            let area = self.nodes[index].aabb.area();
+            let combined_area = self.nodes[index].aabb.union(&leaf_aabb).area();
+            let cost = 2.0 * combined_area;
+            let inheritance = 2.0 * (combined_area - area);
+
+            let child_cost = |c: usize| {
+                let union_area = self.nodes[c].aabb.union(&leaf_aabb).area();
+                if self.nodes[c].children.is_none() {
+                    union_area + inheritance
+                } else {
+                    union_area - self.nodes[c].aabb.area() + inheritance
+                }
+            };
+            let cost0 = child_cost(c0);
+            let cost1 = child_cost(c1);
+            if cost < cost0 && cost < cost1 {
+                break;
+            }
+            index = if cost0 < cost1 { c0 } else { c1 };
+        }
+
+        let sibling = index;
+        let old_parent = self.nodes[sibling].parent;
+        let new_parent = self.allocate(self.nodes[sibling].aabb.union(&leaf_aabb), usize::MAX);
+        self.nodes[new_parent].parent = old_parent;
+        self.nodes[new_parent].children = Some([sibling, leaf]);
+        self.nodes[sibling].parent = Some(new_parent);
+        self.nodes[leaf].parent = Some(new_parent);
+        match old_parent {
+            Some(p) => {
+                let children = self.nodes[p].children.as_mut().unwrap();
+                if children[0] == sibling {
+                    children[0] = new_parent;
+                } else {
+                    children[1] = new_parent;
+                }
+            }
+            None => self.root = Some(new_parent),
+        }
+        self.refit_ancestors(new_parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: usize) {
+        if self.root == Some(leaf) {
+            self.root = None;
+            return;
+        }
+        let parent = self.nodes[leaf].parent.unwrap();
+        let [c0, c1] = self.nodes[parent].children.unwrap();
+        let sibling = if c0 == leaf { c1 } else { c0 };
+        let grand_parent = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grand_parent;
+        match grand_parent {
+            Some(g) => {
+                let children = self.nodes[g].children.as_mut().unwrap();
+                if children[0] == parent {
+                    children[0] = sibling;
+                } else {
+                    children[1] = sibling;
+                }
+                self.refit_ancestors(g);
+            }
+            None => self.root = Some(sibling),
+        }
+        self.nodes[leaf].parent = None;
+        self.free.push(parent);
+    }
+
+    fn refit_ancestors(&mut self, start: usize) {
+        let mut node = Some(start);
+        while let Some(i) = node {
+            let [a, b] = self.nodes[i].children.unwrap();
+            self.nodes[i].aabb = self.nodes[a].aabb.union(&self.nodes[b].aabb);
+            node = self.nodes[i].parent;
+        }
+    }
+
+    // Ids of all leaves whose fat box overlaps aabb.
+    pub fn query_overlap(&mut self, aabb: &Aabb<D>, out: &mut Vec<usize>) {
+        out.clear();
+        let Some(root) = self.root else {
+            return;
+        };
+        self.stack.clear();
+        self.stack.push(root);
+        while let Some(i) = self.stack.pop() {
+            let node = &self.nodes[i];
+            if !node.aabb.overlaps(aabb) {
+                continue;
+            }
+            match node.children {
+                Some([a, b]) => {
+                    self.stack.push(a);
+                    self.stack.push(b);
+                }
+                None => out.push(node.id),
+            }
+        }
+    }
+
+    // Closest hit along the ray. hit_test gets a leaf id and returns the exact
+    // ray parameter of the hit, if any.
+    pub fn raycast(
+        &mut self,
+        origin: &SVector<f64, D>,
+        dir: &SVector<f64, D>,
+        max_t: f64,
+        mut hit_test: impl FnMut(usize) -> Option<f64>,
+    ) -> Option<(usize, f64)> {
+        let root = self.root?;
+        let mut best: Option<(usize, f64)> = None;
+        let mut best_t = max_t;
+        self.stack.clear();
+        self.stack.push(root);
+        while let Some(i) = self.stack.pop() {
+            let node = &self.nodes[i];
+            if node.aabb.ray_intersect(origin, dir, best_t).is_none() {
+                continue;
+            }
+            match node.children {
+                Some([a, b]) => {
+                    self.stack.push(a);
+                    self.stack.push(b);
+                }
+                None => {
+                    if let Some(t) = hit_test(node.id)
+                        && t <= best_t
+                    {
+                        best_t = t;
+                        best = Some((node.id, t));
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    // Branch and bound nearest leaf. dist_squared gets a leaf id and returns
+    // the exact squared distance from p to the object.
+    pub fn nearest(
+        &mut self,
+        p: &SVector<f64, D>,
+        mut dist_squared: impl FnMut(usize) -> f64,
+    ) -> Option<(usize, f64)> {
+        let root = self.root?;
+        let mut best: Option<(usize, f64)> = None;
+        let mut best_d = f64::INFINITY;
+        self.stack.clear();
+        self.stack.push(root);
+        while let Some(i) = self.stack.pop() {
+            let node = &self.nodes[i];
+            if node.aabb.distance_squared(p) > best_d {
+                continue;
+            }
+            match node.children {
+                Some([a, b]) => {
+                    // visit the closer child first
+                    let da = self.nodes[a].aabb.distance_squared(p);
+                    let db = self.nodes[b].aabb.distance_squared(p);
+                    if da < db {
+                        self.stack.push(b);
+                        self.stack.push(a);
+                    } else {
+                        self.stack.push(a);
+                        self.stack.push(b);
+                    }
+                }
+                None => {
+                    let d = dist_squared(node.id);
+                    if d < best_d {
+                        best_d = d;
+                        best = Some((node.id, d));
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector2;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_box(rng: &mut StdRng) -> Aabb<2> {
+        let center = Vector2::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+        Aabb::from_sphere(&center, rng.gen_range(0.5..5.0))
+    }
+
+    // Parents enclose their children and point back to them, every leaf is
+    // reachable from the root exactly once.
+    fn check_invariants(tree: &AabbTree<2>, num_leaves: usize) {
+        let mut leaves = 0;
+        let mut stack: Vec<usize> = tree.root.into_iter().collect();
+        if let Some(root) = tree.root {
+            assert_eq!(tree.nodes[root].parent, None);
+        }
+        while let Some(i) = stack.pop() {
+            match tree.nodes[i].children {
+                Some(children) => {
+                    for c in children {
+                        assert_eq!(tree.nodes[c].parent, Some(i));
+                        assert!(tree.nodes[i].aabb.contains(&tree.nodes[c].aabb));
+                        stack.push(c);
+                    }
+                }
+                None => leaves += 1,
+            }
+        }
+        assert_eq!(leaves, num_leaves);
+    }
+
+    #[test]
+    fn invariants_and_overlaps_after_changes() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut tree = AabbTree::new(0.5);
+        let mut boxes: Vec<Aabb<2>> = (0..100).map(|_| random_box(&mut rng)).collect();
+        let mut proxies: Vec<Option<usize>> = boxes
+            .iter()
+            .enumerate()
+            .map(|(id, b)| Some(tree.insert(*b, id)))
+            .collect();
+        check_invariants(&tree, 100);
+        for proxy in proxies.iter_mut().step_by(3) {
+            tree.remove(proxy.take().unwrap());
+        }
+        let live = proxies.iter().flatten().count();
+        check_invariants(&tree, live);
+        for id in 0..boxes.len() {
+            let Some(proxy) = proxies[id] else {
+                continue;
+            };
+            let shift = Vector2::new(rng.gen_range(-2.0..2.0), rng.gen_range(-2.0..2.0));
+            boxes[id] = Aabb::new(boxes[id].min + shift, boxes[id].max + shift);
+            if id % 2 == 0 {
+                tree.update(proxy, boxes[id]);
+            } else {
+                tree.refit(proxy, boxes[id]);
+            }
+            assert!(tree.fat_aabb(proxy).contains(&boxes[id]));
+        }
+        check_invariants(&tree, live);
+
+        let mut found = Vec::new();
+        for _ in 0..50 {
+            let query = random_box(&mut rng);
+            tree.query_overlap(&query, &mut found);
+            found.sort_unstable();
+            let expected: Vec<usize> = (0..boxes.len())
+                .filter(|&id| proxies[id].is_some_and(|p| tree.fat_aabb(p).overlaps(&query)))
+                .collect();
+            assert_eq!(found, expected);
+            // the fat boxes never miss an overlap of the exact ones
+            for id in 0..boxes.len() {
+                if proxies[id].is_some() && boxes[id].overlaps(&query) {
+                    assert!(found.contains(&id));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_matches_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let points: Vec<Vector2<f64>> = (0..200)
+            .map(|_| Vector2::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0)))
+            .collect();
+        let mut tree = AabbTree::new(0.1);
+        for (id, p) in points.iter().enumerate() {
+            tree.insert(Aabb::from_sphere(p, 0.0), id);
+        }
+        for _ in 0..50 {
+            let q = Vector2::new(rng.gen_range(-10.0..110.0), rng.gen_range(-10.0..110.0));
+            let dist = |id: usize| (points[id] - q).norm_squared();
+            let expected = (0..points.len())
+                .min_by(|&a, &b| dist(a).total_cmp(&dist(b)))
+                .unwrap();
+            assert_eq!(tree.nearest(&q, dist), Some((expected, dist(expected))));
+        }
+    }
+
+    #[test]
+    fn raycast_returns_the_first_hit() {
+        // unit boxes centered at x = 2, 5, 8 on the ray, inserted far to near
+        let boxes: Vec<Aabb<2>> = [8.0, 5.0, 2.0]
+            .iter()
+            .map(|&x| Aabb::from_sphere(&Vector2::new(x, 0.0), 0.5))
+            .collect();
+        let mut tree = AabbTree::new(0.2);
+        let proxies: Vec<usize> = (0..3).map(|id| tree.insert(boxes[id], id)).collect();
+        let (origin, dir) = (Vector2::zeros(), Vector2::new(1.0, 0.0));
+        let hit = |id: usize| boxes[id].ray_intersect(&origin, &dir, 100.0);
+
+        assert_eq!(tree.raycast(&origin, &dir, 100.0, hit), Some((2, 1.5)));
+        assert_eq!(tree.raycast(&origin, &dir, 1.0, hit), None);
+        tree.remove(proxies[2]);
+        assert_eq!(tree.raycast(&origin, &dir, 100.0, hit), Some((1, 4.5)));
+    }
+}
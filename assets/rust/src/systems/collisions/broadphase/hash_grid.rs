@@ -0,0 +1,106 @@
+use nalgebra::SVector;
+
+// Dense spatial hash: objects are bucketed by the cell containing their center,
+// buckets are stored as partial sums (cell_start) over one flat array (cell_entries).
+pub struct HashGrid<const D: usize> {
+    spacing: f64,
+    table_size: usize,
+    cell_start: Vec<usize>,
+    cell_entries: Vec<usize>,
+    query_ids: Vec<usize>,
+}
+
+impl<const D: usize> HashGrid<D> {
+    pub fn new(spacing: f64, max_num_objects: usize) -> Self {
+        let table_size = 2 * max_num_objects.max(1);
+        HashGrid {
+            spacing,
+            table_size,
+            cell_start: vec![0; table_size + 1],
+            cell_entries: vec![0; max_num_objects],
+            query_ids: Vec::new(),
+        }
+    }
+
+    pub fn spacing(&self) -> f64 {
+        self.spacing
+    }
+
+    fn int_coord(&self, x: f64) -> i64 {
+        (x / self.spacing).floor() as i64
+    }
+
+    fn hash_coords(&self, coords: &[i64; D]) -> usize {
+        const PRIMES: [i64; 3] = [92837111, 689287499, 283923481];
+        let mut h: i64 = 0;
+        for (i, c) in coords.iter().enumerate() {
+            h ^= c.wrapping_mul(PRIMES[i % PRIMES.len()]);
+        }
+        (h.unsigned_abs() as usize) % self.table_size
+    }
+
+    fn hash_pos(&self, pos: &SVector<f64, D>) -> usize {
+        let coords = std::array::from_fn(|i| self.int_coord(pos[i]));
+        self.hash_coords(&coords)
+    }
+
+    pub fn create(&mut self, positions: &[SVector<f64, D>]) {
+        if positions.len() > self.cell_entries.len() {
+            *self = HashGrid::new(self.spacing, positions.len());
+        }
+        self.cell_start.fill(0);
+
+        // count
+        for pos in positions {
+            let h = self.hash_pos(pos);
+            self.cell_start[h] += 1;
+        }
+        // partial sums
+        let mut start = 0;
+        for i in 0..self.table_size {
+            start += self.cell_start[i];
+            self.cell_start[i] = start;
+        }
+        self.cell_start[self.table_size] = start;
+        // fill in
+        for (id, pos) in positions.iter().enumerate() {
+            let h = self.hash_pos(pos);
+            self.cell_start[h] -= 1;
+            self.cell_entries[self.cell_start[h]] = id;
+        }
+    }
+
+    // Ids of all objects whose center may lie within max_dist of pos, sorted and unique.
+    pub fn query(&mut self, pos: &SVector<f64, D>, max_dist: f64) -> &[usize] {
+        let lo: [i64; D] = std::array::from_fn(|i| self.int_coord(pos[i] - max_dist));
+        let hi: [i64; D] = std::array::from_fn(|i| self.int_coord(pos[i] + max_dist));
+
+        self.query_ids.clear();
+        let mut cell = lo;
+        loop {
+            let h = self.hash_coords(&cell);
+            let start = self.cell_start[h];
+            let end = self.cell_start[h + 1];
+            self.query_ids
+                .extend_from_slice(&self.cell_entries[start..end]);
+
+            // next cell in [lo, hi]
+            let mut k = 0;
+            while k < D {
+                if cell[k] < hi[k] {
+                    cell[k] += 1;
+                    break;
+                }
+                cell[k] = lo[k];
+                k += 1;
+            }
+            if k == D {
+                break;
+            }
+        }
+        // different cells can share a bucket
+        self.query_ids.sort_unstable();
+        self.query_ids.dedup();
+        &self.query_ids
+    }
+}
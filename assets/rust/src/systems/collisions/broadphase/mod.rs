@@ -0,0 +1,25 @@
+pub mod aabb_tree;
+pub mod hash_grid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BroadphaseKind {
+    BruteForce,
+    HashGrid,
+    AabbTree,
+}
+
+impl BroadphaseKind {
+    pub const ALL: [BroadphaseKind; 3] = [
+        BroadphaseKind::BruteForce,
+        BroadphaseKind::HashGrid,
+        BroadphaseKind::AabbTree,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BroadphaseKind::BruteForce => "brute force",
+            BroadphaseKind::HashGrid => "hash grid",
+            BroadphaseKind::AabbTree => "aabb tree",
+        }
+    }
+}
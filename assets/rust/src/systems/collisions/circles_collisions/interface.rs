@@ -1,18 +1,60 @@
 use super::system::System;
+use super::system_parameters::{ContactSolver, PenaltyModel};
 use super::visualizer::Visualizer;
+use crate::systems::collisions::broadphase::BroadphaseKind;
 use crate::utils::create_window;
+use crate::utils::diagnostics::Diagnostics;
+use crate::utils::integrator::Integrator;
+use crate::utils::recorder::Recorder;
 use crate::utils::ui;
 use three_d::{FrameInput, FrameOutput};
 
 struct State {
     base: ui::BaseState,
     n_points: usize,
+    broadphase: BroadphaseKind,
+    integrator: Integrator,
+    contact_solver: ContactSolver,
+    velocity_iterations: usize,
+    baumgarte: f64,
+    warm_starting: bool,
+    contact_hertz: f64,
+    contact_damping_ratio: f64,
+    penalty_model: PenaltyModel,
+    penalty_stiffness: f64,
+    penalty_damping: f64,
+    penalty_implicit: bool,
+    seed: u64,
+    energy: f64,
 }
 impl State {
     pub fn new() -> Self {
         State {
-            base: ui::BaseState::new(),
+            base: ui::BaseState {
+                diagnostics: Diagnostics::new(&[
+                    "kinetic energy",
+                    "potential energy",
+                    "total energy",
+                    "momentum x",
+                    "momentum y",
+                ]),
+                ..ui::BaseState::new()
+            },
             n_points: 50,
+            broadphase: BroadphaseKind::HashGrid,
+            integrator: Integrator::SymplecticEuler,
+            contact_solver: ContactSolver::Relaxation,
+            velocity_iterations: 10,
+            baumgarte: 0.2,
+            warm_starting: true,
+            contact_hertz: 30.0,
+            contact_damping_ratio: 10.0,
+            penalty_model: PenaltyModel::Linear,
+            penalty_stiffness: 1e5,
+            penalty_damping: 100.0,
+            penalty_implicit: false,
+            seed: 1,
+            energy: 0.0,
         }
     }
 }
@@ -30,9 +72,138 @@ fn gui_logic(gui: &mut three_d::GUI, frame_input: &mut FrameInput, state: &mut S
                         .strong()
                         .color(ui::TEXT_COLOR),
                 );
+                ui.add(three_d::egui::DragValue::new(&mut state.seed));
+                ui.label(
+                    three_d::egui::RichText::new("seed")
+                        .strong()
+                        .color(ui::TEXT_COLOR),
+                );
+            });
+        },
+        |ui, _base_state| {
+            ui.horizontal(|ui| {
+                for kind in BroadphaseKind::ALL {
+                    ui.radio_value(
+                        &mut state.broadphase,
+                        kind,
+                        three_d::egui::RichText::new(kind.name()).color(ui::TEXT_COLOR),
+                    );
+                }
+            });
+            ui.horizontal(|ui| {
+                for integrator in Integrator::ALL {
+                    ui.radio_value(
+                        &mut state.integrator,
+                        integrator,
+                        three_d::egui::RichText::new(integrator.name()).color(ui::TEXT_COLOR),
+                    );
+                }
+                ui.label(
+                    three_d::egui::RichText::new(format!("energy: {:.4e}", state.energy))
+                        .strong()
+                        .color(ui::TEXT_COLOR),
+                );
             });
+            ui.horizontal(|ui| {
+                for solver in ContactSolver::ALL {
+                    ui.radio_value(
+                        &mut state.contact_solver,
+                        solver,
+                        three_d::egui::RichText::new(solver.name()).color(ui::TEXT_COLOR),
+                    );
+                }
+            });
+            match state.contact_solver {
+                ContactSolver::Relaxation => {}
+                ContactSolver::SequentialImpulses | ContactSolver::SoftConstraints => {
+                    ui.horizontal(|ui| {
+                        ui.add(three_d::egui::Slider::new(
+                            &mut state.velocity_iterations,
+                            1..=50,
+                        ));
+                        ui.label(
+                            three_d::egui::RichText::new("iterations")
+                                .strong()
+                                .color(ui::TEXT_COLOR),
+                        );
+                        ui.checkbox(
+                            &mut state.warm_starting,
+                            three_d::egui::RichText::new("warm starting")
+                                .strong()
+                                .color(ui::TEXT_COLOR),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        if state.contact_solver == ContactSolver::SoftConstraints {
+                            ui.add(three_d::egui::Slider::new(
+                                &mut state.contact_hertz,
+                                1.0..=250.0,
+                            ));
+                            ui.label(
+                                three_d::egui::RichText::new("Hz")
+                                    .strong()
+                                    .color(ui::TEXT_COLOR),
+                            );
+                            ui.add(three_d::egui::Slider::new(
+                                &mut state.contact_damping_ratio,
+                                0.0..=20.0,
+                            ));
+                            ui.label(
+                                three_d::egui::RichText::new("damping ratio")
+                                    .strong()
+                                    .color(ui::TEXT_COLOR),
+                            );
+                        } else {
+                            ui.add(three_d::egui::Slider::new(&mut state.baumgarte, 0.0..=1.0));
+                            ui.label(
+                                three_d::egui::RichText::new("baumgarte")
+                                    .strong()
+                                    .color(ui::TEXT_COLOR),
+                            );
+                        }
+                    });
+                }
+                ContactSolver::Penalty => {
+                    ui.horizontal(|ui| {
+                        for model in PenaltyModel::ALL {
+                            ui.radio_value(
+                                &mut state.penalty_model,
+                                model,
+                                three_d::egui::RichText::new(model.name()).color(ui::TEXT_COLOR),
+                            );
+                        }
+                        ui.checkbox(
+                            &mut state.penalty_implicit,
+                            three_d::egui::RichText::new("implicit")
+                                .strong()
+                                .color(ui::TEXT_COLOR),
+                        );
+                    });
+                    // explicit springs blow up once dt exceeds about 2 / sqrt(k / m)
+                    ui.horizontal(|ui| {
+                        for (slider, name) in [
+                            (
+                                three_d::egui::Slider::new(&mut state.penalty_stiffness, 1e2..=1e9)
+                                    .logarithmic(true),
+                                "k",
+                            ),
+                            (
+                                three_d::egui::Slider::new(&mut state.penalty_damping, 0.0..=1e4)
+                                    .logarithmic(true),
+                                "c",
+                            ),
+                        ] {
+                            ui.add(slider);
+                            ui.label(
+                                three_d::egui::RichText::new(name)
+                                    .strong()
+                                    .color(ui::TEXT_COLOR),
+                            );
+                        }
+                    });
+                }
+            }
         },
-        |_ui, _base_state| {},
     );
 }
 
@@ -49,19 +220,50 @@ pub fn run(_canvas_id: &str) {
     let mut gui = three_d::GUI::new(&context);
     let mut state = State::new();
     let mut fps_counter = ui::FPSCounter::new();
+    let mut recorder = Recorder::new();
 
     let mut prev_n_points = state.n_points;
     let mut n_points_timer = 0.0;
     window.render_loop(move |mut frame_input| {
         gui_logic(&mut gui, &mut frame_input, &mut state);
 
-        if state.base.reset {
+        if state.base.reset || state.seed != sys.par.seed {
+            sys.par.seed = state.seed;
             sys.reset();
+            vis.update(&sys);
             state.base.reset = false;
+            state.base.diagnostics.clear();
         }
 
+        sys.par.broadphase = state.broadphase;
+        sys.par.integrator = state.integrator;
+        sys.par.contact_solver = state.contact_solver;
+        sys.par.velocity_iterations = state.velocity_iterations;
+        sys.par.baumgarte = state.baumgarte;
+        sys.par.warm_starting = state.warm_starting;
+        sys.par.contact_hertz = state.contact_hertz;
+        sys.par.contact_damping_ratio = state.contact_damping_ratio;
+        sys.par.penalty_model = state.penalty_model;
+        sys.par.penalty_stiffness = state.penalty_stiffness;
+        sys.par.penalty_damping = state.penalty_damping;
+        sys.par.penalty_implicit = state.penalty_implicit;
+        recorder.update(&mut sys, &mut state.base);
+        state.energy = sys.energy();
+        if !state.base.stop && !state.base.replay.replaying {
+            let (kinetic, potential) = (sys.kinetic_energy(), sys.potential_energy());
+            let momentum = sys.momentum();
+            state.base.diagnostics.push(
+                sys.t,
+                &[
+                    kinetic,
+                    potential,
+                    kinetic + potential,
+                    momentum.x,
+                    momentum.y,
+                ],
+            );
+        }
         if !state.base.stop {
-            sys.update();
             state.base.fps = fps_counter.update(&frame_input);
         }
         vis.render(&frame_input, &sys);
@@ -75,6 +277,7 @@ pub fn run(_canvas_id: &str) {
             prev_n_points = state.n_points;
             n_points_timer = frame_input.accumulated_time;
             vis.update(&sys);
+            state.base.diagnostics.clear();
         }
 
         FrameOutput::default()
@@ -1,4 +1,6 @@
 pub mod interface;
-mod system;
-mod system_parameters;
+pub mod penalty;
+pub mod sequential_impulses;
+pub mod system;
+pub mod system_parameters;
 mod visualizer;
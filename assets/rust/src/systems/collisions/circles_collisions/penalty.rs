@@ -0,0 +1,264 @@
+use super::system_parameters::{PenaltyModel, SystemParameters};
+use crate::utils::ball::{self, Ball};
+use nalgebra::{DVector, Vector2};
+
+// Spring of a contact linearized at the current depth: the elastic force,
+// its derivative with respect to the depth and the damping coefficient.
+fn spring(par: &SystemParameters, depth: f64) -> (f64, f64, f64) {
+    let (k, c) = (par.penalty_stiffness, par.penalty_damping);
+    match par.penalty_model {
+        PenaltyModel::Linear => (k * depth, k, c),
+        PenaltyModel::Hertz => {
+            let root = depth.sqrt();
+            (k * depth * root, 1.5 * k * root, c * root)
+        }
+    }
+}
+
+// Energy stored in a spring compressed by depth.
+fn spring_energy(par: &SystemParameters, depth: f64) -> f64 {
+    let k = par.penalty_stiffness;
+    match par.penalty_model {
+        PenaltyModel::Linear => 0.5 * k * depth * depth,
+        PenaltyModel::Hertz => 0.4 * k * depth * depth * depth.sqrt(),
+    }
+}
+
+// Overlaps of the balls with the walls and of the given pairs:
+// (a, b, normal from a to b, depth), a is None for a wall.
+fn find_overlaps(
+    balls: &[Ball],
+    pairs: &[(usize, usize)],
+    par: &SystemParameters,
+) -> Vec<(Option<usize>, usize, Vector2<f64>, f64)> {
+    let w = par.base.width as f64;
+    let h = par.base.height as f64;
+    let mut contacts = Vec::new();
+    for (b, ball) in balls.iter().enumerate() {
+        for (normal, depth) in ball::wall_penetrations_2d(ball, w, h) {
+            if depth > 0.0 {
+                contacts.push((None, b, normal, depth));
+            }
+        }
+    }
+    for &(i, j) in pairs {
+        let d = balls[j].pos - balls[i].pos;
+        let distance = d.norm();
+        let depth = balls[i].radius + balls[j].radius - distance;
+        if distance > 1e-6 && depth > 0.0 {
+            contacts.push((Some(i), j, d / distance, depth));
+        }
+    }
+    contacts
+}
+
+fn apply_impulse(balls: &mut [Ball], a: Option<usize>, b: usize, p: Vector2<f64>) {
+    if let Some(a) = a {
+        balls[a].vel -= p / balls[a].mass;
+    }
+    balls[b].vel += p / balls[b].mass;
+}
+
+// Velocities updated contact by contact, each spring taken at the end of the
+// step by backward Euler of that spring alone.
+pub fn apply_implicit_contacts(
+    balls: &mut [Ball],
+    pairs: &[(usize, usize)],
+    par: &SystemParameters,
+) {
+    let dt = par.dt;
+    for (a, b, normal, depth) in find_overlaps(balls, pairs, par) {
+        let (va, inv_ma) = a.map_or((Vector2::zeros(), 0.0), |a| {
+            (balls[a].vel, 1.0 / balls[a].mass)
+        });
+        let normal_velocity = (balls[b].vel - va).dot(&normal);
+        let (force, stiffness, damping) = spring(par, depth);
+        // the depth and the velocity at the end of the step:
+        // p = dt (f - (k dt + c) (v + p / m))
+        let inv_mass = inv_ma + 1.0 / balls[b].mass;
+        let k = stiffness * dt + damping;
+        let impulse = dt * (force - k * normal_velocity) / (1.0 + dt * k * inv_mass);
+        // springs only push
+        apply_impulse(balls, a, b, normal * impulse.max(0.0));
+    }
+}
+
+// Accelerations of the balls from gravity and the contact springs, x and y of
+// every ball stacked.
+pub fn accelerations(
+    balls: &[Ball],
+    pairs: &[(usize, usize)],
+    par: &SystemParameters,
+) -> DVector<f64> {
+    let mut acel = DVector::zeros(2 * balls.len());
+    for i in 0..balls.len() {
+        acel[2 * i + 1] = par.g;
+    }
+    for (a, b, normal, depth) in find_overlaps(balls, pairs, par) {
+        let va = a.map_or(Vector2::zeros(), |a| balls[a].vel);
+        let normal_velocity = (balls[b].vel - va).dot(&normal);
+        let (force, _, damping) = spring(par, depth);
+        // springs only push
+        let f = normal * (force - damping * normal_velocity).max(0.0);
+        if let Some(a) = a {
+            let da = -f / balls[a].mass;
+            acel[2 * a] += da.x;
+            acel[2 * a + 1] += da.y;
+        }
+        let db = f / balls[b].mass;
+        acel[2 * b] += db.x;
+        acel[2 * b + 1] += db.y;
+    }
+    acel
+}
+
+// Elastic energy of the springs of the walls and of the given pairs.
+pub fn elastic_energy(balls: &[Ball], pairs: &[(usize, usize)], par: &SystemParameters) -> f64 {
+    find_overlaps(balls, pairs, par)
+        .iter()
+        .map(|&(_, _, _, depth)| spring_energy(par, depth))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::system::System;
+    use super::super::system_parameters::ContactSolver;
+    use super::*;
+    use crate::systems::collisions::broadphase::BroadphaseKind;
+    use crate::utils::integrator::Integrator;
+
+    #[test]
+    fn springs() {
+        let mut par = SystemParameters::default_parameters(800, 600);
+        par.penalty_stiffness = 1000.0;
+        par.penalty_damping = 10.0;
+        par.penalty_model = PenaltyModel::Linear;
+        assert_eq!(spring(&par, 0.5), (500.0, 1000.0, 10.0));
+        assert_eq!(spring_energy(&par, 0.5), 125.0);
+        par.penalty_model = PenaltyModel::Hertz;
+        assert_eq!(spring(&par, 4.0), (8000.0, 3000.0, 20.0));
+        assert_eq!(spring_energy(&par, 4.0), 12800.0);
+        // the stiffness is the derivative of the force, the force of the energy
+        for model in PenaltyModel::ALL {
+            par.penalty_model = model;
+            let (d, h) = (0.3, 1e-6);
+            let (force, stiffness, _) = spring(&par, d);
+            let slope = |f: &dyn Fn(f64) -> f64| (f(d + h) - f(d - h)) / (2.0 * h);
+            let df = slope(&|d| spring(&par, d).0);
+            let de = slope(&|d| spring_energy(&par, d));
+            assert!(
+                (df - stiffness).abs() < 1e-6 * stiffness,
+                "{}",
+                model.name()
+            );
+            assert!((de - force).abs() < 1e-6 * force, "{}", model.name());
+        }
+    }
+
+    // One ball of radius 10 dropped onto the floor.
+    fn dropped_ball(mut par: SystemParameters) -> System {
+        par.n_points = 1;
+        par.contact_solver = ContactSolver::Penalty;
+        let mut system = System::with_parameters(par);
+        system.balls[0].mass = 1.0;
+        system.balls[0].radius = 10.0;
+        system.balls[0].pos = Vector2::new(400.0, 50.0);
+        system.balls[0].vel = Vector2::zeros();
+        system
+    }
+
+    #[test]
+    fn energy_is_kept_through_a_bounce() {
+        for model in PenaltyModel::ALL {
+            let mut par = SystemParameters::default_parameters(800, 600);
+            par.penalty_model = model;
+            par.penalty_damping = 0.0;
+            par.integrator = Integrator::Rk4;
+            par.dt = 1e-4;
+            let mut system = dropped_ball(par);
+            let start = system.energy();
+            let mut in_contact = false;
+            for _ in 0..2000 {
+                system.update();
+                in_contact |= system.balls[0].pos.y < 10.0;
+                // also while the ball is in the floor, the springs hold the rest
+                let error = (system.energy() - start).abs() / start;
+                assert!(error < 1e-3, "{} {}", model.name(), error);
+            }
+            assert!(in_contact, "{}", model.name());
+        }
+    }
+
+    #[test]
+    fn potential_energy_of_the_final_state() {
+        for integrator in [Integrator::ExplicitEuler, Integrator::Rk4] {
+            let mut par = SystemParameters::default_parameters(800, 600);
+            par.n_points = 2;
+            par.g = 0.0;
+            par.sub_steps = 1;
+            par.broadphase = BroadphaseKind::BruteForce;
+            par.contact_solver = ContactSolver::Penalty;
+            par.integrator = integrator;
+            let mut system = System::with_parameters(par);
+            for (ball, x) in system.balls.iter_mut().zip([380.0, 420.0]) {
+                ball.mass = 1.0;
+                ball.radius = 10.0;
+                ball.pos = Vector2::new(x, 300.0);
+                ball.vel = Vector2::new(400.0 - x, 0.0) * 10.0;
+            }
+            let mut in_contact = false;
+            for _ in 0..100 {
+                system.update();
+                let balls = &system.balls;
+                let pairs = if (balls[1].pos - balls[0].pos).norm() < 20.0 {
+                    vec![(0, 1)]
+                } else {
+                    vec![]
+                };
+                in_contact |= !pairs.is_empty();
+                let expected = elastic_energy(balls, &pairs, &system.par);
+                assert_eq!(system.potential_energy(), expected, "{}", integrator.name());
+            }
+            assert!(in_contact);
+        }
+    }
+
+    #[test]
+    fn implicit_contact_rests() {
+        let mut par = SystemParameters::default_parameters(800, 600);
+        par.penalty_implicit = true;
+        let mut system = dropped_ball(par);
+        for _ in 0..2000 {
+            system.update();
+        }
+        let ball = &system.balls[0];
+        // the spring holds the weight at m g / k, the free flight after the
+        // contact adds a little
+        let depth = ball.radius - ball.pos.y;
+        let expected = ball.mass * 200.0 / system.par.penalty_stiffness;
+        assert!(
+            (depth - expected).abs() < 0.2 * expected,
+            "{depth} {expected}"
+        );
+        assert!(ball.vel.norm() < 1e-3);
+    }
+
+    #[test]
+    fn implicit_is_stable_where_explicit_blows_up() {
+        let run = |implicit| {
+            let mut par = SystemParameters::default_parameters(800, 600);
+            par.penalty_stiffness = 1e9;
+            par.penalty_implicit = implicit;
+            let mut system = dropped_ball(par);
+            for _ in 0..200 {
+                system.update();
+            }
+            system.balls[0].vel.norm()
+        };
+        let explicit = run(false);
+        let implicit = run(true);
+        assert!(explicit.is_nan() || explicit > 1e4, "{explicit}");
+        assert!(implicit < 100.0, "{implicit}");
+    }
+}
@@ -0,0 +1,343 @@
+use super::system_parameters::{ContactSolver, SystemParameters};
+use crate::utils::ball::{self, Ball};
+use nalgebra::Vector2;
+use std::collections::HashMap;
+
+// Normal contact of ball b with ball a, or with a wall when a is None.
+struct Contact {
+    a: Option<usize>,
+    b: usize,
+    // identifies the contact across substeps, walls are numbered after the balls
+    key: (usize, usize),
+    // from a to b
+    normal: Vector2<f64>,
+    // effective mass along the normal
+    mass: f64,
+    // penetration beyond slop
+    depth: f64,
+    // target normal velocity of a bouncing contact
+    bounce: f64,
+    // accumulated over the iterations, never negative
+    impulse: f64,
+}
+
+fn inverse_mass(balls: &[Ball], i: Option<usize>) -> f64 {
+    i.map_or(0.0, |i| 1.0 / balls[i].mass)
+}
+
+fn velocity(balls: &[Ball], i: Option<usize>) -> Vector2<f64> {
+    i.map_or(Vector2::zeros(), |i| balls[i].vel)
+}
+
+fn make_contact(
+    balls: &[Ball],
+    a: Option<usize>,
+    b: usize,
+    key: (usize, usize),
+    normal: Vector2<f64>,
+    depth: f64,
+    par: &SystemParameters,
+) -> Contact {
+    let mass = 1.0 / (inverse_mass(balls, a) + 1.0 / balls[b].mass);
+    // slower than 50 substeps of free fall the contact is taken as resting,
+    // bouncing resting contacts makes stacks jitter
+    let normal_velocity = (balls[b].vel - velocity(balls, a)).dot(&normal);
+    let bounce = if normal_velocity < -50.0 * par.g.abs() * par.dt {
+        -par.restitution * normal_velocity
+    } else {
+        0.0
+    };
+    Contact {
+        a,
+        b,
+        key,
+        normal,
+        mass,
+        depth: (depth - par.slop).max(0.0),
+        bounce,
+        impulse: 0.0,
+    }
+}
+
+// How hard the contacts are, Box2D's soft step.
+#[derive(Debug, Clone, Copy)]
+struct Softness {
+    // push out velocity per unit of penetration
+    bias_rate: f64,
+    mass_scale: f64,
+    // share of the accumulated impulse relaxed away every iteration
+    impulse_scale: f64,
+}
+
+impl Softness {
+    // Baumgarte stabilization, removes a fraction of the penetration per step.
+    fn rigid(baumgarte: f64, dt: f64) -> Self {
+        Softness {
+            bias_rate: baumgarte / dt,
+            mass_scale: 1.0,
+            impulse_scale: 0.0,
+        }
+    }
+
+    // Implicit spring-damper of the given frequency and damping ratio, stable at any
+    // stiffness since the spring is solved at the end of the step.
+    fn spring(hertz: f64, damping_ratio: f64, dt: f64) -> Self {
+        let omega = 2.0 * std::f64::consts::PI * hertz;
+        let a1 = 2.0 * damping_ratio + dt * omega;
+        let a2 = dt * omega * a1;
+        let a3 = 1.0 / (1.0 + a2);
+        Softness {
+            bias_rate: omega / a1,
+            mass_scale: a2 * a3,
+            impulse_scale: a3,
+        }
+    }
+}
+
+impl Default for Softness {
+    fn default() -> Self {
+        Softness::rigid(0.0, 1.0)
+    }
+}
+
+fn apply_impulse(balls: &mut [Ball], contact: &Contact, impulse: f64) {
+    let p = contact.normal * impulse;
+    if let Some(a) = contact.a {
+        balls[a].vel -= p / balls[a].mass;
+    }
+    balls[contact.b].vel += p / balls[contact.b].mass;
+}
+
+// Sequential impulses with accumulated impulses kept between substeps.
+#[derive(Default)]
+pub struct ImpulseSolver {
+    contacts: Vec<Contact>,
+    // accumulated impulses of the previous substep
+    cache: HashMap<(usize, usize), f64>,
+    softness: Softness,
+}
+
+impl ImpulseSolver {
+    pub fn clear(&mut self) {
+        self.contacts.clear();
+        self.cache.clear();
+    }
+
+    pub fn num_contacts(&self) -> usize {
+        self.contacts.len()
+    }
+
+    // Contacts of the overlapping pairs and of the balls touching the walls.
+    pub fn prepare(&mut self, balls: &[Ball], pairs: &[(usize, usize)], par: &SystemParameters) {
+        self.contacts.clear();
+        self.softness = match par.contact_solver {
+            ContactSolver::SoftConstraints => {
+                Softness::spring(par.contact_hertz, par.contact_damping_ratio, par.dt)
+            }
+            _ => Softness::rigid(par.baumgarte, par.dt),
+        };
+        let n = balls.len();
+        let w = par.base.width as f64;
+        let h = par.base.height as f64;
+        for (b, ball) in balls.iter().enumerate() {
+            let walls = ball::wall_penetrations_2d(ball, w, h);
+            for (k, (normal, depth)) in walls.into_iter().enumerate() {
+                if depth > 0.0 {
+                    let contact = make_contact(balls, None, b, (n + k, b), normal, depth, par);
+                    self.contacts.push(contact);
+                }
+            }
+        }
+        for &(i, j) in pairs {
+            let d = balls[j].pos - balls[i].pos;
+            let distance = d.norm();
+            if distance < 1e-6 {
+                continue;
+            }
+            let depth = balls[i].radius + balls[j].radius - distance;
+            let contact = make_contact(balls, Some(i), j, (i, j), d / distance, depth, par);
+            self.contacts.push(contact);
+        }
+    }
+
+    // Applies the impulses the same contacts ended with in the previous substep.
+    pub fn warm_start(&mut self, balls: &mut [Ball]) {
+        for contact in &mut self.contacts {
+            if let Some(&impulse) = self.cache.get(&contact.key) {
+                contact.impulse = impulse;
+                apply_impulse(balls, contact, impulse);
+            }
+        }
+    }
+
+    // Without the bias only the bounces are kept, the relax pass of the soft step
+    // takes the push out velocity away again once it moved the balls.
+    pub fn solve(&mut self, balls: &mut [Ball], iterations: usize, use_bias: bool) {
+        let softness = if use_bias {
+            self.softness
+        } else {
+            Softness::default()
+        };
+        for _ in 0..iterations {
+            for contact in &mut self.contacts {
+                let relative = balls[contact.b].vel - velocity(balls, contact.a);
+                let normal_velocity = relative.dot(&contact.normal);
+                let target = contact.bounce.max(softness.bias_rate * contact.depth);
+                let lambda = -contact.mass * softness.mass_scale * (normal_velocity - target)
+                    - softness.impulse_scale * contact.impulse;
+                // the total impulse may only push
+                let impulse = (contact.impulse + lambda).max(0.0);
+                let delta = impulse - contact.impulse;
+                contact.impulse = impulse;
+                apply_impulse(balls, contact, delta);
+            }
+        }
+    }
+
+    pub fn store(&mut self) {
+        self.cache.clear();
+        self.cache
+            .extend(self.contacts.iter().map(|c| (c.key, c.impulse)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // n balls of radius 20 standing on each other on the floor
+    fn stack(n: usize, contact_solver: ContactSolver) -> (Vec<Ball>, SystemParameters) {
+        let mut par = SystemParameters::default_parameters(800, 600);
+        par.contact_solver = contact_solver;
+        let balls = (0..n)
+            .map(|i| Ball {
+                pos: Vector2::new(400.0, 20.0 + 40.0 * i as f64),
+                vel: Vector2::zeros(),
+                acel: Vector2::zeros(),
+                mass: 1.0,
+                radius: 20.0,
+            })
+            .collect();
+        (balls, par)
+    }
+
+    fn pairs(balls: &[Ball]) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for i in 0..balls.len() {
+            for j in i + 1..balls.len() {
+                if (balls[j].pos - balls[i].pos).norm() < balls[i].radius + balls[j].radius {
+                    pairs.push((i, j));
+                }
+            }
+        }
+        pairs
+    }
+
+    // The impulse substep of the system with symplectic Euler.
+    fn substep(balls: &mut [Ball], solver: &mut ImpulseSolver, par: &SystemParameters) {
+        for ball in balls.iter_mut() {
+            ball.vel.y += par.g * par.dt;
+        }
+        solver.prepare(balls, &pairs(balls), par);
+        if par.warm_starting {
+            solver.warm_start(balls);
+        }
+        solver.solve(balls, par.velocity_iterations, true);
+        for ball in balls.iter_mut() {
+            ball.pos += ball.vel * par.dt;
+        }
+        if par.contact_solver == ContactSolver::SoftConstraints {
+            solver.solve(balls, par.velocity_iterations, false);
+        }
+        solver.store();
+    }
+
+    fn max_penetration(balls: &[Ball]) -> f64 {
+        let floor = balls.iter().map(|b| b.radius - b.pos.y);
+        let pairs = pairs(balls)
+            .into_iter()
+            .map(|(i, j)| balls[i].radius + balls[j].radius - (balls[j].pos - balls[i].pos).norm());
+        floor.chain(pairs).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn resting_ball_stays_on_the_floor() {
+        let (mut balls, par) = stack(1, ContactSolver::SequentialImpulses);
+        let mut solver = ImpulseSolver::default();
+        for _ in 0..1000 {
+            substep(&mut balls, &mut solver, &par);
+            assert!(solver.contacts.iter().all(|c| c.impulse >= 0.0));
+            // Baumgarte keeps it from sinking past the slop
+            assert!(max_penetration(&balls) < 2.0 * par.slop);
+        }
+        assert!(balls[0].vel.norm() < 1.0);
+        // the floor carries the weight
+        assert_eq!(solver.num_contacts(), 1);
+        let weight = balls[0].mass * par.g.abs() * par.dt;
+        assert!((solver.contacts[0].impulse - weight).abs() < 0.1 * weight);
+    }
+
+    // Iterations until no accumulated impulse changes by more than a thousandth of
+    // one ball's weight per substep.
+    fn iterations_to_converge(warm_starting: bool) -> usize {
+        let (mut balls, mut par) = stack(6, ContactSolver::SequentialImpulses);
+        par.velocity_iterations = 50;
+        let mut solver = ImpulseSolver::default();
+        for _ in 0..500 {
+            substep(&mut balls, &mut solver, &par);
+        }
+        for ball in &mut balls {
+            ball.vel.y += par.g * par.dt;
+        }
+        solver.prepare(&balls, &pairs(&balls), &par);
+        if warm_starting {
+            solver.warm_start(&mut balls);
+        }
+        let tolerance = 1e-3 * par.g.abs() * par.dt;
+        for iteration in 1..1000 {
+            let before: Vec<f64> = solver.contacts.iter().map(|c| c.impulse).collect();
+            solver.solve(&mut balls, 1, true);
+            let change = solver
+                .contacts
+                .iter()
+                .zip(before)
+                .map(|(c, b)| (c.impulse - b).abs())
+                .fold(0.0, f64::max);
+            if change < tolerance {
+                return iteration;
+            }
+        }
+        1000
+    }
+
+    #[test]
+    fn warm_starting_converges_faster() {
+        let warm = iterations_to_converge(true);
+        let cold = iterations_to_converge(false);
+        assert!(warm < cold, "warm {warm}, cold {cold}");
+    }
+
+    #[test]
+    fn soft_stack_settles() {
+        let (mut balls, mut par) = stack(4, ContactSolver::SoftConstraints);
+        par.restitution = 0.0;
+        // dropped from a little above each other
+        for (i, ball) in balls.iter_mut().enumerate() {
+            ball.pos.y += 5.0 * (i + 1) as f64;
+        }
+        let mut solver = ImpulseSolver::default();
+        let kinetic_energy = |balls: &[Ball]| -> f64 {
+            balls
+                .iter()
+                .map(|b| 0.5 * b.mass * b.vel.norm_squared())
+                .sum()
+        };
+        for _ in 0..3000 {
+            substep(&mut balls, &mut solver, &par);
+            assert!(max_penetration(&balls) < 2.0 * par.slop);
+        }
+        // of the about 3200 the fall brought in
+        assert!(kinetic_energy(&balls) < 0.01);
+        assert!(balls.iter().all(|b| (b.pos.x - 400.0).abs() < 1e-6));
+    }
+}
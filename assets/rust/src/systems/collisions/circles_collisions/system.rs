@@ -1,6 +1,15 @@
-use super::system_parameters::SystemParameters;
+use super::penalty;
+use super::sequential_impulses::ImpulseSolver;
+use super::system_parameters::{ContactSolver, SystemParameters};
+use crate::systems::collisions::broadphase::BroadphaseKind;
+use crate::systems::collisions::broadphase::aabb_tree::{Aabb, AabbTree};
+use crate::systems::collisions::broadphase::hash_grid::HashGrid;
 use crate::utils::ball;
+use crate::utils::recorder::{self, Recordable};
 use ball::Ball;
+use nalgebra::{DVector, Vector2};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 
 fn fix_position(ball1: &mut Ball, ball2: &mut Ball, distance: f64) {
     if distance < 1e-6 {
@@ -29,18 +38,16 @@ fn collide(ball1: &mut Ball, ball2: &mut Ball, distance: f64, par: &SystemParame
     ball1.vel -= impulse / ball1.mass;
     ball2.vel += impulse / ball2.mass;
 }
+fn overlap(ball1: &Ball, ball2: &Ball) -> bool {
+    (ball2.pos - ball1.pos).norm() < ball1.radius + ball2.radius
+}
+
 fn relax(
     ball1: &mut Ball,
     ball2: &mut Ball,
     with_collisions: bool,
     par: &SystemParameters,
 ) -> bool {
-    let w = par.base.width as f64;
-    let h = par.base.height as f64;
-    let r = par.restitution;
-    ball::wall_collision_2d(ball1, w, h, r);
-    ball::wall_collision_2d(ball2, w, h, r);
-
     let distance = (ball2.pos - ball1.pos).norm();
     if distance < (ball1.radius + ball2.radius) {
         if with_collisions {
@@ -58,15 +65,33 @@ pub struct System {
     pub par: SystemParameters,
     pub balls: Vec<Ball>,
     pub t: f64,
+    // pairs (i, j), i < j, of overlapping balls found by the last broadphase pass
+    pub contacts: Vec<(usize, usize)>,
+    pub solver: ImpulseSolver,
+    grid: HashGrid<2>,
+    grid_pos: Vec<Vector2<f64>>,
+    tree: AabbTree<2>,
+    tree_proxies: Vec<usize>,
+    tree_ids: Vec<usize>,
 }
 
 impl System {
     pub fn new(width: u32, height: u32) -> Self {
-        let par = SystemParameters::default_parameters(width, height);
+        Self::with_parameters(SystemParameters::default_parameters(width, height))
+    }
+
+    pub fn with_parameters(par: SystemParameters) -> Self {
         let mut system = System {
             par,
             balls: Vec::new(),
             t: 0.0,
+            contacts: Vec::new(),
+            solver: ImpulseSolver::default(),
+            grid: HashGrid::new(1.0, 0),
+            grid_pos: Vec::new(),
+            tree: AabbTree::new(0.0),
+            tree_proxies: Vec::new(),
+            tree_ids: Vec::new(),
         };
         system.initialize_system();
         system
@@ -78,46 +103,362 @@ impl System {
 
     pub fn initialize_system(&mut self) {
         self.t = 0.0;
+        self.solver.clear();
         let n_points = self.par.n_points;
         let width = self.par.base.width;
         let height = self.par.base.height;
         let space = (width * height) as f64;
         let space_per_point = space / (n_points as f64);
+        #[allow(clippy::approx_constant)]
         let radius = (space_per_point).sqrt() / 3.14;
         let mass_min = 0.9;
         let mass_max = 1.5;
 
+        let mut rng = StdRng::seed_from_u64(self.par.seed);
         self.balls = (0..n_points)
-            .map(|_| ball::get_random_ball(width, height, mass_min, mass_max, radius))
+            .map(|_| ball::get_random_ball(&mut rng, width, height, mass_min, mass_max, radius))
+            .collect();
+        let max_radius = self.balls.iter().map(|b| b.radius).fold(0.0, f64::max);
+        self.grid = HashGrid::new(2.0 * max_radius, n_points);
+        self.tree = AabbTree::new(0.2 * max_radius);
+        self.tree_proxies = self
+            .balls
+            .iter()
+            .enumerate()
+            .map(|(i, b)| self.tree.insert(Aabb::from_sphere(&b.pos, b.radius), i))
             .collect();
         self.update();
     }
 
     pub fn update(&mut self) {
         for _ in 0..self.par.sub_steps {
+            self.substep();
+        }
+    }
+
+    pub fn substep(&mut self) {
+        match self.par.contact_solver {
+            ContactSolver::Relaxation => self.relaxation_substep(),
+            ContactSolver::SequentialImpulses | ContactSolver::SoftConstraints => {
+                self.impulse_substep()
+            }
+            ContactSolver::Penalty => self.penalty_substep(),
+        }
+        self.t += self.par.dt;
+    }
+
+    fn relaxation_substep(&mut self) {
+        // cached impulses are stale once another solver moved the balls
+        self.solver.clear();
+        for ball in &mut self.balls {
+            ball.apply_gravity(self.par.g);
+            ball.update(self.par.dt, self.par.integrator);
+        }
+        for _ in 0..self.par.relax_iter {
+            if self.relax_all_points(true) {
+                break;
+            }
+        }
+    }
+
+    // Velocities are integrated, corrected by the contact impulses, and only
+    // then move the balls. The free flight goes through the chosen integrator, the
+    // impulses add their velocity change over the whole step.
+    fn impulse_substep(&mut self) {
+        let dt = self.par.dt;
+        let mut free_flight = Vec::with_capacity(self.balls.len());
+        for ball in &mut self.balls {
+            ball.apply_gravity(self.par.g);
+            let (mut pos, mut vel) = (ball.pos, ball.vel);
+            let acel = ball.acel;
+            self.par
+                .integrator
+                .step(&mut pos, &mut vel, dt, |_, _| acel);
+            ball.vel = vel;
+            free_flight.push((pos, vel));
+        }
+        self.find_contacts();
+        self.solver.prepare(&self.balls, &self.contacts, &self.par);
+        if self.par.warm_starting {
+            self.solver.warm_start(&mut self.balls);
+        }
+        let iterations = self.par.velocity_iterations;
+        self.solver.solve(&mut self.balls, iterations, true);
+        for (ball, (pos, vel)) in self.balls.iter_mut().zip(free_flight) {
+            ball.pos = pos + (ball.vel - vel) * dt;
+        }
+        if self.par.contact_solver == ContactSolver::SoftConstraints {
+            self.solver.solve(&mut self.balls, iterations, false);
+        }
+        self.solver.store();
+    }
+
+    // Explicit springs go through the chosen integrator together with gravity, the
+    // implicit ones update the velocities before the free flight.
+    fn penalty_substep(&mut self) {
+        let dt = self.par.dt;
+        self.solver.clear();
+        if self.par.penalty_implicit {
+            self.find_contacts();
+            penalty::apply_implicit_contacts(&mut self.balls, &self.contacts, &self.par);
             for ball in &mut self.balls {
                 ball.apply_gravity(self.par.g);
-                ball.update(self.par.dt);
+                ball.update(dt, self.par.integrator);
             }
-            for _ in 0..self.par.relax_iter {
-                if self.relax_all_points_brute_force(true) {
-                    break;
+        } else {
+            // x and y of every ball stacked, contacts found anew at every stage
+            let (mut x, mut v) = self.stacked_state();
+            let integrator = self.par.integrator;
+            integrator.step(&mut x, &mut v, dt, |x, v| {
+                self.set_stacked_state(x, v);
+                self.find_contacts();
+                penalty::accelerations(&self.balls, &self.contacts, &self.par)
+            });
+            self.set_stacked_state(&x, &v);
+        }
+        // the energy of the springs is read from the contacts of the final state
+        self.find_contacts();
+    }
+
+    fn stacked_state(&self) -> (DVector<f64>, DVector<f64>) {
+        let n = 2 * self.balls.len();
+        let x = self.balls.iter().flat_map(|b| [b.pos.x, b.pos.y]);
+        let v = self.balls.iter().flat_map(|b| [b.vel.x, b.vel.y]);
+        (DVector::from_iterator(n, x), DVector::from_iterator(n, v))
+    }
+
+    fn set_stacked_state(&mut self, x: &DVector<f64>, v: &DVector<f64>) {
+        for (i, ball) in self.balls.iter_mut().enumerate() {
+            ball.pos = Vector2::new(x[2 * i], x[2 * i + 1]);
+            ball.vel = Vector2::new(v[2 * i], v[2 * i + 1]);
+        }
+    }
+
+    pub fn kinetic_energy(&self) -> f64 {
+        self.balls
+            .iter()
+            .map(|b| 0.5 * b.mass * b.vel.norm_squared())
+            .sum()
+    }
+
+    // gravitational, height measured from the floor, plus the energy in the
+    // penalty springs
+    pub fn potential_energy(&self) -> f64 {
+        let gravitational: f64 = self
+            .balls
+            .iter()
+            .map(|b| -b.mass * self.par.g * b.pos.y)
+            .sum();
+        if self.par.contact_solver == ContactSolver::Penalty {
+            gravitational + penalty::elastic_energy(&self.balls, &self.contacts, &self.par)
+        } else {
+            gravitational
+        }
+    }
+
+    pub fn energy(&self) -> f64 {
+        self.kinetic_energy() + self.potential_energy()
+    }
+
+    pub fn momentum(&self) -> Vector2<f64> {
+        self.balls.iter().map(|b| b.vel * b.mass).sum()
+    }
+
+    // Every broadphase gives the same pairs in the same order, so the motion does
+    // not depend on the choice.
+    pub fn relax_all_points(&mut self, with_collisions: bool) -> bool {
+        let w = self.par.base.width as f64;
+        let h = self.par.base.height as f64;
+        let r = self.par.restitution;
+        for ball in &mut self.balls {
+            ball::wall_collision_2d(ball, w, h, r);
+        }
+
+        // the pairs are searched again on every call, a push can open new overlaps,
+        // so only a pass that finds none is done
+        self.find_contacts();
+        let all_ok = self.contacts.is_empty();
+        for &(i, j) in &self.contacts {
+            let (left, right) = self.balls.split_at_mut(j);
+            relax(&mut left[i], &mut right[0], with_collisions, &self.par);
+        }
+        all_ok
+    }
+
+    pub fn find_contacts(&mut self) {
+        match self.par.broadphase {
+            BroadphaseKind::BruteForce => self.find_contacts_brute_force(),
+            BroadphaseKind::HashGrid => self.find_contacts_hash_grid(),
+            BroadphaseKind::AabbTree => self.find_contacts_aabb_tree(),
+        }
+    }
+
+    pub fn find_contacts_brute_force(&mut self) {
+        self.contacts.clear();
+        for i in 0..self.balls.len() {
+            for j in i + 1..self.balls.len() {
+                if overlap(&self.balls[i], &self.balls[j]) {
+                    self.contacts.push((i, j));
                 }
             }
-            self.t += self.par.dt;
         }
     }
 
-    pub fn relax_all_points_brute_force(&mut self, with_collisions: bool) -> bool {
-        let mut all_ok = true;
-        for i in 0..self.par.n_points {
-            let (left, right) = self.balls.split_at_mut(i + 1);
-            let ball1 = &mut left[i];
-            for ball2 in right.iter_mut() {
-                let result = relax(ball1, ball2, with_collisions, &self.par);
-                all_ok = all_ok && result;
+    pub fn find_contacts_hash_grid(&mut self) {
+        self.contacts.clear();
+        self.grid_pos.clear();
+        self.grid_pos.extend(self.balls.iter().map(|b| b.pos));
+        self.grid.create(&self.grid_pos);
+
+        // cell size is the largest diameter, so any partner is within r_i + max_r
+        let max_radius = self.grid.spacing() / 2.0;
+        for i in 0..self.balls.len() {
+            let max_dist = self.balls[i].radius + max_radius;
+            let ids = self.grid.query(&self.grid_pos[i], max_dist);
+            let first = ids.partition_point(|&j| j <= i);
+            for &j in &ids[first..] {
+                if overlap(&self.balls[i], &self.balls[j]) {
+                    self.contacts.push((i, j));
+                }
             }
         }
-        all_ok
+    }
+
+    pub fn find_contacts_aabb_tree(&mut self) {
+        self.contacts.clear();
+        for (ball, &proxy) in self.balls.iter().zip(&self.tree_proxies) {
+            self.tree
+                .update(proxy, Aabb::from_sphere(&ball.pos, ball.radius));
+        }
+
+        for i in 0..self.balls.len() {
+            let ball = &self.balls[i];
+            let aabb = Aabb::from_sphere(&ball.pos, ball.radius);
+            self.tree.query_overlap(&aabb, &mut self.tree_ids);
+            self.tree_ids.sort_unstable();
+            let first = self.tree_ids.partition_point(|&j| j <= i);
+            for &j in &self.tree_ids[first..] {
+                if overlap(&self.balls[i], &self.balls[j]) {
+                    self.contacts.push((i, j));
+                }
+            }
+        }
+    }
+}
+
+impl Recordable for System {
+    fn num_substeps(&self) -> usize {
+        self.par.sub_steps
+    }
+
+    fn substep(&mut self) {
+        System::substep(self);
+    }
+
+    fn save_state(&self, out: &mut Vec<f64>) {
+        out.push(self.t);
+        for ball in &self.balls {
+            out.extend_from_slice(&[ball.pos.x, ball.pos.y, ball.vel.x, ball.vel.y]);
+        }
+    }
+
+    fn load_state(&mut self, state: &[f64]) -> std::io::Result<()> {
+        recorder::check_state_len(state, 1 + 4 * self.balls.len())?;
+        self.t = state[0];
+        self.solver.clear();
+        for (ball, s) in self.balls.iter_mut().zip(state[1..].chunks_exact(4)) {
+            ball.pos = Vector2::new(s[0], s[1]);
+            ball.vel = Vector2::new(s[2], s[3]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::integrator::Integrator;
+
+    #[test]
+    fn broadphases_find_the_same_contacts() {
+        let mut par = SystemParameters::default_parameters(800, 600);
+        par.n_points = 200;
+        let mut system = System::with_parameters(par);
+        let mut total = 0;
+        for _ in 0..20 {
+            system.find_contacts_brute_force();
+            let brute_force = system.contacts.clone();
+            system.find_contacts_hash_grid();
+            assert_eq!(system.contacts, brute_force, "hash grid");
+            system.find_contacts_aabb_tree();
+            assert_eq!(system.contacts, brute_force, "aabb tree");
+            total += brute_force.len();
+            system.update();
+        }
+        assert!(total > 0);
+    }
+
+    #[test]
+    fn broadphases_give_the_same_motion() {
+        let run = |broadphase| {
+            let mut par = SystemParameters::default_parameters(800, 600);
+            par.n_points = 200;
+            par.broadphase = broadphase;
+            let mut system = System::with_parameters(par);
+            for _ in 0..20 {
+                system.update();
+            }
+            system.balls.iter().map(|b| b.pos).collect::<Vec<_>>()
+        };
+        let brute_force = run(BroadphaseKind::BruteForce);
+        assert!(run(BroadphaseKind::HashGrid) == brute_force);
+        assert!(run(BroadphaseKind::AabbTree) == brute_force);
+    }
+
+    fn run(seed: u64) -> Vec<u64> {
+        let mut par = SystemParameters::default_parameters(800, 600);
+        par.seed = seed;
+        let mut system = System::with_parameters(par);
+        for _ in 0..50 {
+            system.update();
+        }
+        let state = system
+            .balls
+            .iter()
+            .flat_map(|b| [b.pos.x, b.pos.y, b.vel.x, b.vel.y]);
+        state.map(f64::to_bits).collect()
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    // energy change of one ball thrown across the empty box
+    fn free_flight_drift(solver: ContactSolver, integrator: Integrator) -> f64 {
+        let mut par = SystemParameters::default_parameters(800, 600);
+        par.n_points = 1;
+        par.contact_solver = solver;
+        par.integrator = integrator;
+        let mut system = System::with_parameters(par);
+        system.balls[0].radius = 10.0;
+        system.balls[0].pos = Vector2::new(100.0, 300.0);
+        system.balls[0].vel = Vector2::new(100.0, 100.0);
+        let start = system.energy();
+        for _ in 0..50 {
+            system.update();
+        }
+        (system.energy() - start).abs() / start
+    }
+
+    #[test]
+    fn every_solver_uses_the_integrator() {
+        for solver in ContactSolver::ALL {
+            let euler = free_flight_drift(solver, Integrator::ExplicitEuler);
+            let verlet = free_flight_drift(solver, Integrator::VelocityVerlet);
+            assert!(euler > 1e-5, "{} {}", solver.name(), euler);
+            assert!(verlet < 1e-9, "{} {}", solver.name(), verlet);
+        }
     }
 }
@@ -1,5 +1,58 @@
+use crate::systems::collisions::broadphase::BroadphaseKind;
+use crate::utils::integrator::Integrator;
 use crate::utils::parameters::Parameters;
 
+// How overlapping circles are separated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContactSolver {
+    // positions pushed apart pair by pair, one impulse per pair
+    Relaxation,
+    // accumulated, clamped normal impulses iterated over all contacts
+    SequentialImpulses,
+    // sequential impulses with the contacts acting as damped springs
+    SoftConstraints,
+    // overlapping circles repelled by springs
+    Penalty,
+}
+
+impl ContactSolver {
+    pub const ALL: [ContactSolver; 4] = [
+        ContactSolver::Relaxation,
+        ContactSolver::SequentialImpulses,
+        ContactSolver::SoftConstraints,
+        ContactSolver::Penalty,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContactSolver::Relaxation => "relaxation",
+            ContactSolver::SequentialImpulses => "sequential impulses",
+            ContactSolver::SoftConstraints => "soft constraints",
+            ContactSolver::Penalty => "penalty",
+        }
+    }
+}
+
+// Repulsion of the penalty springs as a function of the penetration depth d.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PenaltyModel {
+    // k d - c v
+    Linear,
+    // k d^(3/2) - c d^(1/2) v, the contact of elastic spheres
+    Hertz,
+}
+
+impl PenaltyModel {
+    pub const ALL: [PenaltyModel; 2] = [PenaltyModel::Linear, PenaltyModel::Hertz];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PenaltyModel::Linear => "linear",
+            PenaltyModel::Hertz => "Hertz",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SystemParameters {
     pub base: Parameters,
@@ -9,6 +62,24 @@ pub struct SystemParameters {
     pub sub_steps: usize,
     pub relax_iter: usize,
     pub n_points: usize,
+    pub broadphase: BroadphaseKind,
+    // of the free flight, and of the explicit penalty springs
+    pub integrator: Integrator,
+    pub contact_solver: ContactSolver,
+    pub velocity_iterations: usize,
+    pub warm_starting: bool,
+    // fraction of the penetration beyond slop removed per substep
+    pub baumgarte: f64,
+    pub slop: f64,
+    // natural frequency and damping ratio of the soft contacts
+    pub contact_hertz: f64,
+    pub contact_damping_ratio: f64,
+    pub penalty_model: PenaltyModel,
+    pub penalty_stiffness: f64,
+    pub penalty_damping: f64,
+    // each contact spring taken at the end of the step, otherwise explicitly
+    pub penalty_implicit: bool,
+    pub seed: u64,
 }
 
 // Default parameters
@@ -22,6 +93,20 @@ impl SystemParameters {
             sub_steps: 5,
             relax_iter: 2,
             n_points: 10,
+            broadphase: BroadphaseKind::HashGrid,
+            integrator: Integrator::SymplecticEuler,
+            contact_solver: ContactSolver::Relaxation,
+            velocity_iterations: 10,
+            warm_starting: true,
+            baumgarte: 0.2,
+            slop: 0.5,
+            contact_hertz: 30.0,
+            contact_damping_ratio: 10.0,
+            penalty_model: PenaltyModel::Linear,
+            penalty_stiffness: 1e5,
+            penalty_damping: 100.0,
+            penalty_implicit: false,
+            seed: 1,
         }
     }
 }
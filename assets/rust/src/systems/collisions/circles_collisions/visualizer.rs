@@ -1,5 +1,7 @@
 use super::system::System;
 use crate::utils::colors::random_color_hsv;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 
 use three_d::*;
 
@@ -63,14 +65,13 @@ impl Visualizer {
             ..Default::default()
         };
 
+        let mut rng = StdRng::seed_from_u64(system.par.seed);
         for (i, ball) in system.balls.iter().enumerate() {
             instances.transformations[i] =
                 Matrix4::from_translation(Vector3::new(ball.pos.x as f32, ball.pos.y as f32, 0.0));
-            if random_colors {
-                if let Some(colors) = &mut instances.colors {
-                    let (r, g, b, a) = random_color_hsv(360.0, 0.4, 0.9);
-                    colors[i] = Srgba::new(r, g, b, a);
-                }
+            if random_colors && let Some(colors) = &mut instances.colors {
+                let (r, g, b, a) = random_color_hsv(&mut rng, 360.0, 0.4, 0.9);
+                colors[i] = Srgba::new(r, g, b, a);
             }
         }
 
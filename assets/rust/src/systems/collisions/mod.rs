@@ -1 +1,2 @@
-pub mod circles_collisions;
\ No newline at end of file
+pub mod broadphase;
+pub mod circles_collisions;
@@ -1 +1,4 @@
+pub mod projective_dynamics;
+pub mod tetra_demo;
+pub mod vbd;
 pub mod xpbd;
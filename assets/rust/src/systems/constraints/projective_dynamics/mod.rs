@@ -0,0 +1,3 @@
+pub mod interface;
+pub mod system;
+pub mod system_parameters;
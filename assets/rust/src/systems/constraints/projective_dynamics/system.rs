@@ -0,0 +1,376 @@
+use super::system_parameters::SystemParameters;
+use crate::utils::console;
+use crate::utils::recorder::{self, InputEvent, Recordable};
+use crate::utils::sparse_cholesky::SparseCholesky;
+use nalgebra::{Matrix3, Vector3};
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+
+// weight of the grab constraint relative to the inertia of the grabbed vertex
+const GRAB_WEIGHT: f64 = 100.0;
+const VOLUME_PROJECTION_ITERATIONS: usize = 5;
+
+// Bouaziz et al., Projective Dynamics. Every element has a strain constraint, F close
+// to its rotation R with weight 2 μ V, and a volume constraint, F close to the nearest
+// det F = 1 deformation with weight λ V. The local step projects F onto both, the global
+// step solves (M/h² + Σ w Gᵀ G) x = M/h² y + Σ w Gᵀ p with G the linear map x -> F.
+// The matrix is the same for x, y and z and is factored once.
+pub struct System {
+    pub par: SystemParameters,
+    orig_pos: Vec<Vector3<f64>>,
+    num_particles: usize,
+    num_elems: usize,
+    pub pos: Vec<Vector3<f64>>,
+    prev_pos: Vec<Vector3<f64>>,
+    inertia: Vec<Vector3<f64>>,
+    vel: Vec<Vector3<f64>>,
+    // lumped rest volume, the mass is density * volume
+    vertex_volume: Vec<f64>,
+    inv_rest_pose: Vec<Matrix3<f64>>,
+    rest_volume: Vec<f64>,
+    tet_ids: Vec<[usize; 4]>,
+    // (strain, volume) projections of every element
+    projections: Vec<(Matrix3<f64>, Matrix3<f64>)>,
+    rhs: Vec<Vector3<f64>>,
+    // one coordinate of rhs, solved in place
+    column: Vec<f64>,
+    factor: Option<SparseCholesky>,
+    // mu, lambda, density, dt and grab_id the factor was built for
+    factor_key: (f64, f64, f64, f64, i32),
+    vol_error: f64,
+    grab_pos: Vector3<f64>,
+    grab_id: i32,
+}
+
+impl System {
+    pub fn new(vertices: &[Vector3<f64>], tet_ids: &[[usize; 4]], par: SystemParameters) -> Self {
+        let num_particles = vertices.len();
+        let mut system = System {
+            par,
+            orig_pos: vertices.to_vec(),
+            num_particles,
+            num_elems: tet_ids.len(),
+            pos: vertices.to_vec(),
+            prev_pos: vertices.to_vec(),
+            inertia: vertices.to_vec(),
+            vel: vec![Vector3::zeros(); num_particles],
+            vertex_volume: vec![0.0; num_particles],
+            inv_rest_pose: vec![Matrix3::zeros(); tet_ids.len()],
+            rest_volume: vec![0.0; tet_ids.len()],
+            tet_ids: tet_ids.to_vec(),
+            projections: Vec::new(),
+            rhs: vec![Vector3::zeros(); num_particles],
+            column: vec![0.0; num_particles],
+            factor: None,
+            factor_key: (0.0, 0.0, 0.0, 0.0, -1),
+            vol_error: 0.0,
+            grab_pos: Vector3::zeros(),
+            grab_id: -1,
+        };
+        system.init_physics();
+        system
+    }
+
+    pub fn reset(&mut self) {
+        self.pos = self.orig_pos.clone();
+        self.prev_pos = self.orig_pos.clone();
+        self.vel = vec![Vector3::zeros(); self.num_particles];
+        self.vol_error = 0.0;
+        self.grab_pos = Vector3::zeros();
+        self.grab_id = -1;
+    }
+
+    pub fn vel(&self) -> &[Vector3<f64>] {
+        &self.vel
+    }
+
+    pub fn tet_ids(&self) -> &[[usize; 4]] {
+        &self.tet_ids
+    }
+
+    // mean det F - 1 after the last substep
+    pub fn vol_error(&self) -> f64 {
+        self.vol_error
+    }
+
+    fn init_physics(&mut self) {
+        for i in 0..self.num_elems {
+            let [id0, id1, id2, id3] = self.tet_ids[i];
+            let p0 = self.orig_pos[id1] - self.orig_pos[id0];
+            let p1 = self.orig_pos[id2] - self.orig_pos[id0];
+            let p2 = self.orig_pos[id3] - self.orig_pos[id0];
+            let p = Matrix3::from_columns(&[p0, p1, p2]);
+            let v = (p.determinant() / 6.0).abs();
+            if v == 0.0 {
+                continue;
+            }
+            self.inv_rest_pose[i] = p.try_inverse().unwrap();
+            self.rest_volume[i] = v;
+            for id in self.tet_ids[i] {
+                self.vertex_volume[id] += v / 4.0;
+            }
+        }
+    }
+
+    // dF/dx_k = I ⊗ b_k for the four corners of an element
+    fn corner_weights(&self, elem_nr: usize) -> [Vector3<f64>; 4] {
+        let b = &self.inv_rest_pose[elem_nr];
+        [
+            -(b.row(0) + b.row(1) + b.row(2)).transpose(),
+            b.row(0).transpose(),
+            b.row(1).transpose(),
+            b.row(2).transpose(),
+        ]
+    }
+
+    fn deformation_gradient(&self, elem_nr: usize) -> Matrix3<f64> {
+        let [id0, id1, id2, id3] = self.tet_ids[elem_nr];
+        let x0 = self.pos[id0];
+        let p =
+            Matrix3::from_columns(&[self.pos[id1] - x0, self.pos[id2] - x0, self.pos[id3] - x0]);
+        p * self.inv_rest_pose[elem_nr]
+    }
+
+    // (strain, volume) weights of an element
+    fn weights(&self, elem_nr: usize, mu: f64, lambda: f64) -> (f64, f64) {
+        let v = self.rest_volume[elem_nr];
+        (2.0 * mu * v, lambda * v)
+    }
+
+    fn grab_weight(&self, dt: f64) -> f64 {
+        let id = self.grab_id as usize;
+        GRAB_WEIGHT * self.par.density * self.vertex_volume[id] / (dt * dt)
+    }
+
+    // Factors M/h² + Σ w Gᵀ G, rebuilt only when the key changes.
+    fn update_factor(&mut self, mu: f64, lambda: f64, dt: f64) {
+        let key = (mu, lambda, self.par.density, dt, self.grab_id);
+        if self.factor.is_some() && key == self.factor_key {
+            return;
+        }
+        let mut entries = Vec::with_capacity(self.num_particles + 10 * self.num_elems + 1);
+        for i in 0..self.num_particles {
+            entries.push((i, i, self.par.density * self.vertex_volume[i] / (dt * dt)));
+        }
+        for elem_nr in 0..self.num_elems {
+            if self.rest_volume[elem_nr] == 0.0 {
+                continue;
+            }
+            let (w_strain, w_volume) = self.weights(elem_nr, mu, lambda);
+            let w = w_strain + w_volume;
+            let b = self.corner_weights(elem_nr);
+            let ids = self.tet_ids[elem_nr];
+            for k in 0..4 {
+                for l in k..4 {
+                    entries.push((ids[k], ids[l], w * b[k].dot(&b[l])));
+                }
+            }
+        }
+        if self.grab_id >= 0 {
+            let id = self.grab_id as usize;
+            entries.push((id, id, self.grab_weight(dt)));
+        }
+        self.factor = SparseCholesky::new(self.num_particles, &entries);
+        // retried every substep, reported once per key
+        if self.factor.is_none() && key != self.factor_key {
+            console::log(
+                "projective dynamics: the system matrix is not positive definite, the global step is skipped",
+            );
+        }
+        self.factor_key = key;
+    }
+
+    // Nearest rotation and nearest volume preserving deformation to F.
+    fn project(f: &Matrix3<f64>) -> (Matrix3<f64>, Matrix3<f64>) {
+        let svd = f.svd(true, true);
+        let (mut u, v_t) = (svd.u.unwrap(), svd.v_t.unwrap());
+        let mut sigma = svd.singular_values;
+        // keep R a rotation for inverted elements
+        if (u * v_t).determinant() < 0.0 {
+            let mut last = u.column_mut(2);
+            last *= -1.0;
+            sigma[2] = -sigma[2];
+        }
+        let sigma = sigma.map(|s| s.max(1e-3));
+        // nearest singular values with σ1 σ2 σ3 = 1, Newton on the linearized constraint
+        let mut d = Vector3::zeros();
+        for _ in 0..VOLUME_PROJECTION_ITERATIONS {
+            let s = sigma + d;
+            let c = s.x * s.y * s.z - 1.0;
+            let g = Vector3::new(s.y * s.z, s.x * s.z, s.x * s.y);
+            d = g * ((g.dot(&d) - c) / g.norm_squared());
+        }
+        let rotation = u * v_t;
+        let volume = u * Matrix3::from_diagonal(&(sigma + d)) * v_t;
+        (rotation, volume)
+    }
+
+    fn local_step(&mut self) {
+        let mut projections = std::mem::take(&mut self.projections);
+        let project = |elem_nr: usize| Self::project(&self.deformation_gradient(elem_nr));
+        #[cfg(not(target_arch = "wasm32"))]
+        (0..self.num_elems)
+            .into_par_iter()
+            .map(project)
+            .collect_into_vec(&mut projections);
+        #[cfg(target_arch = "wasm32")]
+        {
+            projections.clear();
+            projections.extend((0..self.num_elems).map(project));
+        }
+        self.projections = projections;
+    }
+
+    fn global_step(&mut self, mu: f64, lambda: f64, dt: f64) {
+        for i in 0..self.num_particles {
+            self.rhs[i] = self.inertia[i] * (self.par.density * self.vertex_volume[i] / (dt * dt));
+        }
+        for elem_nr in 0..self.num_elems {
+            if self.rest_volume[elem_nr] == 0.0 {
+                continue;
+            }
+            let (w_strain, w_volume) = self.weights(elem_nr, mu, lambda);
+            let (rotation, volume) = &self.projections[elem_nr];
+            let p = rotation * w_strain + volume * w_volume;
+            for (&id, b) in self.tet_ids[elem_nr]
+                .iter()
+                .zip(self.corner_weights(elem_nr))
+            {
+                self.rhs[id] += p * b;
+            }
+        }
+        if self.grab_id >= 0 {
+            let id = self.grab_id as usize;
+            let p = self.grab_pos * self.grab_weight(dt);
+            self.rhs[id] += p;
+        }
+        // not positive definite, reported in update_factor
+        let Some(factor) = &self.factor else {
+            return;
+        };
+        for c in 0..3 {
+            for (x, r) in self.column.iter_mut().zip(&self.rhs) {
+                *x = r[c];
+            }
+            factor.solve(&mut self.column);
+            for (p, x) in self.pos.iter_mut().zip(&self.column) {
+                p[c] = *x;
+            }
+        }
+    }
+
+    // Projects the particles back inside the walls, the tangential motion of this
+    // substep is reduced by friction.
+    fn solve_world_bounds(&mut self) {
+        let (lo, hi) = self.par.world_bounds;
+        for id in 0..self.num_particles {
+            for i in 0..3 {
+                let mut normal = Vector3::zeros();
+                let depth = if self.pos[id][i] < lo[i] {
+                    normal[i] = 1.0;
+                    lo[i] - self.pos[id][i]
+                } else if self.pos[id][i] > hi[i] {
+                    normal[i] = -1.0;
+                    self.pos[id][i] - hi[i]
+                } else {
+                    continue;
+                };
+                self.pos[id] += normal * depth;
+                let d = self.pos[id] - self.prev_pos[id];
+                let dt = d - normal * normal.dot(&d);
+                let dt_len = dt.norm();
+                if dt_len > 0.0 {
+                    self.pos[id] -= dt * (self.par.friction * depth / dt_len).min(1.0);
+                }
+            }
+        }
+    }
+
+    fn find_closest(&self, p: Vector3<f64>) -> i32 {
+        self.pos
+            .iter()
+            .enumerate()
+            .min_by(|a, b| {
+                (a.1 - p)
+                    .norm_squared()
+                    .total_cmp(&(b.1 - p).norm_squared())
+            })
+            .map_or(-1, |(id, _)| id as i32)
+    }
+    pub fn init_grab(&mut self, p: Vector3<f64>) {
+        self.grab_id = self.find_closest(p);
+    }
+    pub fn move_grab(&mut self, p: Vector3<f64>) {
+        self.grab_pos = p;
+    }
+    pub fn release_grab(&mut self) {
+        self.grab_id = -1;
+    }
+
+    pub fn simulate(&mut self) {
+        for _ in 0..self.par.num_substeps {
+            self.substep();
+        }
+    }
+
+    pub fn substep(&mut self) {
+        let dt = self.par.time_step / self.par.num_substeps as f64;
+        let (mu, lambda) = self.par.elasticity.lame();
+        self.update_factor(mu, lambda, dt);
+        for i in 0..self.num_particles {
+            self.prev_pos[i] = self.pos[i];
+            self.inertia[i] = self.pos[i] + self.vel[i] * dt + self.par.gravity * dt * dt;
+            self.pos[i] = self.inertia[i];
+        }
+        for _ in 0..self.par.num_iterations {
+            self.local_step();
+            self.global_step(mu, lambda, dt);
+        }
+        self.solve_world_bounds();
+        for i in 0..self.num_particles {
+            self.vel[i] = (self.pos[i] - self.prev_pos[i]) / dt;
+        }
+        self.vol_error = 0.0;
+        for i in 0..self.num_elems {
+            if self.rest_volume[i] > 0.0 {
+                self.vol_error += self.deformation_gradient(i).determinant() - 1.0;
+            }
+        }
+        if self.num_elems > 0 {
+            self.vol_error /= self.num_elems as f64;
+        }
+    }
+}
+
+impl Recordable for System {
+    fn num_substeps(&self) -> usize {
+        self.par.num_substeps
+    }
+
+    fn substep(&mut self) {
+        System::substep(self);
+    }
+
+    fn save_state(&self, out: &mut Vec<f64>) {
+        for (p, v) in self.pos.iter().zip(&self.vel) {
+            out.extend_from_slice(&[p.x, p.y, p.z, v.x, v.y, v.z]);
+        }
+    }
+
+    fn load_state(&mut self, state: &[f64]) -> std::io::Result<()> {
+        recorder::check_state_len(state, 6 * self.num_particles)?;
+        for (i, s) in state.chunks_exact(6).enumerate() {
+            self.pos[i] = Vector3::new(s[0], s[1], s[2]);
+            self.vel[i] = Vector3::new(s[3], s[4], s[5]);
+        }
+        Ok(())
+    }
+
+    fn apply_input(&mut self, event: &InputEvent) {
+        match *event {
+            InputEvent::InitGrab(p) => self.init_grab(p),
+            InputEvent::MoveGrab(p) => self.move_grab(p),
+            InputEvent::ReleaseGrab => self.release_grab(),
+        }
+    }
+}
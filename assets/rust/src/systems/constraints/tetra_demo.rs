@@ -0,0 +1,280 @@
+use crate::systems::constraints::xpbd::neohookean::interface::{
+    CustomIntersectionResult, OrbitControl, custom_pick,
+};
+use crate::systems::constraints::xpbd::neohookean::material::Elasticity;
+use crate::systems::constraints::xpbd::neohookean::visualizer::{RenderMode, Visualizer};
+use crate::utils::diagnostics::Diagnostics;
+use crate::utils::recorder::{InputEvent, Recordable, Recorder};
+use crate::utils::{base_url, console, create_window, tetra_mesh, ui, ui::BaseState};
+use three_d::*;
+
+// What the GUI of the demo sets, the system takes it over every frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub elasticity: Elasticity,
+    pub density: f64,
+    pub num_iterations: usize,
+    // no slider for systems without damping
+    pub damping: Option<f64>,
+    pub friction: f64,
+}
+
+// A solver of one tetra mesh body in the world bounds.
+pub trait TetraSystem: Recordable + Sized {
+    type Parameters: Default;
+    // upper ends of the E and ν sliders
+    const MAX_YOUNGS_MODULUS: f64;
+    const MAX_POISSON_RATIO: f64;
+
+    fn create(
+        vertices: &[nalgebra::Vector3<f64>],
+        tet_ids: &[[usize; 4]],
+        par: Self::Parameters,
+    ) -> Self;
+    // height of the ground
+    fn floor(par: &Self::Parameters) -> f64;
+    fn settings(par: &Self::Parameters) -> Settings;
+    fn apply(&mut self, settings: &Settings);
+    fn pos(&self) -> &[nalgebra::Vector3<f64>];
+    fn time_step(&self) -> f64;
+    fn vol_error(&self) -> f64;
+    fn reset(&mut self);
+    fn init_grab(&mut self, p: nalgebra::Vector3<f64>);
+    fn move_grab(&mut self, p: nalgebra::Vector3<f64>);
+    fn release_grab(&mut self);
+}
+
+struct State {
+    base: BaseState,
+    settings: Settings,
+    render_mode: RenderMode,
+}
+impl State {
+    pub fn new(settings: Settings) -> Self {
+        State {
+            base: BaseState {
+                diagnostics: Diagnostics::new(&["volume error"]),
+                ..BaseState::new()
+            },
+            settings,
+            render_mode: RenderMode::Surface,
+        }
+    }
+}
+
+fn gui_logic<S: TetraSystem>(
+    gui: &mut three_d::GUI,
+    frame_input: &mut FrameInput,
+    state: &mut State,
+) {
+    ui::gui_logic(
+        gui,
+        frame_input,
+        &mut state.base,
+        |ui, _base_state| {
+            ui.horizontal(|ui| {
+                if let Elasticity::Young {
+                    youngs_modulus,
+                    poisson_ratio,
+                } = &mut state.settings.elasticity
+                {
+                    for (slider, name) in [
+                        (
+                            three_d::egui::Slider::new(
+                                youngs_modulus,
+                                500.0..=S::MAX_YOUNGS_MODULUS,
+                            )
+                            .logarithmic(true),
+                            "E",
+                        ),
+                        (
+                            three_d::egui::Slider::new(poisson_ratio, 0.0..=S::MAX_POISSON_RATIO),
+                            "ν",
+                        ),
+                    ] {
+                        ui.add(slider);
+                        ui.label(
+                            three_d::egui::RichText::new(name)
+                                .strong()
+                                .color(ui::TEXT_COLOR),
+                        );
+                    }
+                }
+                ui.add(three_d::egui::Slider::new(
+                    &mut state.settings.density,
+                    0.1..=10.0,
+                ));
+                ui.label(
+                    three_d::egui::RichText::new("density")
+                        .strong()
+                        .color(ui::TEXT_COLOR),
+                );
+            });
+        },
+        |ui, _base_state| {
+            ui.horizontal(|ui| {
+                ui.add(
+                    three_d::egui::DragValue::new(&mut state.settings.num_iterations)
+                        .range(1..=100),
+                );
+                ui.label(
+                    three_d::egui::RichText::new("iterations")
+                        .strong()
+                        .color(ui::TEXT_COLOR),
+                );
+                for mode in RenderMode::ALL {
+                    ui.radio_value(
+                        &mut state.render_mode,
+                        mode,
+                        three_d::egui::RichText::new(mode.name()).color(ui::TEXT_COLOR),
+                    );
+                }
+            });
+            ui.horizontal(|ui| {
+                if let Some(damping) = &mut state.settings.damping {
+                    ui.add(three_d::egui::Slider::new(damping, 0.0..=0.01));
+                    ui.label(
+                        three_d::egui::RichText::new("damping")
+                            .strong()
+                            .color(ui::TEXT_COLOR),
+                    );
+                }
+                ui.add(three_d::egui::Slider::new(
+                    &mut state.settings.friction,
+                    0.0..=1.0,
+                ));
+                ui.label(
+                    three_d::egui::RichText::new("friction")
+                        .strong()
+                        .color(ui::TEXT_COLOR),
+                );
+            });
+        },
+    );
+}
+
+// The tetra cube dropped in the world bounds, grabbed with the mouse.
+pub async fn run<S: TetraSystem + 'static>(_canvas_id: &str) {
+    #[cfg(not(target_arch = "wasm32"))]
+    let window = create_window::create_window();
+    #[cfg(target_arch = "wasm32")]
+    let window = create_window::create_window(_canvas_id);
+    let context = window.gl();
+
+    let path_to_assets = base_url::get_base_url();
+    let path_to_obj = "tetra_cube.obj";
+    let tetra_mesh = match tetra_mesh::TetraMesh::new(&path_to_assets, path_to_obj).await {
+        Ok(tetra_mesh) => tetra_mesh,
+        Err(e) => {
+            console::log(&format!("{}: {}", path_to_obj, e));
+            return;
+        }
+    };
+
+    let target = three_d::vec3(0.0, 3.0, 0.0);
+    let scene_radius = 20.0;
+
+    let mut control = OrbitControl::new(target, 0.1 * scene_radius, 100.0 * scene_radius, 0.005);
+    // same start as the first XPBD body, just above the ground
+    let par = S::Parameters::default();
+    let min_y = tetra_mesh
+        .positions
+        .iter()
+        .map(|p| p.y)
+        .fold(f64::MAX, f64::min);
+    let lift = nalgebra::Vector3::new(0.0, S::floor(&par) + 0.5 - min_y, 0.0);
+    let vertices: Vec<_> = tetra_mesh.positions.iter().map(|p| p + lift).collect();
+    let mut state = State::new(S::settings(&par));
+    let mut sys = S::create(&vertices, &tetra_mesh.tetr_indexes, par);
+    let mut gui = three_d::GUI::new(&context);
+    let mut fps_counter = ui::FPSCounter::new();
+    let mut recorder = Recorder::new();
+    let mut vis = Visualizer::new(&window, sys.pos(), &[], &tetra_mesh, &target, scene_radius);
+    let mut picked = false;
+    let mut intersection_result = CustomIntersectionResult {
+        position: vec3(0.0, 0.0, 0.0),
+        depth: 0.0,
+        direction: vec3(0.0, 0.0, 0.0),
+        geometry_id: 0,
+        instance_id: 0,
+    };
+    let mut sim_time = 0.0;
+    window.render_loop(move |mut frame_input| {
+        gui_logic::<S>(&mut gui, &mut frame_input, &mut state);
+
+        vis.camera.set_viewport(frame_input.viewport);
+        for event in frame_input.events.iter() {
+            if let Event::MousePress {
+                button, position, ..
+            } = *event
+                && button == MouseButton::Left
+                && let Some(pick) = if vis.mode == RenderMode::Wireframe {
+                    custom_pick(&context, &vis.camera, position, [&vis.edges])
+                } else {
+                    custom_pick(&context, &vis.camera, position, [&vis.surface])
+                }
+            {
+                picked = true;
+                intersection_result = pick;
+                let pos = nalgebra::Vector3::new(
+                    pick.position.x as f64,
+                    pick.position.y as f64,
+                    pick.position.z as f64,
+                );
+
+                sys.init_grab(pos);
+                sys.move_grab(pos);
+                recorder.record_input(InputEvent::InitGrab(pos));
+                recorder.record_input(InputEvent::MoveGrab(pos));
+            }
+            if let Event::MouseMotion { position, .. } = *event
+                && picked
+            {
+                let mut pos_new = vis.camera.position_at_pixel(position);
+                let dir_new = vis.camera.view_direction_at_pixel(position);
+                pos_new += dir_new * intersection_result.depth;
+                let pos =
+                    nalgebra::Vector3::new(pos_new.x as f64, pos_new.y as f64, pos_new.z as f64);
+                sys.move_grab(pos);
+                recorder.record_input(InputEvent::MoveGrab(pos));
+            }
+            if let Event::MouseRelease { button, .. } = *event
+                && button == MouseButton::Left
+            {
+                picked = false;
+                sys.release_grab();
+                recorder.record_input(InputEvent::ReleaseGrab);
+            }
+        }
+        if !picked {
+            control.handle_events(&mut vis.camera, &mut frame_input.events);
+        }
+
+        if state.base.reset {
+            sys.reset();
+            state.base.reset = false;
+            state.base.diagnostics.clear();
+            sim_time = 0.0;
+        }
+
+        sys.apply(&state.settings);
+
+        let mode_changed = vis.mode != state.render_mode;
+        vis.mode = state.render_mode;
+        if recorder.update(&mut sys, &mut state.base) || mode_changed {
+            vis.update(sys.pos(), &[]);
+        }
+        if !state.base.stop && !state.base.replay.replaying {
+            sim_time += sys.time_step();
+            state.base.diagnostics.push(sim_time, &[sys.vol_error()]);
+        }
+        if !state.base.stop {
+            state.base.fps = fps_counter.update(&frame_input);
+        }
+
+        vis.render(&frame_input);
+        frame_input.screen().write(|| gui.render()).unwrap();
+
+        FrameOutput::default()
+    });
+}
@@ -0,0 +1,61 @@
+use super::system::System;
+use super::system_parameters::SystemParameters;
+use crate::systems::constraints::tetra_demo::{self, Settings, TetraSystem};
+use nalgebra::Vector3;
+
+impl TetraSystem for System {
+    type Parameters = SystemParameters;
+    // stiffer materials need more iterations than the default to stay stable
+    const MAX_YOUNGS_MODULUS: f64 = 20000.0;
+    const MAX_POISSON_RATIO: f64 = 0.45;
+
+    fn create(vertices: &[Vector3<f64>], tet_ids: &[[usize; 4]], par: SystemParameters) -> Self {
+        System::new(vertices, tet_ids, par)
+    }
+    fn floor(par: &SystemParameters) -> f64 {
+        par.world_bounds.0.y
+    }
+    fn settings(par: &SystemParameters) -> Settings {
+        Settings {
+            elasticity: par.elasticity,
+            density: par.density,
+            num_iterations: par.num_iterations,
+            damping: Some(par.damping),
+            friction: par.friction,
+        }
+    }
+    fn apply(&mut self, settings: &Settings) {
+        self.par.elasticity = settings.elasticity;
+        self.par.density = settings.density;
+        self.par.num_iterations = settings.num_iterations;
+        if let Some(damping) = settings.damping {
+            self.par.damping = damping;
+        }
+        self.par.friction = settings.friction;
+    }
+    fn pos(&self) -> &[Vector3<f64>] {
+        &self.pos
+    }
+    fn time_step(&self) -> f64 {
+        self.par.time_step
+    }
+    fn vol_error(&self) -> f64 {
+        System::vol_error(self)
+    }
+    fn reset(&mut self) {
+        System::reset(self)
+    }
+    fn init_grab(&mut self, p: Vector3<f64>) {
+        System::init_grab(self, p)
+    }
+    fn move_grab(&mut self, p: Vector3<f64>) {
+        System::move_grab(self, p)
+    }
+    fn release_grab(&mut self) {
+        System::release_grab(self)
+    }
+}
+
+pub async fn run(canvas_id: &str) {
+    tetra_demo::run::<System>(canvas_id).await;
+}
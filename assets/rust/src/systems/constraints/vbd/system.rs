@@ -0,0 +1,441 @@
+use super::system_parameters::SystemParameters;
+use crate::systems::constraints::xpbd::neohookean::material::cofactor;
+use crate::utils::graph_coloring;
+use crate::utils::recorder::{self, InputEvent, Recordable};
+use nalgebra::{Matrix3, Vector3};
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+
+// tangential speed below which wall friction turns into a stiff spring
+const FRICTION_EPS_VELOCITY: f64 = 0.01;
+
+// Chen et al., Vertex Block Descent. An implicit Euler step minimizes
+// G(x) = 1/(2h²) ||x - y||²_M + E(x) with the inertial target y = x + h v + h² g,
+// one Newton step on a single vertex at a time, the vertices of a color in parallel.
+pub struct System {
+    pub par: SystemParameters,
+    orig_pos: Vec<Vector3<f64>>,
+    num_particles: usize,
+    num_elems: usize,
+    pub pos: Vec<Vector3<f64>>,
+    prev_pos: Vec<Vector3<f64>>,
+    inertia: Vec<Vector3<f64>>,
+    // deformation gradients at the start of the substep, for damping
+    prev_def_grads: Vec<Matrix3<f64>>,
+    vel: Vec<Vector3<f64>>,
+    // lumped rest volume, the mass is density * volume
+    vertex_volume: Vec<f64>,
+    inv_rest_pose: Vec<Matrix3<f64>>,
+    rest_volume: Vec<f64>,
+    tet_ids: Vec<[usize; 4]>,
+    // (element, corner) of every element around a vertex
+    vertex_elems: Vec<Vec<(usize, usize)>>,
+    // vertices of a color share no element
+    colors: Vec<Vec<usize>>,
+    // new positions of the vertices of one color
+    solved: Vec<Vector3<f64>>,
+    vol_error: f64,
+    grab_pos: Vector3<f64>,
+    grab_id: i32,
+}
+
+impl System {
+    pub fn new(vertices: &[Vector3<f64>], tet_ids: &[[usize; 4]], par: SystemParameters) -> Self {
+        let num_particles = vertices.len();
+        let mut vertex_elems = vec![Vec::new(); num_particles];
+        for (elem_nr, tet) in tet_ids.iter().enumerate() {
+            for (k, &id) in tet.iter().enumerate() {
+                vertex_elems[id].push((elem_nr, k));
+            }
+        }
+        let mut system = System {
+            par,
+            orig_pos: vertices.to_vec(),
+            num_particles,
+            num_elems: tet_ids.len(),
+            pos: vertices.to_vec(),
+            prev_pos: vertices.to_vec(),
+            inertia: vertices.to_vec(),
+            prev_def_grads: vec![Matrix3::identity(); tet_ids.len()],
+            vel: vec![Vector3::zeros(); num_particles],
+            vertex_volume: vec![0.0; num_particles],
+            inv_rest_pose: vec![Matrix3::zeros(); tet_ids.len()],
+            rest_volume: vec![0.0; tet_ids.len()],
+            tet_ids: tet_ids.to_vec(),
+            vertex_elems,
+            colors: graph_coloring::color_vertices(num_particles, tet_ids),
+            solved: Vec::new(),
+            vol_error: 0.0,
+            grab_pos: Vector3::zeros(),
+            grab_id: -1,
+        };
+        system.init_physics();
+        system
+    }
+
+    pub fn reset(&mut self) {
+        self.pos = self.orig_pos.clone();
+        self.prev_pos = self.orig_pos.clone();
+        self.vel = vec![Vector3::zeros(); self.num_particles];
+        self.prev_def_grads = vec![Matrix3::identity(); self.num_elems];
+        self.vol_error = 0.0;
+        self.grab_pos = Vector3::zeros();
+        self.grab_id = -1;
+    }
+
+    pub fn vel(&self) -> &[Vector3<f64>] {
+        &self.vel
+    }
+
+    pub fn tet_ids(&self) -> &[[usize; 4]] {
+        &self.tet_ids
+    }
+
+    // mean det F - 1 after the last substep
+    pub fn vol_error(&self) -> f64 {
+        self.vol_error
+    }
+
+    fn init_physics(&mut self) {
+        for i in 0..self.num_elems {
+            let [id0, id1, id2, id3] = self.tet_ids[i];
+            let p0 = self.orig_pos[id1] - self.orig_pos[id0];
+            let p1 = self.orig_pos[id2] - self.orig_pos[id0];
+            let p2 = self.orig_pos[id3] - self.orig_pos[id0];
+            let p = Matrix3::from_columns(&[p0, p1, p2]);
+            let v = (p.determinant() / 6.0).abs();
+            if v == 0.0 {
+                continue;
+            }
+            self.inv_rest_pose[i] = p.try_inverse().unwrap();
+            self.rest_volume[i] = v;
+            for id in self.tet_ids[i] {
+                self.vertex_volume[id] += v / 4.0;
+            }
+        }
+    }
+
+    fn deformation_gradient(&self, elem_nr: usize, pos: &[Vector3<f64>]) -> Matrix3<f64> {
+        let [id0, id1, id2, id3] = self.tet_ids[elem_nr];
+        let x0 = pos[id0];
+        let p = Matrix3::from_columns(&[pos[id1] - x0, pos[id2] - x0, pos[id3] - x0]);
+        p * self.inv_rest_pose[elem_nr]
+    }
+
+    // Force and Hessian of the stable Neo-Hookean energy of all elements around
+    // vertex i, Ψ = μ/2 (I_C - 3) + λ'/2 (J - α)² with λ' = λ + μ and α = 1 + μ/λ'
+    // (Smith et al.), so the rest state is stress free.
+    fn elastic_block(
+        &self,
+        i: usize,
+        mu: f64,
+        lambda: f64,
+        dt: f64,
+    ) -> (Vector3<f64>, Matrix3<f64>) {
+        let vol_stiffness = lambda + mu;
+        let alpha = 1.0 + mu / vol_stiffness;
+        let damping = self.par.damping / dt;
+        let mut force = Vector3::zeros();
+        let mut hessian = Matrix3::zeros();
+        for &(elem_nr, k) in &self.vertex_elems[i] {
+            let v = self.rest_volume[elem_nr];
+            if v == 0.0 {
+                continue;
+            }
+            // dF/dx_i = I ⊗ b
+            let b_inv = &self.inv_rest_pose[elem_nr];
+            let b: Vector3<f64> = if k == 0 {
+                -(b_inv.row(0) + b_inv.row(1) + b_inv.row(2)).transpose()
+            } else {
+                b_inv.row(k - 1).transpose()
+            };
+            let f = self.deformation_gradient(elem_nr, &self.pos);
+            let cof = cofactor(&f);
+            let j = f.determinant();
+            let stress = f * mu + cof * (vol_stiffness * (j - alpha));
+            // J is linear in a single vertex, only the outer product of dJ/dx_i remains
+            let g = cof * b;
+            let h = (Matrix3::identity() * (mu * b.norm_squared())
+                + g * g.transpose() * vol_stiffness)
+                * v;
+            force -= stress * b * v;
+            hessian += h;
+            // Rayleigh damping, the stiffness applied to the change of F in this
+            // substep so rigid translations are not damped
+            if damping > 0.0 {
+                let df = f - self.prev_def_grads[elem_nr];
+                let dstress = df * mu + cof * (vol_stiffness * cof.dot(&df));
+                force -= dstress * b * (v * damping);
+                hessian += h * damping;
+            }
+        }
+        (force, hessian)
+    }
+
+    // Penalty walls with lagged Coulomb friction on the displacement of this substep.
+    fn contact_block(&self, i: usize, dt: f64) -> (Vector3<f64>, Matrix3<f64>) {
+        let (lo, hi) = self.par.world_bounds;
+        let x = self.pos[i];
+        let mut force = Vector3::zeros();
+        let mut hessian = Matrix3::zeros();
+        let damping = self.par.contact_stiffness * self.par.damping / dt;
+        for a in 0..3 {
+            let mut normal = Vector3::zeros();
+            let depth = if x[a] < lo[a] {
+                normal[a] = 1.0;
+                lo[a] - x[a]
+            } else if x[a] > hi[a] {
+                normal[a] = -1.0;
+                x[a] - hi[a]
+            } else {
+                continue;
+            };
+            let nn = normal * normal.transpose();
+            let u = x - self.prev_pos[i];
+            let normal_force = self.par.contact_stiffness * depth;
+            force += normal * (normal_force - damping * normal.dot(&u));
+            hessian += nn * (self.par.contact_stiffness + damping);
+
+            let ut = u - normal * normal.dot(&u);
+            let s = self.par.friction * normal_force / ut.norm().max(FRICTION_EPS_VELOCITY * dt);
+            force -= ut * s;
+            hessian += (Matrix3::identity() - nn) * s;
+        }
+        (force, hessian)
+    }
+
+    // New position of vertex i after one Newton step on its local energy.
+    fn solve_vertex(&self, i: usize, mu: f64, lambda: f64, dt: f64) -> Vector3<f64> {
+        let x = self.pos[i];
+        if i as i32 == self.grab_id {
+            return x;
+        }
+        let m = self.par.density * self.vertex_volume[i] / (dt * dt);
+        let (elastic_force, elastic_hessian) = self.elastic_block(i, mu, lambda, dt);
+        let (contact_force, contact_hessian) = self.contact_block(i, dt);
+        let force = (self.inertia[i] - x) * m + elastic_force + contact_force;
+        let hessian = Matrix3::identity() * m + elastic_hessian + contact_hessian;
+        match hessian.cholesky() {
+            Some(cholesky) => x + cholesky.solve(&force),
+            None => x,
+        }
+    }
+
+    fn solve_colors(&mut self, mu: f64, lambda: f64, dt: f64) {
+        let mut solved = std::mem::take(&mut self.solved);
+        for color in &self.colors {
+            let solve = |&i: &usize| self.solve_vertex(i, mu, lambda, dt);
+            #[cfg(not(target_arch = "wasm32"))]
+            color.par_iter().map(solve).collect_into_vec(&mut solved);
+            #[cfg(target_arch = "wasm32")]
+            {
+                solved.clear();
+                solved.extend(color.iter().map(solve));
+            }
+            for (&i, x) in color.iter().zip(&solved) {
+                self.pos[i] = *x;
+            }
+        }
+        self.solved = solved;
+    }
+
+    fn find_closest(&self, p: Vector3<f64>) -> i32 {
+        self.pos
+            .iter()
+            .enumerate()
+            .min_by(|a, b| {
+                (a.1 - p)
+                    .norm_squared()
+                    .total_cmp(&(b.1 - p).norm_squared())
+            })
+            .map_or(-1, |(id, _)| id as i32)
+    }
+    pub fn init_grab(&mut self, p: Vector3<f64>) {
+        self.grab_id = self.find_closest(p);
+    }
+    pub fn move_grab(&mut self, p: Vector3<f64>) {
+        self.grab_pos = p;
+    }
+    pub fn release_grab(&mut self) {
+        self.grab_id = -1;
+    }
+
+    pub fn simulate(&mut self) {
+        for _ in 0..self.par.num_substeps {
+            self.substep();
+        }
+    }
+
+    pub fn substep(&mut self) {
+        let dt = self.par.time_step / self.par.num_substeps as f64;
+        let (mu, lambda) = self.par.elasticity.lame();
+        // the inertial target is also the initial guess
+        for i in 0..self.num_particles {
+            self.prev_pos[i] = self.pos[i];
+            self.inertia[i] = self.pos[i] + self.vel[i] * dt + self.par.gravity * dt * dt;
+            self.pos[i] = self.inertia[i];
+        }
+        if self.par.damping > 0.0 {
+            for i in 0..self.num_elems {
+                self.prev_def_grads[i] = self.deformation_gradient(i, &self.prev_pos);
+            }
+        }
+        if self.grab_id >= 0 {
+            self.pos[self.grab_id as usize] = self.grab_pos;
+        }
+        for _ in 0..self.par.num_iterations {
+            self.solve_colors(mu, lambda, dt);
+        }
+        for i in 0..self.num_particles {
+            self.vel[i] = (self.pos[i] - self.prev_pos[i]) / dt;
+        }
+        self.vol_error = 0.0;
+        for i in 0..self.num_elems {
+            if self.rest_volume[i] > 0.0 {
+                self.vol_error += self.deformation_gradient(i, &self.pos).determinant() - 1.0;
+            }
+        }
+        if self.num_elems > 0 {
+            self.vol_error /= self.num_elems as f64;
+        }
+    }
+}
+
+impl Recordable for System {
+    fn num_substeps(&self) -> usize {
+        self.par.num_substeps
+    }
+
+    fn substep(&mut self) {
+        System::substep(self);
+    }
+
+    fn save_state(&self, out: &mut Vec<f64>) {
+        for (p, v) in self.pos.iter().zip(&self.vel) {
+            out.extend_from_slice(&[p.x, p.y, p.z, v.x, v.y, v.z]);
+        }
+    }
+
+    fn load_state(&mut self, state: &[f64]) -> std::io::Result<()> {
+        recorder::check_state_len(state, 6 * self.num_particles)?;
+        for (i, s) in state.chunks_exact(6).enumerate() {
+            self.pos[i] = Vector3::new(s[0], s[1], s[2]);
+            self.vel[i] = Vector3::new(s[3], s[4], s[5]);
+        }
+        Ok(())
+    }
+
+    fn apply_input(&mut self, event: &InputEvent) {
+        match *event {
+            InputEvent::InitGrab(p) => self.init_grab(p),
+            InputEvent::MoveGrab(p) => self.move_grab(p),
+            InputEvent::ReleaseGrab => self.release_grab(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tetra_mesh::TetraMesh;
+
+    // The tetra cube of the demo floating in the middle of the world bounds.
+    fn cube(par: SystemParameters) -> System {
+        let mesh = TetraMesh::from_obj(include_str!("../../../assets/tetra_cube.obj")).unwrap();
+        let lift = Vector3::new(0.0, 5.0, 0.0);
+        let vertices: Vec<_> = mesh.positions.iter().map(|p| p + lift).collect();
+        System::new(&vertices, &mesh.tetr_indexes, par)
+    }
+
+    // G(x) of the substep without the walls, which the cube does not touch.
+    fn incremental_potential(sys: &System, dt: f64) -> f64 {
+        let (mu, lambda) = sys.par.elasticity.lame();
+        let vol_stiffness = lambda + mu;
+        let alpha = 1.0 + mu / vol_stiffness;
+        let inertia: f64 = (0..sys.num_particles)
+            .map(|i| {
+                let m = sys.par.density * sys.vertex_volume[i];
+                0.5 * m * (sys.pos[i] - sys.inertia[i]).norm_squared() / (dt * dt)
+            })
+            .sum();
+        let elastic: f64 = (0..sys.num_elems)
+            .map(|e| {
+                let f = sys.deformation_gradient(e, &sys.pos);
+                let psi = 0.5 * mu * (f.norm_squared() - 3.0)
+                    + 0.5 * vol_stiffness * (f.determinant() - alpha).powi(2);
+                psi * sys.rest_volume[e]
+            })
+            .sum();
+        inertia + elastic
+    }
+
+    #[test]
+    fn rest_pose_is_stress_free() {
+        let sys = cube(SystemParameters::default());
+        let (mu, lambda) = sys.par.elasticity.lame();
+        for i in 0..sys.num_particles {
+            let (force, _) = sys.elastic_block(i, mu, lambda, 0.01);
+            assert!(force.norm() < 1e-9 * mu, "{i} {force}");
+        }
+        let mut sys = cube(SystemParameters {
+            gravity: Vector3::zeros(),
+            num_iterations: 2,
+            ..Default::default()
+        });
+        for _ in 0..4 {
+            sys.simulate();
+        }
+        for (p, q) in sys.pos.iter().zip(&sys.orig_pos) {
+            assert!((p - q).norm() < 1e-9);
+        }
+        assert!(sys.vol_error().abs() < 1e-12);
+    }
+
+    #[test]
+    fn newton_steps_decrease_the_energy() {
+        let mut sys = cube(SystemParameters {
+            gravity: Vector3::zeros(),
+            damping: 0.0,
+            ..Default::default()
+        });
+        // released from a stretched and sheared state
+        for p in &mut sys.pos {
+            *p = Vector3::new(1.3 * p.x + 0.2 * p.y, p.y, 0.9 * p.z);
+        }
+        sys.inertia = sys.orig_pos.clone();
+        let dt = sys.par.time_step / sys.par.num_substeps as f64;
+        let (mu, lambda) = sys.par.elasticity.lame();
+        let mut energy = incremental_potential(&sys, dt);
+        let start = energy;
+        for _ in 0..3 {
+            for i in 0..sys.num_particles {
+                sys.pos[i] = sys.solve_vertex(i, mu, lambda, dt);
+                let new_energy = incremental_potential(&sys, dt);
+                assert!(
+                    new_energy <= energy + 1e-9 * start,
+                    "{i} {new_energy} {energy}"
+                );
+                energy = new_energy;
+            }
+        }
+        assert!(energy < 0.5 * start);
+    }
+
+    #[test]
+    fn reset_releases_the_grab() {
+        let mut sys = cube(SystemParameters {
+            num_iterations: 2,
+            ..Default::default()
+        });
+        sys.init_grab(sys.pos[0]);
+        sys.move_grab(Vector3::new(0.0, 8.0, 0.0));
+        sys.simulate();
+        sys.reset();
+        assert_eq!(sys.grab_id, -1);
+        assert_eq!(sys.vol_error(), 0.0);
+        sys.simulate();
+        // the cube falls freely again
+        assert!(sys.pos.iter().all(|p| p.y < 6.0 + 1e-9));
+        assert!(sys.vel().iter().all(|v| v.y < 0.0));
+    }
+}
@@ -0,0 +1,39 @@
+use crate::systems::constraints::xpbd::neohookean::material::Elasticity;
+use nalgebra::Vector3;
+
+#[derive(Debug, Clone)]
+pub struct SystemParameters {
+    pub time_step: f64,
+    pub num_substeps: usize,
+    // sweeps over all vertex colors per substep
+    pub num_iterations: usize,
+    pub elasticity: Elasticity,
+    pub density: f64,
+    // stiffness proportional (Rayleigh) damping of the elements and walls, in seconds
+    pub damping: f64,
+    // penalty stiffness and Coulomb friction of the world_bounds walls
+    pub contact_stiffness: f64,
+    pub friction: f64,
+    pub world_bounds: (Vector3<f64>, Vector3<f64>),
+    pub gravity: Vector3<f64>,
+}
+
+impl Default for SystemParameters {
+    fn default() -> Self {
+        SystemParameters {
+            time_step: 0.1 / 60.0,
+            num_substeps: 5,
+            num_iterations: 10,
+            elasticity: Elasticity::Young {
+                youngs_modulus: 5000.0,
+                poisson_ratio: 0.3,
+            },
+            density: 1.0,
+            damping: 1e-3,
+            contact_stiffness: 1e6,
+            friction: 0.5,
+            world_bounds: (Vector3::new(-5.0, 0.0, -5.0), Vector3::new(5.0, 10.0, 5.0)),
+            gravity: Vector3::new(0.0, -100.0, 0.0),
+        }
+    }
+}
@@ -1 +1,2 @@
 pub mod neohookean;
+pub mod rigid_bodies;
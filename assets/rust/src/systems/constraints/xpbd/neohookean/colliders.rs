@@ -0,0 +1,558 @@
+use super::system::closest_on_triangle;
+use crate::utils::tetra_mesh::{
+    self, CUBE_TETS, MeshError, TetraMesh, TriangleSurface, data_lines, parse_error, parse_word,
+};
+use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
+
+// Shapes in the local frame of the collider.
+#[derive(Debug, Clone)]
+pub enum Shape {
+    // half space y <= 0
+    Plane,
+    Sphere { radius: f64 },
+    // segment from -half_height to half_height along y, inflated by radius
+    Capsule { radius: f64, half_height: f64 },
+    Box { half_extents: Vector3<f64> },
+    Sdf(SdfGrid),
+}
+
+impl Shape {
+    // Signed distance, negative inside.
+    pub fn distance(&self, p: &Vector3<f64>) -> f64 {
+        match self {
+            Shape::Plane => p.y,
+            Shape::Sphere { radius } => p.norm() - radius,
+            Shape::Capsule {
+                radius,
+                half_height,
+            } => {
+                (p - Vector3::new(0.0, p.y.clamp(-half_height, *half_height), 0.0)).norm() - radius
+            }
+            Shape::Box { half_extents } => {
+                let q = p.abs() - half_extents;
+                q.sup(&Vector3::zeros()).norm() + q.max().min(0.0)
+            }
+            Shape::Sdf(grid) => grid.distance(p),
+        }
+    }
+
+    // Outward unit normal of the closest surface point.
+    pub fn normal(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        let n = match self {
+            Shape::Plane => Vector3::y(),
+            Shape::Sphere { .. } => *p,
+            Shape::Capsule { half_height, .. } => {
+                p - Vector3::new(0.0, p.y.clamp(-half_height, *half_height), 0.0)
+            }
+            Shape::Box { half_extents } => {
+                let q = p.abs() - half_extents;
+                if q.max() > 0.0 {
+                    q.sup(&Vector3::zeros()).component_mul(&p.map(f64::signum))
+                } else {
+                    // inside, push out through the closest face
+                    let i = q.imax();
+                    let mut n = Vector3::zeros();
+                    n[i] = p[i].signum();
+                    n
+                }
+            }
+            Shape::Sdf(grid) => grid.gradient(p),
+        };
+        n.try_normalize(0.0).unwrap_or_else(Vector3::y)
+    }
+}
+
+// Signed distances sampled on a regular grid, trilinear in between.
+#[derive(Debug, Clone)]
+pub struct SdfGrid {
+    pub origin: Vector3<f64>,
+    pub spacing: f64,
+    // samples along x, y, z
+    pub dims: [usize; 3],
+    // x fastest
+    pub values: Vec<f64>,
+}
+
+impl SdfGrid {
+    // Samples the distance to a closed surface, resolution is the number of
+    // cells along the longest side of its bounding box.
+    pub fn from_surface(surface: &TriangleSurface, resolution: usize) -> Result<Self, MeshError> {
+        let TriangleSurface {
+            positions,
+            triangles,
+        } = surface;
+        let resolution = resolution.max(1);
+        if triangles.is_empty() {
+            return Err(MeshError::Empty { resolution });
+        }
+        tetra_mesh::check_closed(triangles)?;
+        let mut min = Vector3::repeat(f64::MAX);
+        let mut max = Vector3::repeat(f64::MIN);
+        for &i in triangles.iter().flatten() {
+            min = min.inf(&positions[i]);
+            max = max.sup(&positions[i]);
+        }
+        let spacing = (max - min).max() / resolution as f64;
+        if spacing <= 0.0 {
+            return Err(MeshError::Empty { resolution });
+        }
+        // two cells of padding on every side
+        let origin = min - Vector3::repeat(2.0 * spacing);
+        let dims = ((max - min) / spacing).map(|x| x.ceil() as usize + 5);
+        let mut grid = SdfGrid {
+            origin,
+            spacing,
+            dims: [dims.x, dims.y, dims.z],
+            values: Vec::with_capacity(dims.x * dims.y * dims.z),
+        };
+        for i in 0..dims.x * dims.y * dims.z {
+            let p = grid.node(i);
+            let dist = triangles
+                .iter()
+                .map(|&[a, b, c]| {
+                    let (pa, pb, pc) = (positions[a], positions[b], positions[c]);
+                    let w = closest_on_triangle(&p, &pa, &pb, &pc);
+                    (pa * w[0] + pb * w[1] + pc * w[2] - p).norm()
+                })
+                .fold(f64::MAX, f64::min);
+            let inside = tetra_mesh::winding_number(positions, triangles, &p) >= 0.5;
+            grid.values.push(if inside { -dist } else { dist });
+        }
+        Ok(grid)
+    }
+
+    fn node(&self, i: usize) -> Vector3<f64> {
+        let [nx, ny, _] = self.dims;
+        let (x, y, z) = (i % nx, (i / nx) % ny, i / (nx * ny));
+        self.origin + Vector3::new(x as f64, y as f64, z as f64) * self.spacing
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.values[x + self.dims[0] * (y + self.dims[1] * z)]
+    }
+
+    fn max_corner(&self) -> Vector3<f64> {
+        self.origin
+            + Vector3::new(
+                (self.dims[0] - 1) as f64,
+                (self.dims[1] - 1) as f64,
+                (self.dims[2] - 1) as f64,
+            ) * self.spacing
+    }
+
+    // p must be inside the grid
+    fn sample(&self, p: &Vector3<f64>) -> f64 {
+        let g = (p - self.origin) / self.spacing;
+        let mut cell = [0; 3];
+        let mut t = [0.0; 3];
+        for k in 0..3 {
+            let c = (g[k].floor().max(0.0) as usize).min(self.dims[k].saturating_sub(2));
+            cell[k] = c;
+            t[k] = (g[k] - c as f64).clamp(0.0, 1.0);
+        }
+        let mut value = 0.0;
+        for bits in 0..8 {
+            let mut w = 1.0;
+            let mut corner = cell;
+            for k in 0..3 {
+                if bits >> k & 1 == 1 {
+                    w *= t[k];
+                    corner[k] = (corner[k] + 1).min(self.dims[k] - 1);
+                } else {
+                    w *= 1.0 - t[k];
+                }
+            }
+            value += w * self.value(corner[0], corner[1], corner[2]);
+        }
+        value
+    }
+
+    fn clamp(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        p.sup(&self.origin).inf(&self.max_corner())
+    }
+
+    // Outside the grid the distance to the grid box is added.
+    pub fn distance(&self, p: &Vector3<f64>) -> f64 {
+        let q = self.clamp(p);
+        self.sample(&q) + (p - q).norm()
+    }
+
+    pub fn gradient(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        let q = self.clamp(p);
+        if q != *p {
+            return p - q;
+        }
+        let h = 0.5 * self.spacing;
+        let mut grad = Vector3::zeros();
+        for k in 0..3 {
+            let mut dp = Vector3::zeros();
+            dp[k] = h;
+            grad[k] = self.sample(&self.clamp(&(p + dp))) - self.sample(&self.clamp(&(p - dp)));
+        }
+        grad
+    }
+
+    // Zero level set by marching tetrahedra, for rendering. Vertices are not
+    // shared between triangles.
+    pub fn zero_surface(&self) -> TriangleSurface {
+        let mut positions = Vec::new();
+        let mut triangles = Vec::new();
+        let [nx, ny, nz] = self.dims;
+        for z in 0..nz.saturating_sub(1) {
+            for y in 0..ny.saturating_sub(1) {
+                for x in 0..nx.saturating_sub(1) {
+                    let corner = |bits: usize| {
+                        (x + (bits & 1)) + nx * ((y + (bits >> 1 & 1)) + ny * (z + (bits >> 2 & 1)))
+                    };
+                    for tet in CUBE_TETS {
+                        let ids = tet.map(corner);
+                        let (inside, outside): (Vec<usize>, Vec<usize>) =
+                            ids.iter().partition(|&&i| self.values[i] < 0.0);
+                        let cut = |a: usize, b: usize| {
+                            let (va, vb) = (self.values[a], self.values[b]);
+                            let t = va / (va - vb);
+                            self.node(a) * (1.0 - t) + self.node(b) * t
+                        };
+                        let mut polygon = Vec::new();
+                        match (inside.len(), outside.len()) {
+                            (1, 3) => polygon.extend(outside.iter().map(|&o| cut(inside[0], o))),
+                            (3, 1) => polygon.extend(inside.iter().map(|&i| cut(i, outside[0]))),
+                            (2, 2) => polygon.extend([
+                                cut(inside[0], outside[0]),
+                                cut(inside[0], outside[1]),
+                                cut(inside[1], outside[1]),
+                                cut(inside[1], outside[0]),
+                            ]),
+                            _ => continue,
+                        }
+                        for k in 1..polygon.len() - 1 {
+                            let (a, mut b, mut c) = (polygon[0], polygon[k], polygon[k + 1]);
+                            let center = (a + b + c) / 3.0;
+                            if (b - a).cross(&(c - a)).dot(&self.gradient(&center)) < 0.0 {
+                                std::mem::swap(&mut b, &mut c);
+                            }
+                            let first = positions.len();
+                            positions.extend([a, b, c]);
+                            triangles.push([first, first + 1, first + 2]);
+                        }
+                    }
+                }
+            }
+        }
+        TriangleSurface {
+            positions,
+            triangles,
+        }
+    }
+}
+
+// A static collider, or a kinematic one moving with a constant velocity and
+// spinning around its origin.
+#[derive(Debug, Clone)]
+pub struct Collider {
+    pub shape: Shape,
+    pub transform: Isometry3<f64>,
+    pub velocity: Vector3<f64>,
+    pub angular_velocity: Vector3<f64>,
+}
+
+impl Collider {
+    pub fn new(shape: Shape, transform: Isometry3<f64>) -> Self {
+        Collider {
+            shape,
+            transform,
+            velocity: Vector3::zeros(),
+            angular_velocity: Vector3::zeros(),
+        }
+    }
+
+    // Half space below the plane through point with the given normal.
+    pub fn plane(point: Vector3<f64>, normal: Vector3<f64>) -> Self {
+        let rotation =
+            UnitQuaternion::rotation_between(&Vector3::y(), &normal).unwrap_or_else(|| {
+                UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f64::consts::PI)
+            });
+        Self::new(
+            Shape::Plane,
+            Isometry3::from_parts(Translation3::from(point), rotation),
+        )
+    }
+
+    pub fn kinematic(mut self, velocity: Vector3<f64>, angular_velocity: Vector3<f64>) -> Self {
+        self.velocity = velocity;
+        self.angular_velocity = angular_velocity;
+        self
+    }
+
+    pub fn is_kinematic(&self) -> bool {
+        self.velocity != Vector3::zeros() || self.angular_velocity != Vector3::zeros()
+    }
+
+    pub fn distance(&self, p: &Vector3<f64>) -> f64 {
+        let local = self.transform.inverse_transform_point(&(*p).into()).coords;
+        self.shape.distance(&local)
+    }
+
+    // Penetration depth and world normal if p is inside.
+    pub fn contact(&self, p: &Vector3<f64>) -> Option<(f64, Vector3<f64>)> {
+        let local = self.transform.inverse_transform_point(&(*p).into()).coords;
+        let d = self.shape.distance(&local);
+        if d >= 0.0 {
+            return None;
+        }
+        Some((-d, self.transform.rotation * self.shape.normal(&local)))
+    }
+
+    pub fn velocity_at(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        self.velocity
+            + self
+                .angular_velocity
+                .cross(&(p - self.transform.translation.vector))
+    }
+
+    pub fn advance(&mut self, dt: f64) {
+        self.transform.translation.vector += self.velocity * dt;
+        self.transform.rotation =
+            UnitQuaternion::from_scaled_axis(self.angular_velocity * dt) * self.transform.rotation;
+    }
+}
+
+fn parse_vector(
+    words: &[&str],
+    i: usize,
+    line: usize,
+    what: &str,
+) -> Result<Vector3<f64>, MeshError> {
+    Ok(Vector3::new(
+        parse_word(words, i, line, what)?,
+        parse_word(words, i + 1, line, what)?,
+        parse_word(words, i + 2, line, what)?,
+    ))
+}
+
+// Loads colliders from a scene file, one collider per line:
+//   plane nx ny nz | sphere r | capsule r half_height | box hx hy hz | sdf surface.obj resolution
+// followed by any of
+//   pos x y z | rot x y z (axis-angle in degrees) | vel x y z | spin x y z (rad/s)
+// vel and spin make the collider kinematic. The plane normal is rotated by rot.
+pub async fn load_colliders(
+    path_to_assets: &str,
+    file_name: &str,
+) -> Result<Vec<Collider>, MeshError> {
+    let text = tetra_mesh::load_text(path_to_assets, file_name).await?;
+    let mut colliders = Vec::new();
+    for (line, words) in data_lines(&text) {
+        let (mut collider, placement) = match words[0] {
+            "plane" => (
+                Collider::plane(Vector3::zeros(), parse_vector(&words, 1, line, "normal")?),
+                4,
+            ),
+            "sphere" => (
+                Collider::new(
+                    Shape::Sphere {
+                        radius: parse_word(&words, 1, line, "radius")?,
+                    },
+                    Isometry3::identity(),
+                ),
+                2,
+            ),
+            "capsule" => (
+                Collider::new(
+                    Shape::Capsule {
+                        radius: parse_word(&words, 1, line, "radius")?,
+                        half_height: parse_word(&words, 2, line, "half height")?,
+                    },
+                    Isometry3::identity(),
+                ),
+                3,
+            ),
+            "box" => (
+                Collider::new(
+                    Shape::Box {
+                        half_extents: parse_vector(&words, 1, line, "half extent")?,
+                    },
+                    Isometry3::identity(),
+                ),
+                4,
+            ),
+            "sdf" => {
+                let surface_file = words
+                    .get(1)
+                    .ok_or_else(|| parse_error(line, "missing surface file"))?;
+                let resolution = parse_word(&words, 2, line, "resolution")?;
+                let text = tetra_mesh::load_text(path_to_assets, surface_file).await?;
+                let surface = TetraMesh::surface_from_obj(&text)?;
+                (
+                    Collider::new(
+                        Shape::Sdf(SdfGrid::from_surface(&surface, resolution)?),
+                        Isometry3::identity(),
+                    ),
+                    3,
+                )
+            }
+            other => {
+                return Err(parse_error(line, format!("unknown collider '{}'", other)));
+            }
+        };
+        let mut i = placement;
+        while i < words.len() {
+            if !["pos", "rot", "vel", "spin"].contains(&words[i]) {
+                return Err(parse_error(line, format!("unknown keyword '{}'", words[i])));
+            }
+            let v = parse_vector(&words, i + 1, line, words[i])?;
+            match words[i] {
+                "pos" => collider.transform.translation.vector = v,
+                "rot" => {
+                    let rotation = UnitQuaternion::from_scaled_axis(v.map(f64::to_radians));
+                    collider.transform.rotation = rotation * collider.transform.rotation;
+                }
+                "vel" => collider.velocity = v,
+                _ => collider.angular_velocity = v,
+            }
+            i += 4;
+        }
+        colliders.push(collider);
+    }
+    Ok(colliders)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE: &str = "\
+v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\nv -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1
+f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 4 8 7 3\nf 1 5 8 4\nf 2 3 7 6
+";
+
+    fn assert_close(a: Vector3<f64>, b: Vector3<f64>, tolerance: f64) {
+        assert!((a - b).norm() < tolerance, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn shape_distances_and_normals() {
+        let p = Vector3::new(0.3, -0.5, 0.2);
+        assert_eq!(Shape::Plane.distance(&p), -0.5);
+        assert_eq!(Shape::Plane.normal(&p), Vector3::y());
+
+        let sphere = Shape::Sphere { radius: 2.0 };
+        assert!((sphere.distance(&Vector3::new(0.0, 3.0, 4.0)) - 3.0).abs() < 1e-12);
+        assert!((sphere.distance(&Vector3::zeros()) + 2.0).abs() < 1e-12);
+        assert_close(
+            sphere.normal(&Vector3::new(0.0, 3.0, 4.0)),
+            Vector3::new(0.0, 0.6, 0.8),
+            1e-12,
+        );
+
+        let capsule = Shape::Capsule {
+            radius: 0.5,
+            half_height: 1.0,
+        };
+        // beside the segment and above its end
+        assert!((capsule.distance(&Vector3::new(2.0, 0.7, 0.0)) - 1.5).abs() < 1e-12);
+        assert!((capsule.distance(&Vector3::new(0.0, 3.0, 0.0)) - 1.5).abs() < 1e-12);
+        assert_close(
+            capsule.normal(&Vector3::new(0.0, 0.7, -2.0)),
+            -Vector3::z(),
+            1e-12,
+        );
+        assert_close(
+            capsule.normal(&Vector3::new(0.0, 3.0, 0.0)),
+            Vector3::y(),
+            1e-12,
+        );
+
+        let cuboid = Shape::Box {
+            half_extents: Vector3::new(1.0, 2.0, 3.0),
+        };
+        // off a face, off an edge and inside next to the x face
+        assert!((cuboid.distance(&Vector3::new(0.0, 0.0, 4.0)) - 1.0).abs() < 1e-12);
+        assert!((cuboid.distance(&Vector3::new(4.0, 6.0, 0.0)) - 5.0).abs() < 1e-12);
+        assert!((cuboid.distance(&Vector3::new(0.8, 0.0, 0.0)) + 0.2).abs() < 1e-12);
+        assert_close(
+            cuboid.normal(&Vector3::new(4.0, 6.0, 0.0)),
+            Vector3::new(0.6, 0.8, 0.0),
+            1e-12,
+        );
+        assert_close(
+            cuboid.normal(&Vector3::new(-0.8, 0.0, 0.0)),
+            -Vector3::x(),
+            1e-12,
+        );
+    }
+
+    #[test]
+    fn sdf_of_a_cube() {
+        let surface = TetraMesh::surface_from_obj(CUBE).unwrap();
+        let sdf = Shape::Sdf(SdfGrid::from_surface(&surface, 8).unwrap());
+        // exact at the nodes, so at the center and on the faces
+        assert!((sdf.distance(&Vector3::zeros()) + 1.0).abs() < 1e-9);
+        assert!(sdf.distance(&Vector3::new(1.0, 0.0, 0.0)).abs() < 1e-9);
+        assert!((sdf.distance(&Vector3::new(0.0, 1.5, 0.0)) - 0.5).abs() < 1e-9);
+        // outside the grid the distance to its box is added
+        assert!((sdf.distance(&Vector3::new(0.0, 0.0, 10.0)) - 9.0).abs() < 1e-9);
+        assert_close(
+            sdf.normal(&Vector3::new(1.25, 0.1, 0.2)),
+            Vector3::x(),
+            1e-9,
+        );
+        assert_close(
+            sdf.normal(&Vector3::new(0.2, -0.75, 0.1)),
+            -Vector3::y(),
+            1e-9,
+        );
+    }
+
+    #[test]
+    fn placed_colliders() {
+        let plane = Collider::plane(Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!((plane.distance(&Vector3::new(5.0, 2.0, 0.0)) - 1.0).abs() < 1e-12);
+        let (depth, normal) = plane.contact(&Vector3::new(0.0, 0.0, 1.5)).unwrap();
+        assert!((depth - 0.5).abs() < 1e-12);
+        assert_close(normal, -Vector3::z(), 1e-12);
+        assert!(plane.contact(&Vector3::zeros()).is_none());
+    }
+
+    fn scene_dir(name: &str, text: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("colliders_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("test.scene"), text).unwrap();
+        dir.to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn loads_the_scene_asset() {
+        let assets = concat!(env!("CARGO_MANIFEST_DIR"), "/src/assets");
+        let colliders = load_colliders(assets, "colliders.scene").await.unwrap();
+        assert_eq!(colliders.len(), 3);
+        assert!(matches!(colliders[0].shape, Shape::Sphere { radius } if radius == 1.0));
+        assert_close(
+            colliders[0].transform.translation.vector,
+            Vector3::new(3.0, 1.0, -2.0),
+            1e-12,
+        );
+        assert!(matches!(colliders[1].shape, Shape::Box { .. }));
+        assert!(!colliders[1].is_kinematic());
+        assert!(matches!(colliders[2].shape, Shape::Capsule { .. }));
+        assert!(colliders[2].is_kinematic());
+    }
+
+    #[tokio::test]
+    async fn reports_the_line_of_malformed_input() {
+        for (name, text, expected) in [
+            ("number", "# comment\nsphere 1\nbox 1 x 1\n", 3),
+            ("shape", "sphere 1\n\ncone 1 2\n", 3),
+            ("keyword", "sphere 1 pos 0 1 0 turn 0 0 0\n", 1),
+            ("missing", "plane 0 1 0\ncapsule 0.5\n", 2),
+        ] {
+            let dir = scene_dir(name, text);
+            let result = load_colliders(&dir, "test.scene").await;
+            std::fs::remove_dir_all(&dir).unwrap();
+            match result {
+                Err(MeshError::Parse { line, .. }) => assert_eq!(line, expected, "{}", name),
+                Err(e) => panic!("{}: expected a parse error, got {}", name, e),
+                Ok(_) => panic!("{}: expected a parse error", name),
+            }
+        }
+    }
+}
@@ -1,10 +1,13 @@
+use super::colliders;
+use super::material::{Elasticity, MaterialModel};
 use super::system::System;
-use super::visualizer::Visualizer;
-use crate::utils::{create_window, tetra_mesh, ui, ui::BaseState, base_url};
+use super::system_parameters::{BodyParameters, SolverMode, SystemParameters};
+use super::visualizer::{RenderMode, Visualizer};
+use crate::utils::diagnostics::Diagnostics;
+use crate::utils::recorder::{InputEvent, Recorder};
+use crate::utils::{base_url, console, create_window, tetra_mesh, ui, ui::BaseState};
 use three_d::*;
 
-#[cfg(target_arch = "wasm32")]
-use web_sys::console;
 ///
 /// A control that makes the camera orbit around a target.
 ///
@@ -31,6 +34,7 @@ impl OrbitControl {
     }
 
     /// Handles the events. Must be called each frame.
+    #[allow(clippy::collapsible_if, clippy::collapsible_match)]
     pub fn handle_events(&mut self, camera: &mut Camera, events: &mut [Event]) -> bool {
         let mut change = false;
         for event in events.iter_mut() {
@@ -200,17 +204,75 @@ pub fn custom_ray_intersect(
 
 struct State {
     base: BaseState,
-    compliance: f64,
+    // material of the selected body, also used for spawned copies
+    body: usize,
+    num_bodies: usize,
+    material: MaterialModel,
+    elasticity: Elasticity,
+    density: f64,
+    spawn: bool,
+    // index into the spawnable meshes
+    spawn_mesh: usize,
+    mesh_names: Vec<&'static str>,
+    collisions: bool,
+    static_friction: f64,
+    dynamic_friction: f64,
+    restitution: f64,
+    render_mode: RenderMode,
+    solver: SolverMode,
+    jacobi_relaxation: f64,
 }
 impl State {
     pub fn new() -> Self {
         State {
-            base: BaseState::new(),
-            compliance: 0.001,
+            base: BaseState {
+                diagnostics: Diagnostics::new(&["volume error"]),
+                ..BaseState::new()
+            },
+            body: 0,
+            num_bodies: 1,
+            material: MaterialModel::StableNeoHookean,
+            elasticity: Elasticity::Young {
+                youngs_modulus: 5000.0,
+                poisson_ratio: 0.3,
+            },
+            density: 1.0,
+            spawn: false,
+            spawn_mesh: 0,
+            mesh_names: Vec::new(),
+            collisions: true,
+            static_friction: 0.6,
+            dynamic_friction: 0.4,
+            restitution: 0.0,
+            render_mode: RenderMode::Surface,
+            solver: SolverMode::GaussSeidel,
+            jacobi_relaxation: 1.5,
         }
     }
 }
 
+// Places a copy of the mesh above everything in the scene, rotated a bit more for every body.
+fn spawn_transform(sys: &System, tetra_mesh: &tetra_mesh::TetraMesh) -> nalgebra::Isometry3<f64> {
+    let n = sys.bodies().len() as f64;
+    let rotation = nalgebra::UnitQuaternion::from_axis_angle(
+        &nalgebra::Unit::new_normalize(nalgebra::Vector3::new(1.0, 0.0, 1.0)),
+        0.4 * n,
+    );
+    let (mut min_y, mut max_y) = (f64::MAX, f64::MIN);
+    for p in &tetra_mesh.positions {
+        let y = (rotation * p).y;
+        min_y = min_y.min(y);
+        max_y = max_y.max(y);
+    }
+    let top = sys
+        .pos
+        .iter()
+        .map(|p| p.y)
+        .fold(sys.par.world_bounds.0.y, f64::max);
+    let y = (top + 0.5 - min_y).min(sys.par.world_bounds.1.y - max_y);
+    nalgebra::Isometry3::from_parts(nalgebra::Translation3::new(0.0, y, 0.0), rotation)
+}
+
 fn gui_logic(gui: &mut three_d::GUI, frame_input: &mut FrameInput, state: &mut State) {
     ui::gui_logic(
         gui,
@@ -218,21 +280,146 @@ fn gui_logic(gui: &mut three_d::GUI, frame_input: &mut FrameInput, state: &mut S
         &mut state.base,
         |ui, _base_state| {
             ui.horizontal(|ui| {
-                ui.add(three_d::egui::Slider::new(
-                    &mut state.compliance,
-                    0.0..=0.01,
-                ));
+                // the moduli are converted when switching between the pairs
+                let mut lame_input = matches!(state.elasticity, Elasticity::Lame { .. });
+                if ui
+                    .checkbox(
+                        &mut lame_input,
+                        three_d::egui::RichText::new("μ, λ").color(ui::TEXT_COLOR),
+                    )
+                    .changed()
+                {
+                    state.elasticity = if lame_input {
+                        state.elasticity.to_lame()
+                    } else {
+                        state.elasticity.to_young()
+                    };
+                }
+                let sliders = match &mut state.elasticity {
+                    Elasticity::Young {
+                        youngs_modulus,
+                        poisson_ratio,
+                    } => [
+                        (
+                            three_d::egui::Slider::new(youngs_modulus, 500.0..=100000.0)
+                                .logarithmic(true),
+                            "E",
+                        ),
+                        (three_d::egui::Slider::new(poisson_ratio, 0.0..=0.49), "ν"),
+                    ],
+                    Elasticity::Lame { mu, lambda } => [
+                        (
+                            three_d::egui::Slider::new(mu, 200.0..=50000.0).logarithmic(true),
+                            "μ",
+                        ),
+                        (
+                            three_d::egui::Slider::new(lambda, 0.0..=1000000.0).logarithmic(true),
+                            "λ",
+                        ),
+                    ],
+                };
+                for (slider, name) in sliders {
+                    ui.add(slider);
+                    ui.label(
+                        three_d::egui::RichText::new(name)
+                            .strong()
+                            .color(ui::TEXT_COLOR),
+                    );
+                }
+                ui.add(three_d::egui::Slider::new(&mut state.density, 0.1..=10.0));
                 ui.label(
-                    three_d::egui::RichText::new("compliance")
+                    three_d::egui::RichText::new("density")
                         .strong()
                         .color(ui::TEXT_COLOR),
                 );
             });
         },
-        |_ui, _base_state| {},
+        |ui, _base_state| {
+            ui.horizontal(|ui| {
+                ui.add(
+                    three_d::egui::DragValue::new(&mut state.body).range(0..=state.num_bodies - 1),
+                );
+                ui.label(
+                    three_d::egui::RichText::new("body")
+                        .strong()
+                        .color(ui::TEXT_COLOR),
+                );
+                let spawn_b = three_d::egui::Button::new(
+                    three_d::egui::RichText::new("Spawn")
+                        .color(ui::TEXT_COLOR)
+                        .strong(),
+                )
+                .fill(ui::BUTTON_BACKGROUND);
+                if ui.add(spawn_b).clicked() {
+                    state.spawn = true;
+                }
+                for (i, name) in state.mesh_names.iter().enumerate() {
+                    ui.radio_value(
+                        &mut state.spawn_mesh,
+                        i,
+                        three_d::egui::RichText::new(*name).color(ui::TEXT_COLOR),
+                    );
+                }
+                ui.checkbox(
+                    &mut state.collisions,
+                    three_d::egui::RichText::new("collisions").color(ui::TEXT_COLOR),
+                );
+                for mode in RenderMode::ALL {
+                    ui.radio_value(
+                        &mut state.render_mode,
+                        mode,
+                        three_d::egui::RichText::new(mode.name()).color(ui::TEXT_COLOR),
+                    );
+                }
+            });
+            ui.horizontal(|ui| {
+                for material in MaterialModel::ALL {
+                    ui.radio_value(
+                        &mut state.material,
+                        material,
+                        three_d::egui::RichText::new(material.name()).color(ui::TEXT_COLOR),
+                    );
+                }
+            });
+            ui.horizontal(|ui| {
+                for solver in SolverMode::ALL {
+                    ui.radio_value(
+                        &mut state.solver,
+                        solver,
+                        three_d::egui::RichText::new(solver.name()).color(ui::TEXT_COLOR),
+                    );
+                }
+                if state.solver == SolverMode::Jacobi {
+                    ui.add(three_d::egui::Slider::new(
+                        &mut state.jacobi_relaxation,
+                        0.1..=2.0,
+                    ));
+                    ui.label(
+                        three_d::egui::RichText::new("relaxation")
+                            .strong()
+                            .color(ui::TEXT_COLOR),
+                    );
+                }
+            });
+            ui.horizontal(|ui| {
+                for (value, name) in [
+                    (&mut state.static_friction, "static friction"),
+                    (&mut state.dynamic_friction, "dynamic friction"),
+                    (&mut state.restitution, "restitution"),
+                ] {
+                    ui.add(three_d::egui::Slider::new(value, 0.0..=1.0));
+                    ui.label(
+                        three_d::egui::RichText::new(name)
+                            .strong()
+                            .color(ui::TEXT_COLOR),
+                    );
+                }
+            });
+        },
     );
 }
 
+#[allow(clippy::collapsible_if)]
 pub async fn run(_canvas_id: &str) {
     #[cfg(not(target_arch = "wasm32"))]
     let window = create_window::create_window();
@@ -243,23 +430,67 @@ pub async fn run(_canvas_id: &str) {
     let path_to_assets = base_url::get_base_url();
     let path_to_obj = "tetra_cube.obj";
     #[cfg(target_arch = "wasm32")]
-{
-    console::log_1(&format!("path_to_assets: {:?}", path_to_assets).into());
-    let path = format!("{}/{}", path_to_assets, path_to_obj);
-    console::log_1(&format!("path: {:?}", path).into());
-}
+    {
+        console::log(&format!("path_to_assets: {:?}", path_to_assets));
+        let path = format!("{}/{}", path_to_assets, path_to_obj);
+        console::log(&format!("path: {:?}", path));
+    }
 
-    let tetra_mesh = tetra_mesh::TetraMesh::new(&path_to_assets, path_to_obj).await;
+    let tetra_mesh = match tetra_mesh::TetraMesh::new(&path_to_assets, path_to_obj).await {
+        Ok(tetra_mesh) => tetra_mesh,
+        Err(e) => {
+            console::log(&format!("{}: {}", path_to_obj, e));
+            return;
+        }
+    };
+
+    // the sphere is a closed surface filled with tetrahedra at load time
+    let mut meshes = vec![("cube", tetra_mesh)];
+    let path_to_surface = "sphere.obj";
+    match tetra_mesh::TetraMesh::from_surface(&path_to_assets, path_to_surface, 8).await {
+        Ok(sphere) => meshes.push(("sphere", sphere)),
+        Err(e) => console::log(&format!("{}: {}", path_to_surface, e)),
+    }
+    let tetra_mesh = &meshes[0].1;
+
+    // static and kinematic obstacles, the scene still runs without them
+    let path_to_scene = "colliders.scene";
+    let colliders = match colliders::load_colliders(&path_to_assets, path_to_scene).await {
+        Ok(colliders) => colliders,
+        Err(e) => {
+            console::log(&format!("{}: {}", path_to_scene, e));
+            Vec::new()
+        }
+    };
 
     let target = three_d::vec3(0.0, 3.0, 0.0);
     let scene_radius = 20.0;
 
     let mut control = OrbitControl::new(target, 0.1 * scene_radius, 100.0 * scene_radius, 0.005);
-    let mut sys = System::new(&tetra_mesh.positions, &tetra_mesh.tetr_indexes);
+    // the first body starts above the ground, flattening it would tangle the surface
+    let mut sys = System::empty(SystemParameters::default());
+    let body_par = BodyParameters {
+        transform: spawn_transform(&sys, tetra_mesh),
+        ..Default::default()
+    };
+    sys.add_body(&tetra_mesh.positions, &tetra_mesh.tetr_indexes, body_par);
+    for collider in colliders {
+        sys.add_collider(collider);
+    }
     let mut state = State::new();
+    state.mesh_names = meshes.iter().map(|(name, _)| *name).collect();
     let mut gui = three_d::GUI::new(&context);
     let mut fps_counter = ui::FPSCounter::new();
-    let mut vis = Visualizer::new(&window, &sys, &tetra_mesh, &target, scene_radius);
+    let mut recorder = Recorder::new();
+    let mut vis = Visualizer::new(
+        &window,
+        &sys.pos,
+        &sys.colliders,
+        tetra_mesh,
+        &target,
+        scene_radius,
+    );
+    let mut selected_body = 0;
     let mut picked = false;
     let mut intersection_result = CustomIntersectionResult {
         position: vec3(0.0, 0.0, 0.0),
@@ -269,6 +500,7 @@ pub async fn run(_canvas_id: &str) {
         instance_id: 0,
     };
     // console::log_1(&format!("Here {}", 2).into());
+    let mut sim_time = 0.0;
     window.render_loop(move |mut frame_input| {
         gui_logic(&mut gui, &mut frame_input, &mut state);
 
@@ -279,8 +511,12 @@ pub async fn run(_canvas_id: &str) {
             } = *event
             {
                 if button == MouseButton::Left {
-                    if let Some(pick) = custom_pick(&context, &vis.camera, position, &[&vis.edges])
-                    {
+                    let pick = if vis.mode == RenderMode::Wireframe {
+                        custom_pick(&context, &vis.camera, position, [&vis.edges])
+                    } else {
+                        custom_pick(&context, &vis.camera, position, [&vis.surface])
+                    };
+                    if let Some(pick) = pick {
                         picked = true;
                         intersection_result = pick;
                         let pos = nalgebra::Vector3::new(
@@ -291,6 +527,8 @@ pub async fn run(_canvas_id: &str) {
 
                         sys.init_grab(pos);
                         sys.move_grab(pos);
+                        recorder.record_input(InputEvent::InitGrab(pos));
+                        recorder.record_input(InputEvent::MoveGrab(pos));
                     }
                 }
             }
@@ -299,17 +537,20 @@ pub async fn run(_canvas_id: &str) {
                     let mut pos_new = vis.camera.position_at_pixel(position);
                     let dir_new = vis.camera.view_direction_at_pixel(position);
                     pos_new += dir_new * intersection_result.depth;
-                    sys.move_grab(nalgebra::Vector3::new(
+                    let pos = nalgebra::Vector3::new(
                         pos_new.x as f64,
                         pos_new.y as f64,
                         pos_new.z as f64,
-                    ));
+                    );
+                    sys.move_grab(pos);
+                    recorder.record_input(InputEvent::MoveGrab(pos));
                 }
             }
             if let Event::MouseRelease { button, .. } = *event {
                 if button == MouseButton::Left {
                     picked = false;
                     sys.release_grab();
+                    recorder.record_input(InputEvent::ReleaseGrab);
                 }
             }
         }
@@ -320,17 +561,65 @@ pub async fn run(_canvas_id: &str) {
         if state.base.reset {
             sys.reset();
             state.base.reset = false;
+            state.base.diagnostics.clear();
+            sim_time = 0.0;
         }
 
+        if state.spawn {
+            state.spawn = false;
+            let tetra_mesh = &meshes[state.spawn_mesh].1;
+            let body_par = BodyParameters {
+                material: state.material,
+                elasticity: state.elasticity,
+                density: state.density,
+                transform: spawn_transform(&sys, tetra_mesh),
+            };
+            let body_nr = sys.add_body(&tetra_mesh.positions, &tetra_mesh.tetr_indexes, body_par);
+            vis.add_body(
+                &sys.pos,
+                &sys.colliders,
+                tetra_mesh,
+                sys.bodies()[body_nr].first_particle,
+            );
+            state.num_bodies = sys.bodies().len();
+            state.body = body_nr;
+            selected_body = body_nr;
+        }
+        if state.body != selected_body {
+            // show the material of the newly selected body
+            selected_body = state.body;
+            let body_par = &sys.bodies()[selected_body].par;
+            state.material = body_par.material;
+            state.elasticity = body_par.elasticity;
+            state.density = body_par.density;
+        }
+        let mut body_par = sys.bodies()[selected_body].par.clone();
+        body_par.material = state.material;
+        body_par.elasticity = state.elasticity;
+        body_par.density = state.density;
+        sys.set_body_parameters(selected_body, body_par);
+
+        sys.par.collisions = state.collisions;
+        sys.par.solver = state.solver;
+        sys.par.jacobi_relaxation = state.jacobi_relaxation;
+        sys.par.static_friction = state.static_friction;
+        sys.par.dynamic_friction = state.dynamic_friction;
+        sys.par.restitution = state.restitution;
+
+        let mode_changed = vis.mode != state.render_mode;
+        vis.mode = state.render_mode;
+        if recorder.update(&mut sys, &mut state.base) || mode_changed {
+            vis.update(&sys.pos, &sys.colliders);
+        }
+        if !state.base.stop && !state.base.replay.replaying {
+            sim_time += sys.par.time_step;
+            state.base.diagnostics.push(sim_time, &[sys.vol_error()]);
+        }
         if !state.base.stop {
-            sys.par.vol_compliance = state.compliance;
-            sys.par.dev_compliance = state.compliance;
-            sys.simulate();
             state.base.fps = fps_counter.update(&frame_input);
-            vis.update(&sys, &tetra_mesh);
         }
 
-        vis.render(&mut frame_input, &sys);
+        vis.render(&frame_input);
         frame_input.screen().write(|| gui.render()).unwrap();
 
         FrameOutput::default()
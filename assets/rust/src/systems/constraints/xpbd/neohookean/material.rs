@@ -0,0 +1,353 @@
+use nalgebra::Matrix3;
+
+// One energy term Ψ = ½ k C(F)² of a material with k = dev μ + vol λ, solved as an
+// XPBD constraint with compliance 1 / (k V) for an element of rest volume V.
+pub struct Term {
+    pub c: f64,
+    // dC/dF
+    pub grad: Matrix3<f64>,
+    pub dev: f64,
+    pub vol: f64,
+}
+
+pub trait Material: Sync {
+    fn name(&self) -> &'static str;
+    fn num_terms(&self) -> usize;
+    // term i at the deformation gradient f, for the Lamé parameters mu and lambda
+    fn term(&self, i: usize, f: &Matrix3<f64>, mu: f64, lambda: f64) -> Term;
+}
+
+// Elastic moduli as entered by the user, either pair describes the same material.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Elasticity {
+    Young {
+        youngs_modulus: f64,
+        poisson_ratio: f64,
+    },
+    Lame {
+        mu: f64,
+        lambda: f64,
+    },
+}
+
+impl Elasticity {
+    // (mu, lambda)
+    pub fn lame(&self) -> (f64, f64) {
+        match *self {
+            Elasticity::Young {
+                youngs_modulus: e,
+                poisson_ratio: nu,
+            } => (
+                e / (2.0 * (1.0 + nu)),
+                e * nu / ((1.0 + nu) * (1.0 - 2.0 * nu)),
+            ),
+            Elasticity::Lame { mu, lambda } => (mu, lambda),
+        }
+    }
+
+    // (youngs_modulus, poisson_ratio)
+    pub fn young(&self) -> (f64, f64) {
+        match *self {
+            Elasticity::Young {
+                youngs_modulus,
+                poisson_ratio,
+            } => (youngs_modulus, poisson_ratio),
+            Elasticity::Lame { mu, lambda } => (
+                mu * (3.0 * lambda + 2.0 * mu) / (lambda + mu),
+                lambda / (2.0 * (lambda + mu)),
+            ),
+        }
+    }
+
+    pub fn to_young(self) -> Self {
+        let (youngs_modulus, poisson_ratio) = self.young();
+        Elasticity::Young {
+            youngs_modulus,
+            poisson_ratio,
+        }
+    }
+
+    pub fn to_lame(self) -> Self {
+        let (mu, lambda) = self.lame();
+        Elasticity::Lame { mu, lambda }
+    }
+}
+
+// d(det F)/dF
+pub(crate) fn cofactor(f: &Matrix3<f64>) -> Matrix3<f64> {
+    let (f0, f1, f2) = (f.column(0), f.column(1), f.column(2));
+    Matrix3::from_columns(&[f1.cross(&f2), f2.cross(&f0), f0.cross(&f1)])
+}
+
+// C = ||F||, Ψ = k/2 tr(FᵀF) with k = dev μ
+fn sqrt_first_invariant(f: &Matrix3<f64>, dev: f64) -> Term {
+    let c = f.norm();
+    Term {
+        c,
+        grad: if c > 0.0 { f / c } else { Matrix3::zeros() },
+        dev,
+        vol: 0.0,
+    }
+}
+
+// C = det F - γ with k = dev μ + vol λ, the offset γ cancels the rest stress of the
+// other terms
+fn volume(f: &Matrix3<f64>, rest_stress: f64, dev: f64, vol: f64, mu: f64, lambda: f64) -> Term {
+    Term {
+        c: f.determinant() - 1.0 - rest_stress / (dev * mu + vol * lambda),
+        grad: cofactor(f),
+        dev,
+        vol,
+    }
+}
+
+// Macklin and Müller, A Constraint-based Formulation of Stable Neo-Hookean Materials.
+// The volume stiffness is λ + μ (Smith et al.) so small strains match linear elasticity.
+pub struct StableNeoHookean;
+
+impl Material for StableNeoHookean {
+    fn name(&self) -> &'static str {
+        "stable Neo-Hookean"
+    }
+
+    fn num_terms(&self) -> usize {
+        2
+    }
+
+    fn term(&self, i: usize, f: &Matrix3<f64>, mu: f64, lambda: f64) -> Term {
+        match i {
+            0 => sqrt_first_invariant(f, 1.0),
+            _ => volume(f, mu, 1.0, 1.0, mu, lambda),
+        }
+    }
+}
+
+// Ψ = μ ||E||² + λ/2 tr(E)², E = (FᵀF - I) / 2. It barely resists strong compression,
+// soft bodies under load collapse and invert.
+pub struct StVenantKirchhoff;
+
+impl Material for StVenantKirchhoff {
+    fn name(&self) -> &'static str {
+        "St. Venant-Kirchhoff"
+    }
+
+    fn num_terms(&self) -> usize {
+        2
+    }
+
+    fn term(&self, i: usize, f: &Matrix3<f64>, _mu: f64, _lambda: f64) -> Term {
+        let e = (f.transpose() * f - Matrix3::identity()) * 0.5;
+        match i {
+            0 => {
+                let c = e.norm();
+                Term {
+                    c,
+                    grad: if c > 0.0 { f * e / c } else { Matrix3::zeros() },
+                    dev: 2.0,
+                    vol: 0.0,
+                }
+            }
+            _ => Term {
+                c: e.trace(),
+                grad: *f,
+                dev: 0.0,
+                vol: 1.0,
+            },
+        }
+    }
+}
+
+// Linear elasticity in the frame of the rotation R of the polar decomposition F = RS,
+// Ψ = μ ||F - R||² + λ/2 tr(RᵀF - I)²
+pub struct CorotatedLinear;
+
+impl CorotatedLinear {
+    fn rotation(f: &Matrix3<f64>) -> Matrix3<f64> {
+        let svd = f.svd(true, true);
+        let (mut u, v_t) = (svd.u.unwrap(), svd.v_t.unwrap());
+        // keep R a rotation for inverted elements
+        if (u * v_t).determinant() < 0.0 {
+            let mut last = u.column_mut(2);
+            last *= -1.0;
+        }
+        u * v_t
+    }
+}
+
+impl Material for CorotatedLinear {
+    fn name(&self) -> &'static str {
+        "corotated linear"
+    }
+
+    fn num_terms(&self) -> usize {
+        2
+    }
+
+    fn term(&self, i: usize, f: &Matrix3<f64>, _mu: f64, _lambda: f64) -> Term {
+        let r = Self::rotation(f);
+        match i {
+            0 => {
+                let c = (f - r).norm();
+                Term {
+                    c,
+                    grad: if c > 0.0 {
+                        (f - r) / c
+                    } else {
+                        Matrix3::zeros()
+                    },
+                    dev: 2.0,
+                    vol: 0.0,
+                }
+            }
+            _ => Term {
+                c: (r.transpose() * f).trace() - 3.0,
+                grad: r,
+                dev: 0.0,
+                vol: 1.0,
+            },
+        }
+    }
+}
+
+// Compressible Mooney-Rivlin, Ψ = C10 I1 + C01 I2 + λ/2 (det F - γ)² with
+// C10 = C01 = μ/4, so the small strain shear modulus is μ. As for Neo-Hookean the
+// volume stiffness is raised, by 2 C10, to match linear elasticity.
+pub struct MooneyRivlin;
+
+impl Material for MooneyRivlin {
+    fn name(&self) -> &'static str {
+        "Mooney-Rivlin"
+    }
+
+    fn num_terms(&self) -> usize {
+        3
+    }
+
+    fn term(&self, i: usize, f: &Matrix3<f64>, mu: f64, lambda: f64) -> Term {
+        // C10 = C01 = μ/4
+        match i {
+            0 => sqrt_first_invariant(f, 0.5),
+            1 => {
+                let b = f * f.transpose();
+                let i1 = b.trace();
+                let i2 = 0.5 * (i1 * i1 - (b * b).trace());
+                let c = i2.max(0.0).sqrt();
+                Term {
+                    c,
+                    // dI2/dF = 2 (I1 F - F FᵀF)
+                    grad: if c > 0.0 {
+                        (f * i1 - b * f) / c
+                    } else {
+                        Matrix3::zeros()
+                    },
+                    dev: 0.5,
+                    vol: 0.0,
+                }
+            }
+            // rest stress 2 C10 + 4 C01
+            _ => volume(f, 1.5 * mu, 0.5, 1.0, mu, lambda),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaterialModel {
+    StableNeoHookean,
+    StVenantKirchhoff,
+    CorotatedLinear,
+    MooneyRivlin,
+}
+
+impl MaterialModel {
+    pub const ALL: [MaterialModel; 4] = [
+        MaterialModel::StableNeoHookean,
+        MaterialModel::StVenantKirchhoff,
+        MaterialModel::CorotatedLinear,
+        MaterialModel::MooneyRivlin,
+    ];
+
+    pub fn material(&self) -> &'static dyn Material {
+        match self {
+            MaterialModel::StableNeoHookean => &StableNeoHookean,
+            MaterialModel::StVenantKirchhoff => &StVenantKirchhoff,
+            MaterialModel::CorotatedLinear => &CorotatedLinear,
+            MaterialModel::MooneyRivlin => &MooneyRivlin,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.material().name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // first Piola-Kirchhoff stress, the derivative of Ψ = Σ ½ k C² by F
+    fn stress(material: &dyn Material, f: &Matrix3<f64>, mu: f64, lambda: f64) -> Matrix3<f64> {
+        (0..material.num_terms())
+            .map(|i| {
+                let term = material.term(i, f, mu, lambda);
+                term.grad * (term.c * (term.dev * mu + term.vol * lambda))
+            })
+            .sum()
+    }
+
+    #[test]
+    fn rest_state_is_stress_free() {
+        let (mu, lambda) = Elasticity::Young {
+            youngs_modulus: 5000.0,
+            poisson_ratio: 0.3,
+        }
+        .lame();
+        for model in MaterialModel::ALL {
+            let p = stress(model.material(), &Matrix3::identity(), mu, lambda);
+            assert!(p.norm() < 1e-9 * mu, "{}: {}", model.name(), p);
+        }
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let f = Matrix3::new(1.1, 0.2, -0.1, 0.05, 0.9, 0.15, -0.2, 0.1, 1.2);
+        let (mu, lambda) = (100.0, 300.0);
+        let h = 1e-6;
+        for model in MaterialModel::ALL {
+            let material = model.material();
+            for i in 0..material.num_terms() {
+                let grad = material.term(i, &f, mu, lambda).grad;
+                for k in 0..9 {
+                    let mut df = Matrix3::zeros();
+                    df[k] = h;
+                    let c = |f: Matrix3<f64>| material.term(i, &f, mu, lambda).c;
+                    let slope = (c(f + df) - c(f - df)) / (2.0 * h);
+                    assert!(
+                        (slope - grad[k]).abs() < 1e-6,
+                        "{} term {}",
+                        model.name(),
+                        i
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn young_and_lame_round_trip() {
+        let young = Elasticity::Young {
+            youngs_modulus: 5000.0,
+            poisson_ratio: 0.3,
+        };
+        let (mu, lambda) = young.lame();
+        assert!((mu - 5000.0 / 2.6).abs() < 1e-9);
+        assert!((lambda - 1500.0 / 0.52).abs() < 1e-9);
+        let (e, nu) = young.to_lame().young();
+        assert!((e - 5000.0).abs() < 1e-9 && (nu - 0.3).abs() < 1e-12);
+        let lame = Elasticity::Lame {
+            mu: 10.0,
+            lambda: 20.0,
+        };
+        let (mu, lambda) = lame.to_young().lame();
+        assert!((mu - 10.0).abs() < 1e-12 && (lambda - 20.0).abs() < 1e-12);
+    }
+}
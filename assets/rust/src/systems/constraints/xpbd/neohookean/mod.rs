@@ -1,4 +1,6 @@
+pub mod colliders;
 pub mod interface;
-mod system;
-mod system_parameters;
-mod visualizer;
+pub mod material;
+pub mod system;
+pub mod system_parameters;
+pub mod visualizer;
@@ -1,29 +1,81 @@
-use nalgebra::{Matrix3, Vector3};
-
-pub struct SystemParameters {
-    time_step: f64,
-    num_substeps: usize,
-    friction: f64,
-    world_bounds: (Vector3<f64>, Vector3<f64>),
-    pub dev_compliance: f64,
-    pub vol_compliance: f64,
-    density: f64,
-    gravity: Vector3<f64>,
-}
+use super::colliders::Collider;
+use super::material::Material;
+use super::system_parameters::{BodyParameters, SolverMode, SystemParameters};
+use crate::systems::collisions::broadphase::aabb_tree::{Aabb, AabbTree};
+use crate::systems::collisions::broadphase::hash_grid::HashGrid;
+use crate::utils::graph_coloring;
+use crate::utils::recorder::{self, InputEvent, Recordable};
+use crate::utils::tetra_mesh::TetraMesh;
+use nalgebra::{Isometry3, Matrix3, Quaternion, UnitQuaternion, Vector3};
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
 
-impl Default for SystemParameters {
-    fn default() -> Self {
-        SystemParameters {
-            time_step: 0.1 / 60.0,
-            num_substeps: 50,
-            friction: 1000.0,
-            world_bounds: (Vector3::new(-5.0, 0.0, -5.0), Vector3::new(5.0, 10.0, 5.0)),
-            dev_compliance: 0.001,
-            vol_compliance: 0.001,
-            density: 1.0,
-            gravity: Vector3::new(0.0, -100.0, 0.0),
-        }
+// Closest point on triangle abc to p as barycentric weights of a, b, c (Ericson, RTCD 5.1.5).
+pub(super) fn closest_on_triangle(
+    p: &Vector3<f64>,
+    a: &Vector3<f64>,
+    b: &Vector3<f64>,
+    c: &Vector3<f64>,
+) -> [f64; 3] {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return [1.0, 0.0, 0.0];
+    }
+    let bp = p - b;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return [0.0, 1.0, 0.0];
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return [1.0 - v, v, 0.0];
+    }
+    let cp = p - c;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return [0.0, 0.0, 1.0];
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return [1.0 - w, 0.0, w];
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return [0.0, 1.0 - w, w];
     }
+    let denom = 1.0 / (va + vb + vc);
+    let v = vb * denom;
+    let w = vc * denom;
+    [1.0 - v - w, v, w]
+}
+
+// Particle pushed out of a wall or collider in the current substep.
+struct BoundContact {
+    id: usize,
+    normal: Vector3<f64>,
+    depth: f64,
+    // velocity of the obstacle at the particle
+    velocity: Vector3<f64>,
+    // relative normal velocity before the solve, for restitution
+    vn: f64,
+}
+
+// A body owns a contiguous range of particles and elements.
+pub struct Body {
+    pub par: BodyParameters,
+    pub first_particle: usize,
+    pub num_particles: usize,
+    pub first_elem: usize,
+    pub num_elems: usize,
 }
 
 pub struct System {
@@ -36,222 +88,653 @@ pub struct System {
     vel: Vec<Vector3<f64>>,
     inv_mass: Vec<f64>,
     inv_rest_pose: Vec<Matrix3<f64>>,
+    // 1 / (mu V) and 1 / (lambda V) of every element
+    dev_compliance: Vec<f64>,
+    vol_compliance: Vec<f64>,
     tet_ids: Vec<[usize; 4]>,
     vol_error: f64,
     grab_pos: Vector3<f64>,
     grab_id: i32,
-    grads: Vec<Vector3<f64>>,
+    // elements of a color share no particle
+    colors: Vec<Vec<usize>>,
+    // new positions and volume errors of the elements of one color
+    solved_elems: Vec<([Vector3<f64>; 4], f64)>,
+    // summed Jacobi position deltas and the number of elements of every particle
+    jacobi_deltas: Vec<Vector3<f64>>,
+    elem_counts: Vec<usize>,
+    tree: AabbTree<3>,
+    tree_proxies: Vec<usize>,
+    bodies: Vec<Body>,
+    particle_body: Vec<usize>,
+    // boundary surfaces of all bodies, hashed by vertex for collisions
+    surface: Vec<[usize; 3]>,
+    surface_verts: Vec<usize>,
+    grid: HashGrid<3>,
+    grid_pos: Vec<Vector3<f64>>,
+    candidates: Vec<usize>,
+    bound_contacts: Vec<BoundContact>,
+    pub colliders: Vec<Collider>,
+    orig_collider_transforms: Vec<Isometry3<f64>>,
 }
 
 impl System {
-    pub fn new(vertices: &Vec<Vector3<f64>>, tet_ids: &Vec<[usize; 4]>) -> Self {
-        let num_particles = vertices.len();
-        let num_elems = tet_ids.len();
-
-        let pos = vertices.clone();
-        let prev_pos = vertices.clone();
-        let vel = vec![Vector3::zeros(); num_particles];
-        let inv_mass = vec![0.0; num_particles];
-        let inv_rest_pose = vec![Matrix3::zeros(); num_elems];
-        let vol_error = 0.0;
-
-        let grab_pos = Vector3::zeros();
-        let grab_id = -1;
-
-        let grads = vec![Vector3::zeros(); 4];
-        let par = SystemParameters::default();
-        let mut system = System {
-            orig_pos: vertices.clone(),
-            par,
-            num_particles,
-            num_elems,
-            pos,
-            prev_pos,
-            vel,
-            inv_mass,
-            inv_rest_pose,
-            tet_ids: tet_ids.clone(),
-            vol_error,
-            grab_pos,
-            grab_id,
-            grads,
-        };
+    pub fn new(vertices: &[Vector3<f64>], tet_ids: &[[usize; 4]]) -> Self {
+        Self::with_parameters(vertices, tet_ids, SystemParameters::default())
+    }
 
-        system.init_physics();
+    pub fn with_parameters(
+        vertices: &[Vector3<f64>],
+        tet_ids: &[[usize; 4]],
+        par: SystemParameters,
+    ) -> Self {
+        let mut system = Self::empty(par);
+        system.add_body(vertices, tet_ids, BodyParameters::default());
         system
     }
+
+    pub fn empty(par: SystemParameters) -> Self {
+        System {
+            orig_pos: Vec::new(),
+            par,
+            num_particles: 0,
+            num_elems: 0,
+            pos: Vec::new(),
+            prev_pos: Vec::new(),
+            vel: Vec::new(),
+            inv_mass: Vec::new(),
+            inv_rest_pose: Vec::new(),
+            dev_compliance: Vec::new(),
+            vol_compliance: Vec::new(),
+            tet_ids: Vec::new(),
+            vol_error: 0.0,
+            grab_pos: Vector3::zeros(),
+            grab_id: -1,
+            colors: Vec::new(),
+            solved_elems: Vec::new(),
+            jacobi_deltas: Vec::new(),
+            elem_counts: Vec::new(),
+            tree: AabbTree::new(0.05),
+            tree_proxies: Vec::new(),
+            bodies: Vec::new(),
+            particle_body: Vec::new(),
+            surface: Vec::new(),
+            surface_verts: Vec::new(),
+            grid: HashGrid::new(0.0, 0),
+            grid_pos: Vec::new(),
+            candidates: Vec::new(),
+            bound_contacts: Vec::new(),
+            colliders: Vec::new(),
+            orig_collider_transforms: Vec::new(),
+        }
+    }
+
+    // Adds a copy of the mesh placed by body_par.transform, returns the body index.
+    pub fn add_body(
+        &mut self,
+        vertices: &[Vector3<f64>],
+        tet_ids: &[[usize; 4]],
+        body_par: BodyParameters,
+    ) -> usize {
+        let first_particle = self.num_particles;
+        let first_elem = self.num_elems;
+        for v in vertices {
+            let p = body_par.transform.transform_point(&(*v).into()).coords;
+            self.orig_pos.push(p);
+            self.pos.push(p);
+            self.prev_pos.push(p);
+            self.vel.push(Vector3::zeros());
+            self.inv_mass.push(0.0);
+        }
+        self.elem_counts
+            .resize(self.num_particles + vertices.len(), 0);
+        for tet in tet_ids {
+            for &id in tet {
+                self.elem_counts[id + first_particle] += 1;
+            }
+            self.tet_ids.push(tet.map(|id| id + first_particle));
+            self.inv_rest_pose.push(Matrix3::zeros());
+            self.dev_compliance.push(f64::INFINITY);
+            self.vol_compliance.push(f64::INFINITY);
+        }
+        self.num_particles += vertices.len();
+        self.num_elems += tet_ids.len();
+        let body_nr = self.bodies.len();
+        self.particle_body.resize(self.num_particles, body_nr);
+        self.add_surface(vertices, tet_ids, first_particle);
+        self.bodies.push(Body {
+            par: body_par,
+            first_particle,
+            num_particles: vertices.len(),
+            first_elem,
+            num_elems: tet_ids.len(),
+        });
+
+        self.init_body(body_nr);
+        self.build_tree();
+        self.colors = graph_coloring::color_elements(&self.tet_ids);
+        body_nr
+    }
+
+    fn add_surface(&mut self, vertices: &[Vector3<f64>], tet_ids: &[[usize; 4]], first: usize) {
+        let surface = TetraMesh::boundary_surface(vertices, tet_ids);
+        let mut max_edge: f64 = 0.0;
+        for tri in &surface {
+            for k in 0..3 {
+                max_edge = max_edge.max((vertices[tri[k]] - vertices[tri[(k + 1) % 3]]).norm());
+            }
+            self.surface.push(tri.map(|id| id + first));
+        }
+        let mut verts: Vec<usize> = surface.iter().flatten().map(|&id| id + first).collect();
+        verts.sort_unstable();
+        verts.dedup();
+        self.surface_verts.extend(verts);
+        self.grid_pos
+            .resize(self.surface_verts.len(), Vector3::zeros());
+
+        // triangles are queried with their circumscribing radius, keep cells about that size
+        let spacing = max_edge.max(self.grid.spacing());
+        self.grid = HashGrid::new(spacing, self.surface_verts.len());
+    }
+
+    // Kinematic colliders start from their current transform on reset.
+    pub fn add_collider(&mut self, collider: Collider) -> usize {
+        self.orig_collider_transforms.push(collider.transform);
+        self.colliders.push(collider);
+        self.colliders.len() - 1
+    }
+
+    pub fn bodies(&self) -> &[Body] {
+        &self.bodies
+    }
+
+    // Updates the material, the rest transform is fixed when the body is added.
+    pub fn set_body_parameters(&mut self, body_nr: usize, body_par: BodyParameters) {
+        let changed = self.bodies[body_nr].par.density != body_par.density
+            || self.bodies[body_nr].par.elasticity != body_par.elasticity;
+        self.bodies[body_nr].par.material = body_par.material;
+        self.bodies[body_nr].par.elasticity = body_par.elasticity;
+        self.bodies[body_nr].par.density = body_par.density;
+        if changed {
+            self.init_body(body_nr);
+        }
+    }
+
     pub fn reset(&mut self) {
         self.pos = self.orig_pos.clone();
         self.prev_pos = self.orig_pos.clone();
         self.vel = vec![Vector3::zeros(); self.num_particles];
-        self.grads = vec![Vector3::zeros(); 4];
+        for (collider, transform) in self
+            .colliders
+            .iter_mut()
+            .zip(&self.orig_collider_transforms)
+        {
+            collider.transform = *transform;
+        }
         self.init_physics();
+        self.build_tree();
+    }
+
+    pub fn vel(&self) -> &[Vector3<f64>] {
+        &self.vel
+    }
+
+    pub fn tet_ids(&self) -> &[[usize; 4]] {
+        &self.tet_ids
+    }
+
+    // mean volume constraint error of the last substep
+    pub fn vol_error(&self) -> f64 {
+        self.vol_error
+    }
+
+    fn tet_aabb(&self, elem_nr: usize) -> Aabb<3> {
+        Aabb::from_points(self.tet_ids[elem_nr].iter().map(|&id| &self.pos[id]))
+    }
+
+    fn build_tree(&mut self) {
+        self.tree.clear();
+        self.tree_proxies = (0..self.num_elems)
+            .map(|i| self.tree.insert(self.tet_aabb(i), i))
+            .collect();
+    }
+
+    fn update_tree(&mut self) {
+        for i in 0..self.num_elems {
+            let aabb = self.tet_aabb(i);
+            self.tree.update(self.tree_proxies[i], aabb);
+        }
     }
     fn init_physics(&mut self) {
-        for i in 0..self.num_particles {
+        for body_nr in 0..self.bodies.len() {
+            self.init_body(body_nr);
+        }
+    }
+
+    fn init_body(&mut self, body_nr: usize) {
+        let body = &self.bodies[body_nr];
+        let particles = body.first_particle..body.first_particle + body.num_particles;
+        let elems = body.first_elem..body.first_elem + body.num_elems;
+        let density = body.par.density;
+        let (mu, lambda) = body.par.lame();
+        for i in particles.clone() {
             self.inv_mass[i] = 0.0;
         }
 
-        for i in 0..self.num_elems {
+        for i in elems {
             let id0 = self.tet_ids[i][0];
             let id1 = self.tet_ids[i][1];
             let id2 = self.tet_ids[i][2];
             let id3 = self.tet_ids[i][3];
-            let p0 = self.pos[id1] - self.pos[id0];
-            let p1 = self.pos[id2] - self.pos[id0];
-            let p2 = self.pos[id3] - self.pos[id0];
+            let p0 = self.orig_pos[id1] - self.orig_pos[id0];
+            let p1 = self.orig_pos[id2] - self.orig_pos[id0];
+            let p2 = self.orig_pos[id3] - self.orig_pos[id0];
             let p = Matrix3::from_columns(&[p0, p1, p2]);
             let v = (p.determinant() / 6.0).abs();
+            self.dev_compliance[i] = f64::INFINITY;
+            self.vol_compliance[i] = f64::INFINITY;
             if v == 0.0 {
                 continue;
             }
             let inv_rest_pose = p.try_inverse().unwrap();
-            let pm = v / 4.0 * self.par.density;
+            let pm = v / 4.0 * density;
             self.inv_mass[id0] += pm;
             self.inv_mass[id1] += pm;
             self.inv_mass[id2] += pm;
             self.inv_mass[id3] += pm;
             self.inv_rest_pose[i] = inv_rest_pose;
+            self.dev_compliance[i] = 1.0 / (mu * v);
+            self.vol_compliance[i] = 1.0 / (lambda * v);
         }
 
-        for i in 0..self.num_particles {
+        for i in particles {
             if self.inv_mass[i] != 0.0 {
                 self.inv_mass[i] = 1.0 / self.inv_mass[i];
             }
         }
     }
 
-    fn solve_shape(&mut self, elem_nr: usize, dt: f64) {
-        //set grads to zero
-        for i in 0..4 {
-            self.grads[i] = Vector3::zeros();
-        }
-        let ir = &self.inv_rest_pose[elem_nr];
-
-        let id0 = self.tet_ids[elem_nr][0];
-        let id1 = self.tet_ids[elem_nr][1];
-        let id2 = self.tet_ids[elem_nr][2];
-        let id3 = self.tet_ids[elem_nr][3];
-
-        let p0 = self.pos[id1] - self.pos[id0];
-        let p1 = self.pos[id2] - self.pos[id0];
-        let p2 = self.pos[id3] - self.pos[id0];
-        let p = Matrix3::from_columns(&[p0, p1, p2]);
-        let f = p * ir;
-
-        let f0 = f.column(0);
-        let f1 = f.column(1);
-        let f2 = f.column(2);
-
-        self.grads[1] += f0 * 2.0 * ir[(0, 0)];
-        self.grads[1] += f1 * 2.0 * ir[(0, 1)];
-        self.grads[1] += f2 * 2.0 * ir[(0, 2)];
-
-        self.grads[2] += f0 * 2.0 * ir[(1, 0)];
-        self.grads[2] += f1 * 2.0 * ir[(1, 1)];
-        self.grads[2] += f2 * 2.0 * ir[(1, 2)];
-
-        self.grads[3] += f0 * 2.0 * ir[(2, 0)];
-        self.grads[3] += f1 * 2.0 * ir[(2, 1)];
-        self.grads[3] += f2 * 2.0 * ir[(2, 2)];
-
-        let c = f0.norm_squared() + f1.norm_squared() + f2.norm_squared() - 3.0;
+    fn elem_positions(&self, elem_nr: usize) -> [Vector3<f64>; 4] {
+        self.tet_ids[elem_nr].map(|id| self.pos[id])
+    }
 
-        self.apply_to_elem(elem_nr, c, self.par.dev_compliance, dt);
+    fn set_elem_positions(&mut self, elem_nr: usize, x: &[Vector3<f64>; 4]) {
+        for (k, &id) in self.tet_ids[elem_nr].iter().enumerate() {
+            self.pos[id] = x[k];
+        }
     }
 
-    fn solve_volume(&mut self, elem_nr: usize, dt: f64) {
-        let ir = &self.inv_rest_pose[elem_nr];
+    fn deformation_gradient(&self, elem_nr: usize, x: &[Vector3<f64>; 4]) -> Matrix3<f64> {
+        let p = Matrix3::from_columns(&[x[1] - x[0], x[2] - x[0], x[3] - x[0]]);
+        p * self.inv_rest_pose[elem_nr]
+    }
 
-        let id0 = self.tet_ids[elem_nr][0];
-        let id1 = self.tet_ids[elem_nr][1];
-        let id2 = self.tet_ids[elem_nr][2];
-        let id3 = self.tet_ids[elem_nr][3];
+    fn apply_to_elem(
+        &self,
+        elem_nr: usize,
+        x: &mut [Vector3<f64>; 4],
+        grads: &[Vector3<f64>; 4],
+        c: f64,
+        compliance: f64,
+        dt: f64,
+    ) {
+        if c == 0.0 {
+            return;
+        }
+        let inv_mass = self.tet_ids[elem_nr].map(|id| self.inv_mass[id]);
+        let mut w = 0.0;
+        for i in 0..4 {
+            w += grads[i].norm_squared() * inv_mass[i];
+        }
 
-        let p0 = self.pos[id1] - self.pos[id0];
-        let p1 = self.pos[id2] - self.pos[id0];
-        let p2 = self.pos[id3] - self.pos[id0];
-        let p = Matrix3::from_columns(&[p0, p1, p2]);
-        let f = p * ir;
+        if w == 0.0 {
+            return;
+        }
+        let alpha = compliance / (dt * dt);
+        let dlambda = -c / (w + alpha);
 
-        //set grads to zero
         for i in 0..4 {
-            self.grads[i] = Vector3::zeros();
+            x[i] += grads[i] * dlambda * inv_mass[i];
         }
-        let f0 = f.column(0);
-        let f1 = f.column(1);
-        let f2 = f.column(2);
+    }
 
-        let df0 = f1.cross(&f2);
-        let df1 = f2.cross(&f0);
-        let df2 = f0.cross(&f1);
+    // Solves the energy terms of the material one after another on the element
+    // positions x, returns the volume error det F - 1.
+    fn solve_elem(
+        &self,
+        elem_nr: usize,
+        material: &dyn Material,
+        lame: (f64, f64),
+        dt: f64,
+        x: &mut [Vector3<f64>; 4],
+    ) -> f64 {
+        let dev_compliance = self.dev_compliance[elem_nr];
+        let vol_compliance = self.vol_compliance[elem_nr];
+        if dev_compliance.is_infinite() && vol_compliance.is_infinite() {
+            return 0.0;
+        }
+        for i in 0..material.num_terms() {
+            let f = self.deformation_gradient(elem_nr, x);
+            let term = material.term(i, &f, lame.0, lame.1);
+            // k V
+            let mut stiffness = 0.0;
+            if term.dev != 0.0 {
+                stiffness += term.dev / dev_compliance;
+            }
+            if term.vol != 0.0 {
+                stiffness += term.vol / vol_compliance;
+            }
+            if stiffness.is_nan() || stiffness <= 0.0 {
+                continue;
+            }
+            // dC/dx_i = dC/dF * Dm^-T
+            let g = term.grad * self.inv_rest_pose[elem_nr].transpose();
+            let (g1, g2, g3) = (g.column(0), g.column(1), g.column(2));
+            let grads = [-g1 - g2 - g3, g1.into(), g2.into(), g3.into()];
+            self.apply_to_elem(elem_nr, x, &grads, term.c, 1.0 / stiffness, dt);
+        }
+        self.deformation_gradient(elem_nr, x).determinant() - 1.0
+    }
 
-        self.grads[1] += df0 * ir[(0, 0)];
-        self.grads[1] += df1 * ir[(0, 1)];
-        self.grads[1] += df2 * ir[(0, 2)];
+    fn body_materials(&self) -> Vec<(&'static dyn Material, (f64, f64))> {
+        self.bodies
+            .iter()
+            .map(|body| (body.par.material.material(), body.par.lame()))
+            .collect()
+    }
 
-        self.grads[2] += df0 * ir[(1, 0)];
-        self.grads[2] += df1 * ir[(1, 1)];
-        self.grads[2] += df2 * ir[(1, 2)];
+    fn solve_elems_gauss_seidel(&mut self, dt: f64) {
+        for (body_nr, (material, lame)) in self.body_materials().into_iter().enumerate() {
+            let first = self.bodies[body_nr].first_elem;
+            for i in first..first + self.bodies[body_nr].num_elems {
+                let mut x = self.elem_positions(i);
+                self.vol_error += self.solve_elem(i, material, lame, dt, &mut x);
+                self.set_elem_positions(i, &x);
+            }
+        }
+    }
 
-        self.grads[3] += df0 * ir[(2, 0)];
-        self.grads[3] += df1 * ir[(2, 1)];
-        self.grads[3] += df2 * ir[(2, 2)];
+    // Elements of one color are independent and solved in parallel on native,
+    // the colors one after another.
+    fn solve_elems_colored(&mut self, dt: f64) {
+        let materials = self.body_materials();
+        let mut solved = std::mem::take(&mut self.solved_elems);
+        for color in &self.colors {
+            let solve = |&elem_nr: &usize| {
+                let (material, lame) = materials[self.particle_body[self.tet_ids[elem_nr][0]]];
+                let mut x = self.elem_positions(elem_nr);
+                let vol_error = self.solve_elem(elem_nr, material, lame, dt, &mut x);
+                (x, vol_error)
+            };
+            #[cfg(not(target_arch = "wasm32"))]
+            color.par_iter().map(solve).collect_into_vec(&mut solved);
+            #[cfg(target_arch = "wasm32")]
+            {
+                solved.clear();
+                solved.extend(color.iter().map(solve));
+            }
+            for (&elem_nr, (x, vol_error)) in color.iter().zip(&solved) {
+                for (k, &id) in self.tet_ids[elem_nr].iter().enumerate() {
+                    self.pos[id] = x[k];
+                }
+                self.vol_error += vol_error;
+            }
+        }
+        self.solved_elems = solved;
+    }
 
-        let c = f.determinant() - 1.0;
-        self.vol_error += c;
+    // Every element is solved from the positions of the previous iteration, the
+    // deltas are averaged per particle (Bridson et al. mass splitting) so that
+    // stacked constraints do not overshoot.
+    fn solve_elems_jacobi(&mut self, dt: f64) {
+        let materials = self.body_materials();
+        let mut solved = std::mem::take(&mut self.solved_elems);
+        let solve = |elem_nr: usize| {
+            let (material, lame) = materials[self.particle_body[self.tet_ids[elem_nr][0]]];
+            let mut x = self.elem_positions(elem_nr);
+            let vol_error = self.solve_elem(elem_nr, material, lame, dt, &mut x);
+            (x, vol_error)
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        (0..self.num_elems)
+            .into_par_iter()
+            .map(solve)
+            .collect_into_vec(&mut solved);
+        #[cfg(target_arch = "wasm32")]
+        {
+            solved.clear();
+            solved.extend((0..self.num_elems).map(solve));
+        }
 
-        self.apply_to_elem(elem_nr, c, self.par.vol_compliance, dt);
+        self.jacobi_deltas.clear();
+        self.jacobi_deltas
+            .resize(self.num_particles, Vector3::zeros());
+        for (elem_nr, (x, vol_error)) in solved.iter().enumerate() {
+            for (k, &id) in self.tet_ids[elem_nr].iter().enumerate() {
+                self.jacobi_deltas[id] += x[k] - self.pos[id];
+            }
+            self.vol_error += vol_error;
+        }
+        for i in 0..self.num_particles {
+            if self.elem_counts[i] > 0 {
+                self.pos[i] +=
+                    self.jacobi_deltas[i] * self.par.jacobi_relaxation / self.elem_counts[i] as f64;
+            }
+        }
+        self.solved_elems = solved;
     }
 
-    fn apply_to_elem(&mut self, elem_nr: usize, c: f64, compliance: f64, dt: f64) {
-        if c == 0.0 {
+    fn solve_collisions(&mut self, dt: f64) {
+        if !self.par.collisions || self.surface.is_empty() {
             return;
         }
-        self.grads[0] = -self.grads[1] - self.grads[2] - self.grads[3];
+        for (k, &id) in self.surface_verts.iter().enumerate() {
+            self.grid_pos[k] = self.pos[id];
+        }
+        self.grid.create(&self.grid_pos);
 
-        let mut w = 0.0;
-        for i in 0..4 {
-            let id = self.tet_ids[elem_nr][i];
-            // make vector3
-            let g = self.grads[i];
-            w += g.norm_squared() * self.inv_mass[id];
+        let thickness = self.par.collision_thickness;
+        for tri_nr in 0..self.surface.len() {
+            let tri = self.surface[tri_nr];
+            let center = (self.pos[tri[0]] + self.pos[tri[1]] + self.pos[tri[2]]) / 3.0;
+            let radius = tri
+                .iter()
+                .map(|&id| (self.pos[id] - center).norm())
+                .fold(0.0, f64::max);
+            self.candidates.clear();
+            self.candidates
+                .extend_from_slice(self.grid.query(&center, radius + thickness));
+            for i in 0..self.candidates.len() {
+                let id = self.surface_verts[self.candidates[i]];
+                // a vertex only skips the triangles it belongs to
+                if !tri.contains(&id) {
+                    self.solve_contact(id, tri, dt);
+                }
+            }
+        }
+    }
+
+    // Keeps vertex id at collision_thickness in front of the outward side of tri.
+    fn solve_contact(&mut self, id: usize, tri: [usize; 3], dt: f64) {
+        let thickness = self.par.collision_thickness;
+        let [a, b, c] = tri;
+        let p = self.pos[id];
+        let bary = closest_on_triangle(&p, &self.pos[a], &self.pos[b], &self.pos[c]);
+        let q = self.pos[a] * bary[0] + self.pos[b] * bary[1] + self.pos[c] * bary[2];
+        let face = (self.pos[b] - self.pos[a]).cross(&(self.pos[c] - self.pos[a]));
+        if face.norm_squared() == 0.0 {
+            return;
         }
+        let face = face.normalize();
+        let diff = p - q;
+        let dist = diff.norm();
+        if dist >= thickness {
+            return;
+        }
+        // the face normal inside the triangle, at an edge or corner the direction from
+        // the closest point, only in front of the face, behind it the vertex is left to
+        // the triangles it projects into
+        let inside = bary.iter().all(|&w| w > 0.0);
+        let (n, d) = if inside || dist < 1e-12 {
+            (face, face.dot(&diff))
+        } else if face.dot(&diff) > 0.0 {
+            (diff / dist, dist)
+        } else {
+            return;
+        };
 
+        let mut w = self.inv_mass[id];
+        for k in 0..3 {
+            w += bary[k] * bary[k] * self.inv_mass[tri[k]];
+        }
         if w == 0.0 {
             return;
         }
-        let alpha = compliance / (dt * dt);
-        let dlambda = -c / (w + alpha);
+        let alpha = self.par.collision_compliance / (dt * dt);
+        let dlambda = -(d - thickness) / (w + alpha);
+        self.pos[id] += n * dlambda * self.inv_mass[id];
+        for k in 0..3 {
+            self.pos[tri[k]] -= n * dlambda * bary[k] * self.inv_mass[tri[k]];
+        }
+    }
 
-        for i in 0..4 {
-            let id = self.tet_ids[elem_nr][i];
-            let g = self.grads[i];
-            self.pos[id] += g * dlambda * self.inv_mass[id];
+    // Moves a particle out along normal, with static friction undoing the tangential
+    // motion relative to the obstacle while it stays inside the friction cone.
+    fn push_out(
+        &mut self,
+        id: usize,
+        normal: Vector3<f64>,
+        depth: f64,
+        velocity: Vector3<f64>,
+        dt: f64,
+    ) {
+        self.pos[id] += normal * depth;
+        let dp = self.pos[id] - self.prev_pos[id] - velocity * dt;
+        let dp_t = dp - normal * normal.dot(&dp);
+        if dp_t.norm() < self.par.static_friction * depth {
+            self.pos[id] -= dp_t;
         }
+        self.bound_contacts.push(BoundContact {
+            id,
+            normal,
+            depth,
+            velocity,
+            vn: normal.dot(&(self.vel[id] - velocity)),
+        });
     }
 
-    fn solve_elem(&mut self, elem_nr: usize, dt: f64) {
-        self.solve_shape(elem_nr, dt);
-        self.solve_volume(elem_nr, dt);
+    // Projects particles back inside the walls.
+    fn solve_world_bounds(&mut self, dt: f64) {
+        let (lo, hi) = self.par.world_bounds;
+        for id in 0..self.num_particles {
+            for i in 0..3 {
+                let mut normal = Vector3::zeros();
+                let depth = if self.pos[id][i] < lo[i] {
+                    normal[i] = 1.0;
+                    lo[i] - self.pos[id][i]
+                } else if self.pos[id][i] > hi[i] {
+                    normal[i] = -1.0;
+                    self.pos[id][i] - hi[i]
+                } else {
+                    continue;
+                };
+                self.push_out(id, normal, depth, Vector3::zeros(), dt);
+            }
+        }
     }
-    fn world_bounds(pos: &mut Vector3<f64>, world_bounds: (Vector3<f64>, Vector3<f64>)) {
-        for i in 0..3 {
-            pos[i] = f64::max(pos[i], world_bounds.0[i]);
-            pos[i] = f64::min(pos[i], world_bounds.1[i]);
+
+    fn solve_colliders(&mut self, dt: f64) {
+        for c in 0..self.colliders.len() {
+            for id in 0..self.num_particles {
+                if let Some((depth, normal)) = self.colliders[c].contact(&self.pos[id]) {
+                    let velocity = self.colliders[c].velocity_at(&self.pos[id]);
+                    self.push_out(id, normal, depth, velocity, dt);
+                }
+            }
         }
     }
-    fn find_closest(&self, p: Vector3<f64>) -> i32 {
-        let mut min_dist = f64::MAX;
-        let mut min_id = -1;
-        for i in 0..self.num_particles {
-            let dist = (self.pos[i] - p).norm_squared();
-            if dist < min_dist {
-                min_dist = dist;
-                min_id = i as i32;
+
+    // Mass weighted mean velocity of the body along normal.
+    fn mean_normal_velocity(&self, body: &Body, normal: &Vector3<f64>) -> Option<f64> {
+        let (mut momentum, mut mass) = (0.0, 0.0);
+        for i in body.first_particle..body.first_particle + body.num_particles {
+            if self.inv_mass[i] > 0.0 {
+                momentum += self.vel[i].dot(normal) / self.inv_mass[i];
+                mass += 1.0 / self.inv_mass[i];
             }
         }
-        min_id
+        (mass > 0.0).then(|| momentum / mass)
+    }
+
+    // Bodies hitting a wall or collider in this substep: the mean normal of the
+    // contacts, the normal velocity of the obstacles and of the body before the solve.
+    // Resting contacts do not bounce.
+    fn find_bounces(&self, dt: f64) -> Vec<Option<(Vector3<f64>, f64, f64)>> {
+        let threshold = 2.0 * self.par.gravity.norm() * dt;
+        let mut normals = vec![Vector3::zeros(); self.bodies.len()];
+        let mut velocities = vec![Vector3::zeros(); self.bodies.len()];
+        let mut counts = vec![0; self.bodies.len()];
+        for c in self.bound_contacts.iter().filter(|c| c.vn < -threshold) {
+            let b = self.particle_body[c.id];
+            normals[b] += c.normal;
+            velocities[b] += c.velocity;
+            counts[b] += 1;
+        }
+        self.bodies
+            .iter()
+            .enumerate()
+            .map(|(b, body)| {
+                let normal = normals[b].try_normalize(1e-12)?;
+                let obstacle = velocities[b].dot(&normal) / counts[b] as f64;
+                let before = self.mean_normal_velocity(body, &normal)?;
+                Some((normal, obstacle, before))
+            })
+            .collect()
+    }
+
+    // Dynamic friction on the velocities of the wall contacts, which stop moving
+    // along the normal. The restitution acts on the mean velocity of a bouncing body,
+    // reflecting only the contact particles would shake it instead.
+    fn solve_bound_velocities(&mut self, dt: f64, bounces: &[Option<(Vector3<f64>, f64, f64)>]) {
+        for c in &self.bound_contacts {
+            let v = self.vel[c.id] - c.velocity;
+            let vn = c.normal.dot(&v);
+            let vt = v - c.normal * vn;
+            let vt_len = vt.norm();
+            if vt_len > 0.0 {
+                let dv = (self.par.dynamic_friction * c.depth / dt).min(vt_len);
+                self.vel[c.id] -= vt * (dv / vt_len);
+            }
+            self.vel[c.id] -= c.normal * vn;
+        }
+        for (body, bounce) in self.bodies.iter().zip(bounces) {
+            let Some((normal, obstacle, before)) = *bounce else {
+                continue;
+            };
+            let Some(after) = self.mean_normal_velocity(body, &normal) else {
+                continue;
+            };
+            let target = obstacle - self.par.restitution * (before - obstacle);
+            if after < target {
+                for i in body.first_particle..body.first_particle + body.num_particles {
+                    self.vel[i] += normal * (target - after);
+                }
+            }
+        }
+    }
+    fn find_closest(&mut self, p: Vector3<f64>) -> i32 {
+        let pos = &self.pos;
+        let tet_ids = &self.tet_ids;
+        let closest_in_tet = |elem_nr: usize| {
+            tet_ids[elem_nr]
+                .iter()
+                .map(|&id| (id, (pos[id] - p).norm_squared()))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap()
+        };
+        match self.tree.nearest(&p, |elem_nr| closest_in_tet(elem_nr).1) {
+            Some((elem_nr, _)) => closest_in_tet(elem_nr).0 as i32,
+            None => -1,
+        }
     }
     pub fn init_grab(&mut self, p: Vector3<f64>) {
         self.grab_id = self.find_closest(p);
@@ -264,32 +747,351 @@ impl System {
     }
 
     pub fn simulate(&mut self) {
-        let dt = self.par.time_step / self.par.num_substeps as f64;
         for _ in 0..self.par.num_substeps {
-            // XPBD prediction
-            for i in 0..self.num_particles {
-                self.vel[i] += self.par.gravity * dt;
-                self.prev_pos[i] = self.pos[i];
-                self.pos[i] += self.vel[i] * dt * 0.99995;
-            }
-            if self.grab_id >= 0 {
-                self.pos[self.grab_id as usize] = self.grab_pos;
+            self.substep();
+        }
+    }
+
+    pub fn substep(&mut self) {
+        let dt = self.par.time_step / self.par.num_substeps as f64;
+        for collider in &mut self.colliders {
+            collider.advance(dt);
+        }
+        // XPBD prediction
+        for i in 0..self.num_particles {
+            self.vel[i] += self.par.gravity * dt;
+            self.prev_pos[i] = self.pos[i];
+            self.pos[i] += self.vel[i] * dt * 0.99995;
+        }
+        if self.grab_id >= 0 {
+            self.pos[self.grab_id as usize] = self.grab_pos;
+        }
+        // solve
+        self.vol_error = 0.0;
+        match self.par.solver {
+            SolverMode::GaussSeidel => self.solve_elems_gauss_seidel(dt),
+            SolverMode::Colored => self.solve_elems_colored(dt),
+            SolverMode::Jacobi => self.solve_elems_jacobi(dt),
+        }
+        if self.num_elems > 0 {
+            self.vol_error /= self.num_elems as f64;
+        }
+        self.solve_collisions(dt);
+        // world collision
+        self.bound_contacts.clear();
+        self.solve_world_bounds(dt);
+        self.solve_colliders(dt);
+
+        // XPBD velocity update
+        let bounces = self.find_bounces(dt);
+        for i in 0..self.num_particles {
+            self.vel[i] = (self.pos[i] - self.prev_pos[i]) / dt;
+        }
+        self.solve_bound_velocities(dt, &bounces);
+        self.update_tree();
+    }
+}
+
+impl Recordable for System {
+    fn num_substeps(&self) -> usize {
+        self.par.num_substeps
+    }
+
+    fn substep(&mut self) {
+        System::substep(self);
+    }
+
+    fn save_state(&self, out: &mut Vec<f64>) {
+        for (p, v) in self.pos.iter().zip(&self.vel) {
+            out.extend_from_slice(&[p.x, p.y, p.z, v.x, v.y, v.z]);
+        }
+        for collider in &self.colliders {
+            let t = collider.transform.translation.vector;
+            let q = collider.transform.rotation.coords;
+            out.extend_from_slice(&[t.x, t.y, t.z, q.x, q.y, q.z, q.w]);
+        }
+    }
+
+    fn load_state(&mut self, state: &[f64]) -> std::io::Result<()> {
+        recorder::check_state_len(state, 6 * self.num_particles + 7 * self.colliders.len())?;
+        let (particles, colliders) = state.split_at(6 * self.num_particles);
+        for (i, s) in particles.chunks_exact(6).enumerate() {
+            self.pos[i] = Vector3::new(s[0], s[1], s[2]);
+            self.vel[i] = Vector3::new(s[3], s[4], s[5]);
+        }
+        for (collider, s) in self.colliders.iter_mut().zip(colliders.chunks_exact(7)) {
+            collider.transform = Isometry3::from_parts(
+                Vector3::new(s[0], s[1], s[2]).into(),
+                UnitQuaternion::new_unchecked(Quaternion::new(s[6], s[3], s[4], s[5])),
+            );
+        }
+        self.update_tree();
+        Ok(())
+    }
+
+    fn apply_input(&mut self, event: &InputEvent) {
+        match *event {
+            InputEvent::InitGrab(p) => self.init_grab(p),
+            InputEvent::MoveGrab(p) => self.move_grab(p),
+            InputEvent::ReleaseGrab => self.release_grab(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::constraints::xpbd::neohookean::material::MaterialModel;
+
+    // n^3 cubes of side h, six tetrahedra each, standing on the ground
+    fn block(n: usize, h: f64) -> (Vec<Vector3<f64>>, Vec<[usize; 4]>) {
+        let id = |i: usize, j: usize, k: usize| (i * (n + 1) + j) * (n + 1) + k;
+        let mut vertices = Vec::new();
+        for i in 0..=n {
+            for j in 0..=n {
+                for k in 0..=n {
+                    vertices.push(Vector3::new(i as f64, j as f64, k as f64) * h);
+                }
             }
-            // solve
-            self.vol_error = 0.0;
-            for i in 0..self.num_elems {
-                self.solve_elem(i, dt);
+        }
+        let mut tets = Vec::new();
+        for i in 0..n {
+            for j in 0..n {
+                for k in 0..n {
+                    let c = [
+                        id(i, j, k),
+                        id(i + 1, j, k),
+                        id(i + 1, j + 1, k),
+                        id(i, j + 1, k),
+                        id(i, j, k + 1),
+                        id(i + 1, j, k + 1),
+                        id(i + 1, j + 1, k + 1),
+                        id(i, j + 1, k + 1),
+                    ];
+                    // around the diagonal from corner 0 to corner 6
+                    for [a, b] in [[1, 2], [2, 3], [3, 7], [7, 4], [4, 5], [5, 1]] {
+                        tets.push([c[0], c[a], c[b], c[6]]);
+                    }
+                }
             }
-            self.vol_error /= self.num_elems as f64;
-            // world collision
-            for i in 0..self.num_particles {
-                Self::world_bounds(&mut self.pos[i], self.par.world_bounds);
+        }
+        (vertices, tets)
+    }
+
+    fn settle(solver: SolverMode) -> Vec<Vector3<f64>> {
+        let (vertices, tets) = block(2, 0.5);
+        let par = SystemParameters {
+            solver,
+            num_substeps: 10,
+            collisions: false,
+            ..SystemParameters::default()
+        };
+        let mut system = System::with_parameters(&vertices, &tets, par);
+        for _ in 0..100 {
+            system.simulate();
+        }
+        system.pos
+    }
+
+    #[test]
+    fn parallel_solvers_match_gauss_seidel() {
+        let reference = settle(SolverMode::GaussSeidel);
+        // Jacobi converges slower per substep and sags a little more
+        for (solver, tolerance) in [(SolverMode::Colored, 1e-3), (SolverMode::Jacobi, 5e-2)] {
+            let pos = settle(solver);
+            let diff = pos.iter().zip(&reference).map(|(p, q)| (p - q).norm());
+            let max = diff.fold(0.0, f64::max);
+            assert!(max < tolerance, "{} is {} off", solver.name(), max);
+        }
+    }
+
+    fn body_positions(system: &System, body_nr: usize) -> &[Vector3<f64>] {
+        let body = &system.bodies()[body_nr];
+        &system.pos[body.first_particle..body.first_particle + body.num_particles]
+    }
+
+    fn strictly_inside(p: &Vector3<f64>, corners: [Vector3<f64>; 4]) -> bool {
+        let m = Matrix3::from_columns(&[
+            corners[1] - corners[0],
+            corners[2] - corners[0],
+            corners[3] - corners[0],
+        ]);
+        let Some(inv) = m.try_inverse() else {
+            return false;
+        };
+        let w = inv * (p - corners[0]);
+        let margin = 1e-3;
+        w.min() > margin && w.sum() < 1.0 - margin
+    }
+
+    #[test]
+    fn stacked_bodies_do_not_interpenetrate() {
+        let (vertices, tets) = block(2, 1.0);
+        let (small, small_tets) = block(1, 1.0);
+        let mut system = System::empty(SystemParameters {
+            num_substeps: 10,
+            ..SystemParameters::default()
+        });
+        system.add_body(&vertices, &tets, BodyParameters::default());
+        let top = BodyParameters {
+            transform: Isometry3::translation(0.3, 2.2, 0.3),
+            ..BodyParameters::default()
+        };
+        system.add_body(&small, &small_tets, top);
+        for _ in 0..80 {
+            system.simulate();
+        }
+        let upper = body_positions(&system, 1);
+        // it landed and rests on the lower one
+        let bottom = upper.iter().map(|p| p.y).fold(f64::MAX, f64::min);
+        assert!(
+            bottom < 2.1 && bottom > 1.8,
+            "bottom of the upper body at {}",
+            bottom
+        );
+        for (b, other) in [(0, 1), (1, 0)] {
+            let body = &system.bodies()[other];
+            let tets = &system.tet_ids()[body.first_elem..body.first_elem + body.num_elems];
+            for p in body_positions(&system, b) {
+                for tet in tets {
+                    assert!(
+                        !strictly_inside(p, tet.map(|id| system.pos[id])),
+                        "{} in body {}",
+                        p,
+                        other
+                    );
+                }
             }
+        }
+    }
+
+    // Mean slide of the bottom of a block resting on the floor, pushed sideways with
+    // the given share of its weight.
+    fn slide(push: f64) -> f64 {
+        let (vertices, tets) = block(1, 1.0);
+        let par = SystemParameters {
+            num_substeps: 10,
+            collisions: false,
+            ..SystemParameters::default()
+        };
+        let mut system = System::with_parameters(&vertices, &tets, par);
+        for _ in 0..100 {
+            system.simulate();
+        }
+        let start: Vec<f64> = system.pos.iter().map(|p| p.x).collect();
+        system.par.gravity.x = -push * system.par.gravity.y;
+        for _ in 0..100 {
+            system.simulate();
+        }
+        let bottom: Vec<usize> = (0..vertices.len())
+            .filter(|&i| vertices[i].y == 0.0)
+            .collect();
+        bottom
+            .iter()
+            .map(|&i| system.pos[i].x - start[i])
+            .sum::<f64>()
+            / bottom.len() as f64
+    }
+
+    #[test]
+    fn friction_holds_inside_the_cone() {
+        // a quarter of and above the static friction coefficient of 0.6
+        let stick = slide(0.15);
+        let slip = slide(1.0);
+        assert!(stick.abs() < 1e-3, "slid {} inside the cone", stick);
+        assert!(slip > 0.1, "slid only {} outside the cone", slip);
+    }
 
-            // XPBD velocity update
-            for i in 0..self.num_particles {
-                self.vel[i] = (self.pos[i] - self.prev_pos[i]) / dt;
+    // Highest the block gets after it first touched the floor, dropped from 1 above.
+    fn rebound(restitution: f64) -> f64 {
+        let (vertices, tets) = block(1, 1.0);
+        let par = SystemParameters {
+            num_substeps: 10,
+            collisions: false,
+            restitution,
+            ..SystemParameters::default()
+        };
+        let mut system = System::empty(par);
+        let body_par = BodyParameters {
+            transform: Isometry3::translation(0.0, 1.0, 0.0),
+            ..BodyParameters::default()
+        };
+        system.add_body(&vertices, &tets, body_par);
+        // lowest point of the bottom before it rises again, and the highest after
+        let mut lowest = f64::MAX;
+        let mut rising = false;
+        let mut height: f64 = 0.0;
+        for _ in 0..250 {
+            system.simulate();
+            let bottom = system.pos.iter().map(|p| p.y).fold(f64::MAX, f64::min);
+            if !rising && bottom <= lowest {
+                lowest = bottom;
+            } else {
+                rising = true;
+                height = height.max(bottom);
             }
         }
+        height
+    }
+
+    #[test]
+    fn restitution_sets_the_rebound() {
+        let dead = rebound(0.0);
+        // the block keeps some of the energy in its vibration
+        let elastic = rebound(1.0);
+        assert!(dead < 0.05, "rebounds to {} without restitution", dead);
+        assert!(
+            elastic > 0.5 && elastic < 1.0,
+            "rebounds to {} with full restitution",
+            elastic
+        );
+    }
+
+    // Block resting on the floor after a second, with collisions off.
+    fn rest(body_par: BodyParameters) -> Vec<Vector3<f64>> {
+        let (vertices, tets) = block(1, 1.0);
+        let mut system = System::empty(SystemParameters {
+            num_substeps: 10,
+            collisions: false,
+            ..SystemParameters::default()
+        });
+        let name = body_par.material.name();
+        system.add_body(&vertices, &tets, body_par);
+        for _ in 0..100 {
+            system.simulate();
+        }
+        let volumes_positive = system.tet_ids().iter().all(|tet| {
+            let [a, b, c, d] = tet.map(|id| system.pos[id]);
+            (b - a).cross(&(c - a)).dot(&(d - a)) > 0.0
+        });
+        assert!(volumes_positive, "{} inverts", name);
+        system.pos
+    }
+
+    #[test]
+    fn every_material_holds_a_block() {
+        for material in MaterialModel::ALL {
+            let body_par = BodyParameters {
+                material,
+                ..BodyParameters::default()
+            };
+            let top = rest(body_par).iter().map(|p| p.y).fold(f64::MIN, f64::max);
+            assert!(
+                top > 0.9 && top < 1.01,
+                "{} top at {}",
+                material.name(),
+                top
+            );
+        }
+    }
+
+    #[test]
+    fn young_and_lame_give_the_same_motion() {
+        let young = BodyParameters::default();
+        let lame = BodyParameters {
+            elasticity: young.elasticity.to_lame(),
+            ..BodyParameters::default()
+        };
+        assert!(rest(young) == rest(lame));
     }
 }
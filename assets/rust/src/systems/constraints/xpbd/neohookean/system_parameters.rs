@@ -1,26 +1,96 @@
-// use crate::utils::parameters::Parameters;
-
-// #[derive(Debug)]
-// pub struct SystemParameters {
-//     pub base: Parameters,
-//     pub g: f64,
-//     pub dt: f64,
-//     pub restitution: f64,
-//     pub sub_steps: usize,
-//     pub relax_iter: usize,
-// }
-
-// // Default parameters
-// impl SystemParameters {
-//     pub fn default_parameters(width: u32, height: u32) -> SystemParameters {
-//         SystemParameters {
-//             base: Parameters::new(width, height),
-//             g: -200.,
-//             dt: 0.001,
-//             restitution: 0.95,
-//             sub_steps: 5,
-//             relax_iter: 2,
-//             n_points: 10,
-//         }
-//     }
-// }
+use super::material::{Elasticity, MaterialModel};
+use nalgebra::{Isometry3, Vector3};
+
+// Order in which the element constraints are solved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolverMode {
+    GaussSeidel,
+    // graph coloring, the elements of a color in parallel
+    Colored,
+    // all elements from the same positions in parallel, the deltas of a particle
+    // averaged and scaled by the relaxation factor
+    Jacobi,
+}
+
+impl SolverMode {
+    pub const ALL: [SolverMode; 3] = [
+        SolverMode::GaussSeidel,
+        SolverMode::Colored,
+        SolverMode::Jacobi,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SolverMode::GaussSeidel => "Gauss-Seidel",
+            SolverMode::Colored => "colored",
+            SolverMode::Jacobi => "Jacobi",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SystemParameters {
+    pub time_step: f64,
+    pub num_substeps: usize,
+    pub solver: SolverMode,
+    pub jacobi_relaxation: f64,
+    // Coulomb friction and restitution of the world_bounds walls
+    pub static_friction: f64,
+    pub dynamic_friction: f64,
+    pub restitution: f64,
+    pub world_bounds: (Vector3<f64>, Vector3<f64>),
+    pub gravity: Vector3<f64>,
+    // vertex - triangle contacts between boundary surfaces, within and across bodies
+    pub collisions: bool,
+    pub collision_compliance: f64,
+    pub collision_thickness: f64,
+}
+
+impl Default for SystemParameters {
+    fn default() -> Self {
+        SystemParameters {
+            time_step: 0.1 / 60.0,
+            num_substeps: 50,
+            solver: SolverMode::GaussSeidel,
+            jacobi_relaxation: 1.5,
+            static_friction: 0.6,
+            dynamic_friction: 0.4,
+            restitution: 0.0,
+            world_bounds: (Vector3::new(-5.0, 0.0, -5.0), Vector3::new(5.0, 10.0, 5.0)),
+            gravity: Vector3::new(0.0, -100.0, 0.0),
+            collisions: true,
+            collision_compliance: 0.0,
+            collision_thickness: 0.05,
+        }
+    }
+}
+
+// Material and placement of one body, the rest shape is the mesh moved by transform.
+#[derive(Debug, Clone)]
+pub struct BodyParameters {
+    pub material: MaterialModel,
+    pub elasticity: Elasticity,
+    pub density: f64,
+    pub transform: Isometry3<f64>,
+}
+
+impl Default for BodyParameters {
+    fn default() -> Self {
+        BodyParameters {
+            material: MaterialModel::StableNeoHookean,
+            elasticity: Elasticity::Young {
+                youngs_modulus: 5000.0,
+                poisson_ratio: 0.3,
+            },
+            density: 1.0,
+            transform: Isometry3::identity(),
+        }
+    }
+}
+
+impl BodyParameters {
+    // (mu, lambda)
+    pub fn lame(&self) -> (f64, f64) {
+        self.elasticity.lame()
+    }
+}
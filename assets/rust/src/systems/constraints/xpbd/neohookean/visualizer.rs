@@ -1,25 +1,65 @@
-use super::system::System;
+use super::colliders::{Collider, Shape};
 use crate::utils::tetra_mesh;
-use nalgebra::Vector3;
+use nalgebra::{Isometry3, Vector3};
 use three_d::prelude::*;
 use three_d::{
-    AmbientLight, Camera, CpuMaterial, CpuMesh, Cull, FrameInput, Gm, InstancedMesh, Instances,
-    Mat4, Mesh, PhysicalMaterial, Quat, SpotLight, Srgba, Vec3, Window,
+    AmbientLight, Camera, Context, CpuMaterial, CpuMesh, Cull, FrameInput, Geometry, Gm, Indices,
+    InstancedMesh, Instances, Mat4, Mesh, PhysicalMaterial, Positions, Quat, SpotLight, Srgba,
+    Vec3, Window,
 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    Wireframe,
+    Surface,
+    Both,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 3] = [RenderMode::Wireframe, RenderMode::Surface, RenderMode::Both];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RenderMode::Wireframe => "wireframe",
+            RenderMode::Surface => "surface",
+            RenderMode::Both => "both",
+        }
+    }
+
+    fn wireframe(&self) -> bool {
+        *self != RenderMode::Surface
+    }
+
+    fn surface(&self) -> bool {
+        *self != RenderMode::Wireframe
+    }
+}
+
 pub struct Visualizer {
     pub camera: Camera,
     pub plane: Gm<Mesh, PhysicalMaterial>,
     pub edges: InstancedMesh,
+    pub surface: Gm<Mesh, PhysicalMaterial>,
+    // one object per collider
+    pub colliders: Vec<Gm<Mesh, PhysicalMaterial>>,
+    pub mode: RenderMode,
     wireframe_material: PhysicalMaterial,
     ambient: AmbientLight,
     spot_light: SpotLight,
     instances: Instances,
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    // indexes of all bodies into the particle positions
+    edge_indexes: Vec<usize>,
+    surface_indexes: Vec<[usize; 3]>,
+    context: Context,
 }
 
 impl Visualizer {
     pub fn new(
         window: &Window,
-        _sys: &System,
+        pos: &[Vector3<f64>],
+        colliders: &[Collider],
         tetra_mesh: &tetra_mesh::TetraMesh,
         target: &three_d::Vector3<f32>,
         scene_radius: f32,
@@ -58,19 +98,25 @@ impl Visualizer {
             .unwrap();
         let plane = three_d::Gm::new(
             Mesh::new(&context, &cpu_plane),
-            PhysicalMaterial::new_opaque(&context, &CpuMaterial {
-                albedo: Srgba::new_opaque(200, 200, 250),
-                ..Default::default()
-            }),
+            PhysicalMaterial::new_opaque(
+                &context,
+                &CpuMaterial {
+                    albedo: Srgba::new_opaque(200, 200, 250),
+                    ..Default::default()
+                },
+            ),
         );
 
         // mesh
-        let mut wireframe_material = PhysicalMaterial::new_opaque(&context, &CpuMaterial {
-            albedo: Srgba::new_opaque(140, 40, 40),
-            roughness: 0.5,
-            metallic: 0.0,
-            ..Default::default()
-        });
+        let mut wireframe_material = PhysicalMaterial::new_opaque(
+            &context,
+            &CpuMaterial {
+                albedo: Srgba::new_opaque(140, 40, 40),
+                roughness: 0.5,
+                metallic: 0.0,
+                ..Default::default()
+            },
+        );
         wireframe_material.render_states.cull = Cull::Back;
         let mut cylinder = CpuMesh::cylinder(3);
         cylinder
@@ -83,27 +129,220 @@ impl Visualizer {
         instances
             .transformations
             .resize(tetra_mesh.edge_indexes.len() / 2, Mat4::identity());
-        Self::edge_transformations(
-            &mut instances,
-            &tetra_mesh.edge_indexes,
-            &tetra_mesh.positions,
+        Self::edge_transformations(&mut instances, &tetra_mesh.edge_indexes, pos);
+        let edges = InstancedMesh::new(&context, &instances, &cylinder);
+
+        // surface, all particles are uploaded so the tet indexes can be used as is
+        let positions: Vec<Vec3> = pos.iter().map(Self::to_vec3).collect();
+        let mut normals = vec![Vec3::zero(); positions.len()];
+        Self::surface_normals(&mut normals, &tetra_mesh.surface_indexes, &positions);
+        let surface_mesh =
+            Self::surface_mesh(&context, &positions, &normals, &tetra_mesh.surface_indexes);
+        let mut surface_material = PhysicalMaterial::new_opaque(
+            &context,
+            &CpuMaterial {
+                albedo: Srgba::new_opaque(200, 90, 80),
+                roughness: 0.6,
+                metallic: 0.0,
+                ..Default::default()
+            },
         );
-        let mut edges = InstancedMesh::new(&context, &instances, &cylinder);
+        surface_material.render_states.cull = Cull::Back;
+        let surface = Gm::new(surface_mesh, surface_material);
+
+        let colliders = colliders
+            .iter()
+            .map(|collider| Self::collider_object(&context, collider))
+            .collect();
+
         Visualizer {
             camera,
             plane,
             edges,
+            surface,
+            colliders,
+            mode: RenderMode::Surface,
             wireframe_material,
             ambient,
             spot_light,
             instances,
+            positions,
+            normals,
+            edge_indexes: tetra_mesh.edge_indexes.clone(),
+            surface_indexes: tetra_mesh.surface_indexes.clone(),
+            context,
+        }
+    }
+
+    // Adds the edges and surface of a body that was added to the system, its particles
+    // start at first.
+    pub fn add_body(
+        &mut self,
+        pos: &[Vector3<f64>],
+        colliders: &[Collider],
+        tetra_mesh: &tetra_mesh::TetraMesh,
+        first: usize,
+    ) {
+        self.edge_indexes
+            .extend(tetra_mesh.edge_indexes.iter().map(|&i| i + first));
+        self.surface_indexes.extend(
+            tetra_mesh
+                .surface_indexes
+                .iter()
+                .map(|tri| tri.map(|i| i + first)),
+        );
+        self.instances
+            .transformations
+            .resize(self.edge_indexes.len() / 2, Mat4::identity());
+        self.positions.resize(pos.len(), Vec3::zero());
+        self.normals.resize(pos.len(), Vec3::zero());
+        self.surface.geometry = Self::surface_mesh(
+            &self.context,
+            &self.positions,
+            &self.normals,
+            &self.surface_indexes,
+        );
+        // rebuild both representations regardless of the mode
+        let mode = self.mode;
+        self.mode = RenderMode::Both;
+        self.update(pos, colliders);
+        self.mode = mode;
+    }
+
+    fn surface_mesh(
+        context: &Context,
+        positions: &[Vec3],
+        normals: &[Vec3],
+        surface_indexes: &[[usize; 3]],
+    ) -> Mesh {
+        let cpu_surface = CpuMesh {
+            positions: Positions::F32(positions.to_vec()),
+            indices: Indices::U32(
+                surface_indexes
+                    .iter()
+                    .flatten()
+                    .map(|&i| i as u32)
+                    .collect(),
+            ),
+            normals: Some(normals.to_vec()),
+            ..Default::default()
+        };
+        Mesh::new(context, &cpu_surface)
+    }
+
+    // Mesh of a collider in its local frame, placed by its transformation.
+    fn collider_object(context: &Context, collider: &Collider) -> Gm<Mesh, PhysicalMaterial> {
+        let mut cpu_mesh = match &collider.shape {
+            Shape::Plane => {
+                let mut square = CpuMesh::square();
+                square
+                    .transform(Mat4::from_scale(5.0) * Mat4::from_angle_x(degrees(-90.0)))
+                    .unwrap();
+                square
+            }
+            Shape::Sphere { radius } => {
+                let mut sphere = CpuMesh::sphere(16);
+                sphere.transform(Mat4::from_scale(*radius as f32)).unwrap();
+                sphere
+            }
+            Shape::Capsule {
+                radius,
+                half_height,
+            } => {
+                // a sphere with the poles along y and the halves pulled apart,
+                // odd subdivisions leave no vertex on the equator
+                let mut capsule = CpuMesh::sphere(15);
+                capsule
+                    .transform(
+                        Mat4::from_scale(*radius as f32) * Mat4::from_angle_x(degrees(-90.0)),
+                    )
+                    .unwrap();
+                if let Positions::F32(positions) = &mut capsule.positions {
+                    for p in positions.iter_mut() {
+                        p.y += (*half_height as f32).copysign(p.y);
+                    }
+                }
+                capsule
+            }
+            Shape::Box { half_extents } => {
+                let mut cube = CpuMesh::cube();
+                cube.transform(Mat4::from_nonuniform_scale(
+                    half_extents.x as f32,
+                    half_extents.y as f32,
+                    half_extents.z as f32,
+                ))
+                .unwrap();
+                cube
+            }
+            Shape::Sdf(grid) => {
+                let surface = grid.zero_surface();
+                CpuMesh {
+                    positions: Positions::F32(
+                        surface.positions.iter().map(Self::to_vec3).collect(),
+                    ),
+                    indices: Indices::U32(
+                        surface
+                            .triangles
+                            .iter()
+                            .flatten()
+                            .map(|&i| i as u32)
+                            .collect(),
+                    ),
+                    ..Default::default()
+                }
+            }
+        };
+        if let Shape::Sdf(_) = collider.shape {
+            cpu_mesh.compute_normals();
+        }
+        let mut material = PhysicalMaterial::new_opaque(
+            context,
+            &CpuMaterial {
+                albedo: Srgba::new_opaque(110, 130, 160),
+                roughness: 0.7,
+                metallic: 0.0,
+                ..Default::default()
+            },
+        );
+        if !matches!(collider.shape, Shape::Plane) {
+            material.render_states.cull = Cull::Back;
+        }
+        let mut object = Gm::new(Mesh::new(context, &cpu_mesh), material);
+        object.set_transformation(Self::to_mat4(&collider.transform));
+        object
+    }
+
+    fn to_mat4(transform: &Isometry3<f64>) -> Mat4 {
+        let t = transform.translation.vector;
+        let q = transform.rotation;
+        Mat4::from_translation(three_d::vec3(t.x as f32, t.y as f32, t.z as f32))
+            * Mat4::from(Quat::new(q.w as f32, q.i as f32, q.j as f32, q.k as f32))
+    }
+
+    fn to_vec3(p: &Vector3<f64>) -> Vec3 {
+        three_d::vec3(p.x as f32, p.y as f32, p.z as f32)
+    }
+
+    // area weighted vertex normals
+    fn surface_normals(normals: &mut [Vec3], surface_indexes: &[[usize; 3]], positions: &[Vec3]) {
+        normals.fill(Vec3::zero());
+        for &[a, b, c] in surface_indexes {
+            let n = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+            normals[a] += n;
+            normals[b] += n;
+            normals[c] += n;
+        }
+        for n in normals.iter_mut() {
+            if n.magnitude2() > 0.0 {
+                *n = n.normalize();
+            }
         }
     }
 
     fn edge_transformations(
         instances: &mut Instances,
-        edge_indexes: &Vec<usize>,
-        positions: &Vec<Vector3<f64>>,
+        edge_indexes: &[usize],
+        positions: &[Vector3<f64>],
     ) {
         // вектор размерa edge_indexes/2
         for i in (0..edge_indexes.len()).step_by(2) {
@@ -123,26 +362,55 @@ impl Visualizer {
             ))
             * Mat4::from_nonuniform_scale((p1 - p2).magnitude(), 1.0, 1.0)
     }
-    pub fn update(&mut self, sys: &System, tetra_mesh: &tetra_mesh::TetraMesh) {
-        Self::edge_transformations(&mut self.instances, &tetra_mesh.edge_indexes, &sys.pos);
-        self.edges.set_instances(&self.instances);
+    pub fn update(&mut self, pos: &[Vector3<f64>], colliders: &[Collider]) {
+        if self.mode.wireframe() {
+            Self::edge_transformations(&mut self.instances, &self.edge_indexes, pos);
+            self.edges.set_instances(&self.instances);
+        }
+        if self.mode.surface() {
+            for (p, q) in self.positions.iter_mut().zip(pos) {
+                *p = Self::to_vec3(q);
+            }
+            Self::surface_normals(&mut self.normals, &self.surface_indexes, &self.positions);
+            self.surface.geometry.positions_mut().fill(&self.positions);
+            if let Some(normals) = self.surface.geometry.normals_mut() {
+                normals.fill(&self.normals);
+            }
+        }
+        for (object, collider) in self.colliders.iter_mut().zip(colliders) {
+            if collider.is_kinematic() {
+                object.set_transformation(Self::to_mat4(&collider.transform));
+            }
+        }
         // shadow
-        self.spot_light.generate_shadow_map(2048, &[&self.edges]);
+        let body: &dyn Geometry = if self.mode.surface() {
+            &self.surface
+        } else {
+            &self.edges
+        };
+        let colliders = self.colliders.iter().map(|object| object as &dyn Geometry);
+        self.spot_light
+            .generate_shadow_map(2048, colliders.chain(std::iter::once(body)));
     }
 
-    pub fn render(&mut self, frame_input: &FrameInput, _sys: &System) {
-        frame_input
-            .screen()
-            .clear(three_d::ClearState::color_and_depth(
-                1.0, 1.0, 1.0, 1.0, 1.0,
-            ))
-            .render_with_material(&self.wireframe_material, &self.camera, &[&self.edges], &[
-                &self.ambient,
-                &self.spot_light,
-            ])
-            .render(&self.camera, &self.plane, &[
-                &self.ambient,
-                &self.spot_light,
-            ]);
+    pub fn render(&mut self, frame_input: &FrameInput) {
+        let lights: [&dyn three_d::Light; 2] = [&self.ambient, &self.spot_light];
+        let screen = frame_input.screen();
+        screen.clear(three_d::ClearState::color_and_depth(
+            1.0, 1.0, 1.0, 1.0, 1.0,
+        ));
+        if self.mode.wireframe() {
+            screen.render_with_material(
+                &self.wireframe_material,
+                &self.camera,
+                [&self.edges],
+                &lights,
+            );
+        }
+        if self.mode.surface() {
+            screen.render(&self.camera, &self.surface, &lights);
+        }
+        screen.render(&self.camera, &self.colliders, &lights);
+        screen.render(&self.camera, &self.plane, &lights);
     }
 }
@@ -0,0 +1,146 @@
+use super::shape::Shape;
+use nalgebra::{Isometry3, Point3, Quaternion, UnitQuaternion, Vector3};
+
+pub struct RigidBody {
+    pub shape: Shape,
+    pub pose: Isometry3<f64>,
+    // pose at the start of the substep
+    pub prev_pose: Isometry3<f64>,
+    pub vel: Vector3<f64>,
+    // angular velocity in world space
+    pub omega: Vector3<f64>,
+    // zero for static bodies
+    pub inv_mass: f64,
+    // inverse principal moments in the body frame
+    pub inv_inertia: Vector3<f64>,
+}
+
+impl RigidBody {
+    pub fn new(shape: Shape, pose: Isometry3<f64>, density: f64) -> Self {
+        RigidBody {
+            shape,
+            pose,
+            prev_pose: pose,
+            vel: Vector3::zeros(),
+            omega: Vector3::zeros(),
+            inv_mass: 1.0 / (density * shape.volume()),
+            inv_inertia: (shape.unit_inertia() * density).map(|i| 1.0 / i),
+        }
+    }
+
+    // Does not move, other bodies still collide with it.
+    pub fn fixed(shape: Shape, pose: Isometry3<f64>) -> Self {
+        RigidBody {
+            inv_mass: 0.0,
+            inv_inertia: Vector3::zeros(),
+            ..RigidBody::new(shape, pose, 1.0)
+        }
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.inv_mass == 0.0
+    }
+
+    pub fn position(&self) -> Vector3<f64> {
+        self.pose.translation.vector
+    }
+
+    pub fn world_point(&self, local: &Vector3<f64>) -> Vector3<f64> {
+        (self.pose * Point3::from(*local)).coords
+    }
+
+    pub fn local_point(&self, world: &Vector3<f64>) -> Vector3<f64> {
+        self.pose
+            .inverse_transform_point(&Point3::from(*world))
+            .coords
+    }
+
+    pub fn velocity_at(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        self.vel + self.omega.cross(&(p - self.position()))
+    }
+
+    // I⁻¹ v with the inertia tensor rotated into world space
+    fn apply_inv_inertia(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        let q = self.pose.rotation;
+        q * self
+            .inv_inertia
+            .component_mul(&q.inverse_transform_vector(v))
+    }
+
+    // Generalized inverse mass along normal, for a correction at pos or, without pos,
+    // for a pure rotation about normal.
+    pub fn inverse_mass(&self, normal: &Vector3<f64>, pos: Option<&Vector3<f64>>) -> f64 {
+        match pos {
+            Some(p) => {
+                let rn = (p - self.position()).cross(normal);
+                self.inv_mass + rn.dot(&self.apply_inv_inertia(&rn))
+            }
+            None => normal.dot(&self.apply_inv_inertia(normal)),
+        }
+    }
+
+    // Applies the impulse corr at pos, or the angular impulse corr without pos, to
+    // the pose or, at velocity level, to the velocities.
+    pub fn apply_correction(
+        &mut self,
+        corr: &Vector3<f64>,
+        pos: Option<&Vector3<f64>>,
+        velocity_level: bool,
+    ) {
+        if self.is_static() {
+            return;
+        }
+        let angular = match pos {
+            Some(p) => (p - self.position()).cross(corr),
+            None => *corr,
+        };
+        let dw = self.apply_inv_inertia(&angular);
+        let dx = if pos.is_some() {
+            corr * self.inv_mass
+        } else {
+            Vector3::zeros()
+        };
+        if velocity_level {
+            self.vel += dx;
+            self.omega += dw;
+        } else {
+            self.pose.translation.vector += dx;
+            self.rotate(&dw, 1.0);
+        }
+    }
+
+    // q += ½ dt [ω, 0] q, then normalized
+    fn rotate(&mut self, omega: &Vector3<f64>, dt: f64) {
+        let q = *self.pose.rotation.quaternion();
+        let dq = Quaternion::from_parts(0.0, *omega) * q * (0.5 * dt);
+        self.pose.rotation = UnitQuaternion::new_normalize(q + dq);
+    }
+
+    pub fn integrate(&mut self, gravity: &Vector3<f64>, dt: f64) {
+        self.prev_pose = self.pose;
+        if self.is_static() {
+            return;
+        }
+        self.vel += gravity * dt;
+        self.pose.translation.vector += self.vel * dt;
+
+        // gyroscopic term of the Euler equations, in the body frame
+        let q = self.pose.rotation;
+        let w = q.inverse_transform_vector(&self.omega);
+        let inertia = self.inv_inertia.map(|i| 1.0 / i);
+        let torque = -w.cross(&inertia.component_mul(&w));
+        self.omega += q * self.inv_inertia.component_mul(&torque) * dt;
+        let omega = self.omega;
+        self.rotate(&omega, dt);
+    }
+
+    pub fn update_velocities(&mut self, dt: f64) {
+        if self.is_static() {
+            return;
+        }
+        self.vel = (self.position() - self.prev_pose.translation.vector) / dt;
+        let dq = self.pose.rotation * self.prev_pose.rotation.inverse();
+        let omega = dq.quaternion().imag() * (2.0 / dt);
+        self.omega = if dq.w >= 0.0 { omega } else { -omega };
+    }
+}
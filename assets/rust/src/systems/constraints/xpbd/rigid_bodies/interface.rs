@@ -0,0 +1,388 @@
+use super::body::RigidBody;
+use super::joint::{Joint, JointKind};
+use super::shape::Shape;
+use super::system::System;
+use super::system_parameters::SystemParameters;
+use super::visualizer::Visualizer;
+use crate::systems::constraints::xpbd::neohookean::interface::{
+    CustomIntersectionResult, OrbitControl, custom_pick,
+};
+use crate::utils::recorder::{InputEvent, Recorder};
+use crate::utils::{create_window, ui, ui::BaseState};
+use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
+use three_d::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpawnShape {
+    Box,
+    Sphere,
+    Capsule,
+}
+
+impl SpawnShape {
+    const ALL: [SpawnShape; 3] = [SpawnShape::Box, SpawnShape::Sphere, SpawnShape::Capsule];
+
+    fn name(&self) -> &'static str {
+        match self {
+            SpawnShape::Box => "box",
+            SpawnShape::Sphere => "sphere",
+            SpawnShape::Capsule => "capsule",
+        }
+    }
+
+    fn shape(&self) -> Shape {
+        match self {
+            SpawnShape::Box => Shape::Box {
+                half_extents: Vector3::new(0.5, 0.4, 0.3),
+            },
+            SpawnShape::Sphere => Shape::Sphere { radius: 0.4 },
+            SpawnShape::Capsule => Shape::Capsule {
+                radius: 0.25,
+                half_height: 0.5,
+            },
+        }
+    }
+}
+
+struct State {
+    base: BaseState,
+    spawn_shape: SpawnShape,
+    spawn: bool,
+    density: f64,
+    static_friction: f64,
+    dynamic_friction: f64,
+    restitution: f64,
+    joint_compliance: f64,
+}
+impl State {
+    pub fn new() -> Self {
+        let par = SystemParameters::default();
+        State {
+            base: BaseState::new(),
+            spawn_shape: SpawnShape::Box,
+            spawn: false,
+            density: par.density,
+            static_friction: par.static_friction,
+            dynamic_friction: par.dynamic_friction,
+            restitution: par.restitution,
+            joint_compliance: 0.0,
+        }
+    }
+}
+
+fn pose(x: f64, y: f64, z: f64, rotation: UnitQuaternion<f64>) -> Isometry3<f64> {
+    Isometry3::from_parts(Translation3::new(x, y, z), rotation)
+}
+
+// A box stack, a box resting on a fixed ramp, loose spheres and a capsule, a chain
+// of capsules on ball joints, a double pendulum of hinged plates and two boxes
+// welded by a fixed joint.
+fn build_scene(sys: &mut System) {
+    let density = sys.par.density;
+    let none = UnitQuaternion::identity();
+
+    let cube = Shape::Box {
+        half_extents: Vector3::repeat(0.5),
+    };
+    for k in 0..4 {
+        sys.add_body(RigidBody::new(
+            cube,
+            pose(-3.0, 0.5 + 1.01 * k as f64, -2.0, none),
+            density,
+        ));
+    }
+
+    let tilt = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 0.35);
+    let ramp = pose(0.5, 1.0, 2.5, tilt);
+    sys.add_body(RigidBody::fixed(
+        Shape::Box {
+            half_extents: Vector3::new(1.5, 0.1, 1.0),
+        },
+        ramp,
+    ));
+    let up = tilt * Vector3::y();
+    let on_ramp = ramp.translation.vector + up * 0.41;
+    sys.add_body(RigidBody::new(
+        Shape::Box {
+            half_extents: Vector3::repeat(0.3),
+        },
+        pose(on_ramp.x, on_ramp.y, on_ramp.z, tilt),
+        density,
+    ));
+
+    for (x, y, z) in [(-0.5, 4.0, -1.5), (0.3, 6.0, -1.2)] {
+        sys.add_body(RigidBody::new(
+            Shape::Sphere { radius: 0.5 },
+            pose(x, y, z, none),
+            density,
+        ));
+    }
+    sys.add_body(RigidBody::new(
+        Shape::Capsule {
+            radius: 0.3,
+            half_height: 0.6,
+        },
+        pose(
+            1.0,
+            3.0,
+            -1.0,
+            UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 1.0),
+        ),
+        density,
+    ));
+
+    // chain along -x, the capsule axes turned from y to x
+    let link = Shape::Capsule {
+        radius: 0.12,
+        half_height: 0.3,
+    };
+    let along_x = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), std::f64::consts::FRAC_PI_2);
+    let anchor = Vector3::new(3.5, 8.0, -2.0);
+    let mut prev = None;
+    for k in 0..5 {
+        let end = anchor - Vector3::x() * (0.84 * k as f64);
+        let c = end - Vector3::x() * 0.42;
+        let id = sys.add_body(RigidBody::new(link, pose(c.x, c.y, c.z, along_x), density));
+        let joint = Joint::new(
+            JointKind::Ball,
+            &sys.bodies,
+            prev,
+            Some(id),
+            end,
+            Vector3::z(),
+        );
+        sys.add_joint(joint);
+        prev = Some(id);
+    }
+
+    let plate = Shape::Box {
+        half_extents: Vector3::new(0.8, 0.05, 0.4),
+    };
+    let anchor = Vector3::new(1.5, 6.0, 2.0);
+    let mut prev = None;
+    for k in 0..2 {
+        let end = anchor + Vector3::x() * (1.6 * k as f64);
+        let c = end + Vector3::x() * 0.8;
+        let id = sys.add_body(RigidBody::new(plate, pose(c.x, c.y, c.z, none), density));
+        let joint = Joint::new(
+            JointKind::Hinge,
+            &sys.bodies,
+            prev,
+            Some(id),
+            end,
+            Vector3::z(),
+        );
+        sys.add_joint(joint);
+        prev = Some(id);
+    }
+
+    let a = sys.add_body(RigidBody::new(
+        Shape::Box {
+            half_extents: Vector3::new(0.6, 0.2, 0.2),
+        },
+        pose(-2.5, 5.0, 2.0, none),
+        density,
+    ));
+    let b = sys.add_body(RigidBody::new(
+        Shape::Box {
+            half_extents: Vector3::new(0.2, 0.6, 0.2),
+        },
+        pose(-2.1, 5.8, 2.0, none),
+        density,
+    ));
+    let joint = Joint::new(
+        JointKind::Fixed,
+        &sys.bodies,
+        Some(a),
+        Some(b),
+        Vector3::new(-2.1, 5.2, 2.0),
+        Vector3::z(),
+    );
+    sys.add_joint(joint);
+}
+
+// Drops a body above everything in the scene, rotated a bit more for every body.
+fn spawn_body(sys: &System, shape: Shape) -> RigidBody {
+    let n = sys.bodies.len() as f64;
+    let rotation = UnitQuaternion::from_axis_angle(
+        &nalgebra::Unit::new_normalize(Vector3::new(1.0, 0.0, 1.0)),
+        0.4 * n,
+    );
+    let r = shape.bounding_radius();
+    let top = sys
+        .bodies
+        .iter()
+        .filter(|body| body.position().x.abs() < 1.0 && body.position().z.abs() < 1.0)
+        .map(|body| body.position().y + body.shape.bounding_radius())
+        .fold(sys.par.world_bounds.0.y, f64::max);
+    let y = (top + 0.5 + r).min(sys.par.world_bounds.1.y - r);
+    RigidBody::new(shape, pose(0.0, y, 0.0, rotation), sys.par.density)
+}
+
+fn gui_logic(gui: &mut three_d::GUI, frame_input: &mut FrameInput, state: &mut State) {
+    ui::gui_logic(
+        gui,
+        frame_input,
+        &mut state.base,
+        |ui, _base_state| {
+            ui.horizontal(|ui| {
+                for shape in SpawnShape::ALL {
+                    ui.radio_value(
+                        &mut state.spawn_shape,
+                        shape,
+                        three_d::egui::RichText::new(shape.name()).color(ui::TEXT_COLOR),
+                    );
+                }
+                let spawn_b = three_d::egui::Button::new(
+                    three_d::egui::RichText::new("Spawn")
+                        .color(ui::TEXT_COLOR)
+                        .strong(),
+                )
+                .fill(ui::BUTTON_BACKGROUND);
+                if ui.add(spawn_b).clicked() {
+                    state.spawn = true;
+                }
+                ui.add(three_d::egui::Slider::new(&mut state.density, 0.1..=10.0));
+                ui.label(
+                    three_d::egui::RichText::new("density")
+                        .strong()
+                        .color(ui::TEXT_COLOR),
+                );
+            });
+        },
+        |ui, _base_state| {
+            ui.horizontal(|ui| {
+                for (value, name) in [
+                    (&mut state.static_friction, "static friction"),
+                    (&mut state.dynamic_friction, "dynamic friction"),
+                    (&mut state.restitution, "restitution"),
+                ] {
+                    ui.add(three_d::egui::Slider::new(value, 0.0..=1.0));
+                    ui.label(
+                        three_d::egui::RichText::new(name)
+                            .strong()
+                            .color(ui::TEXT_COLOR),
+                    );
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.add(
+                    three_d::egui::Slider::new(&mut state.joint_compliance, 0.0..=1e-2)
+                        .logarithmic(true),
+                );
+                ui.label(
+                    three_d::egui::RichText::new("joint compliance")
+                        .strong()
+                        .color(ui::TEXT_COLOR),
+                );
+            });
+        },
+    );
+}
+
+pub fn run(_canvas_id: &str) {
+    #[cfg(not(target_arch = "wasm32"))]
+    let window = create_window::create_window();
+    #[cfg(target_arch = "wasm32")]
+    let window = create_window::create_window(_canvas_id);
+    let context = window.gl();
+
+    let target = three_d::vec3(0.0, 3.0, 0.0);
+    let scene_radius = 20.0;
+
+    let mut control = OrbitControl::new(target, 0.1 * scene_radius, 100.0 * scene_radius, 0.005);
+    let mut sys = System::new(SystemParameters::default());
+    build_scene(&mut sys);
+    let mut state = State::new();
+    let mut gui = three_d::GUI::new(&context);
+    let mut fps_counter = ui::FPSCounter::new();
+    let mut recorder = Recorder::new();
+    let mut vis = Visualizer::new(&window, &sys.bodies, &target, scene_radius);
+    let mut picked = false;
+    let mut intersection_result = CustomIntersectionResult {
+        position: vec3(0.0, 0.0, 0.0),
+        depth: 0.0,
+        direction: vec3(0.0, 0.0, 0.0),
+        geometry_id: 0,
+        instance_id: 0,
+    };
+    window.render_loop(move |mut frame_input| {
+        gui_logic(&mut gui, &mut frame_input, &mut state);
+
+        vis.camera.set_viewport(frame_input.viewport);
+        for event in frame_input.events.iter() {
+            if let Event::MousePress {
+                button, position, ..
+            } = *event
+                && button == MouseButton::Left
+                && let Some(pick) = custom_pick(&context, &vis.camera, position, &vis.bodies)
+            {
+                picked = true;
+                intersection_result = pick;
+                let pos = nalgebra::Vector3::new(
+                    pick.position.x as f64,
+                    pick.position.y as f64,
+                    pick.position.z as f64,
+                );
+
+                sys.init_grab(pos);
+                sys.move_grab(pos);
+                recorder.record_input(InputEvent::InitGrab(pos));
+                recorder.record_input(InputEvent::MoveGrab(pos));
+            }
+            if let Event::MouseMotion { position, .. } = *event
+                && picked
+            {
+                let mut pos_new = vis.camera.position_at_pixel(position);
+                let dir_new = vis.camera.view_direction_at_pixel(position);
+                pos_new += dir_new * intersection_result.depth;
+                let pos =
+                    nalgebra::Vector3::new(pos_new.x as f64, pos_new.y as f64, pos_new.z as f64);
+                sys.move_grab(pos);
+                recorder.record_input(InputEvent::MoveGrab(pos));
+            }
+            if let Event::MouseRelease { button, .. } = *event
+                && button == MouseButton::Left
+            {
+                picked = false;
+                sys.release_grab();
+                recorder.record_input(InputEvent::ReleaseGrab);
+            }
+        }
+        if !picked {
+            control.handle_events(&mut vis.camera, &mut frame_input.events);
+        }
+
+        if state.base.reset {
+            sys.reset();
+            state.base.reset = false;
+        }
+
+        sys.par.density = state.density;
+        if state.spawn {
+            state.spawn = false;
+            let body = spawn_body(&sys, state.spawn_shape.shape());
+            vis.add_body(&body);
+            sys.add_body(body);
+        }
+
+        sys.par.static_friction = state.static_friction;
+        sys.par.dynamic_friction = state.dynamic_friction;
+        sys.par.restitution = state.restitution;
+        for joint in &mut sys.joints {
+            joint.compliance = state.joint_compliance;
+        }
+
+        if recorder.update(&mut sys, &mut state.base) {
+            vis.update(&sys.bodies);
+        }
+        if !state.base.stop {
+            state.base.fps = fps_counter.update(&frame_input);
+        }
+
+        vis.render(&frame_input);
+        frame_input.screen().write(|| gui.render()).unwrap();
+
+        FrameOutput::default()
+    });
+}
@@ -0,0 +1,55 @@
+use super::body::RigidBody;
+use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JointKind {
+    // the anchors coincide
+    Ball,
+    // the anchors coincide and the z axes of the joint frames stay aligned
+    Hinge,
+    // the joint frames coincide
+    Fixed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Joint {
+    pub kind: JointKind,
+    // None attaches to the world
+    pub body_a: Option<usize>,
+    pub body_b: Option<usize>,
+    // joint frames in the frames of the bodies, or in world space
+    pub frame_a: Isometry3<f64>,
+    pub frame_b: Isometry3<f64>,
+    pub compliance: f64,
+}
+
+impl Joint {
+    // Joint at the world space anchor, turning about axis for a hinge, with the bodies
+    // at their current poses as the rest state.
+    pub fn new(
+        kind: JointKind,
+        bodies: &[RigidBody],
+        body_a: Option<usize>,
+        body_b: Option<usize>,
+        anchor: Vector3<f64>,
+        axis: Vector3<f64>,
+    ) -> Self {
+        let rotation =
+            UnitQuaternion::rotation_between(&Vector3::z(), &axis).unwrap_or_else(|| {
+                UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f64::consts::PI)
+            });
+        let world = Isometry3::from_parts(Translation3::from(anchor), rotation);
+        let local = |body: Option<usize>| match body {
+            Some(i) => bodies[i].pose.inverse() * world,
+            None => world,
+        };
+        Joint {
+            kind,
+            body_a,
+            body_b,
+            frame_a: local(body_a),
+            frame_b: local(body_b),
+            compliance: 0.0,
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod body;
+pub mod interface;
+pub mod joint;
+pub mod shape;
+pub mod system;
+pub mod system_parameters;
+pub mod visualizer;
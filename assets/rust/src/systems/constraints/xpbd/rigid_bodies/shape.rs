@@ -0,0 +1,500 @@
+use nalgebra::{Isometry3, Point3, Vector3};
+use std::f64::consts::PI;
+
+// Shapes in the local frame of a body, centered at its center of mass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Sphere { radius: f64 },
+    // segment from -half_height to half_height along y, inflated by radius
+    Capsule { radius: f64, half_height: f64 },
+    Box { half_extents: Vector3<f64> },
+}
+
+impl Shape {
+    pub fn volume(&self) -> f64 {
+        match *self {
+            Shape::Sphere { radius } => 4.0 / 3.0 * PI * radius.powi(3),
+            Shape::Capsule {
+                radius,
+                half_height,
+            } => PI * radius * radius * (2.0 * half_height + 4.0 / 3.0 * radius),
+            Shape::Box { half_extents } => 8.0 * half_extents.product(),
+        }
+    }
+
+    // Principal moments of inertia for unit density.
+    pub fn unit_inertia(&self) -> Vector3<f64> {
+        match *self {
+            Shape::Sphere { radius } => Vector3::repeat(0.4 * self.volume() * radius * radius),
+            Shape::Capsule {
+                radius,
+                half_height,
+            } => {
+                // cylinder plus the two hemispheres moved to its ends
+                let (r2, h) = (radius * radius, 2.0 * half_height);
+                let m_cyl = PI * r2 * h;
+                let m_caps = 4.0 / 3.0 * PI * r2 * radius;
+                let axial = m_cyl * r2 / 2.0 + m_caps * 0.4 * r2;
+                let side = m_cyl * (r2 / 4.0 + h * h / 12.0)
+                    + m_caps * (0.4 * r2 + h * h / 4.0 + 3.0 * h * radius / 8.0);
+                Vector3::new(side, axial, side)
+            }
+            Shape::Box { half_extents } => {
+                let s = half_extents.component_mul(&half_extents);
+                Vector3::new(s.y + s.z, s.x + s.z, s.x + s.y) * (self.volume() / 3.0)
+            }
+        }
+    }
+
+    // radius of a sphere around the center enclosing the shape
+    pub fn bounding_radius(&self) -> f64 {
+        match *self {
+            Shape::Sphere { radius } => radius,
+            Shape::Capsule {
+                radius,
+                half_height,
+            } => radius + half_height,
+            Shape::Box { half_extents } => half_extents.norm(),
+        }
+    }
+
+    // Signed distance, negative inside.
+    pub fn distance(&self, p: &Vector3<f64>) -> f64 {
+        match *self {
+            Shape::Sphere { radius } => p.norm() - radius,
+            Shape::Capsule {
+                radius,
+                half_height,
+            } => (p - Vector3::new(0.0, p.y.clamp(-half_height, half_height), 0.0)).norm() - radius,
+            Shape::Box { half_extents } => {
+                let q = p.abs() - half_extents;
+                q.sup(&Vector3::zeros()).norm() + q.max().min(0.0)
+            }
+        }
+    }
+}
+
+// Touching surfaces of two shapes in world space. The normal points from b to a,
+// point_a is the point of a deepest inside b and point_b the matching point on the
+// surface of b, so the penetration depth is (point_b - point_a)·normal.
+#[derive(Debug, Clone, Copy)]
+pub struct ContactPoint {
+    pub point_a: Vector3<f64>,
+    pub point_b: Vector3<f64>,
+    pub normal: Vector3<f64>,
+}
+
+impl ContactPoint {
+    fn flipped(self) -> Self {
+        ContactPoint {
+            point_a: self.point_b,
+            point_b: self.point_a,
+            normal: -self.normal,
+        }
+    }
+}
+
+fn capsule_segment(pose: &Isometry3<f64>, half_height: f64) -> [Vector3<f64>; 2] {
+    [-half_height, half_height].map(|y| (pose * Point3::new(0.0, y, 0.0)).coords)
+}
+
+fn box_corners(pose: &Isometry3<f64>, half_extents: &Vector3<f64>) -> [Vector3<f64>; 8] {
+    std::array::from_fn(|i| {
+        let sign = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+        let local = Point3::new(
+            sign(1) * half_extents.x,
+            sign(2) * half_extents.y,
+            sign(4) * half_extents.z,
+        );
+        (pose * local).coords
+    })
+}
+
+fn closest_on_segment(p: &Vector3<f64>, a: &Vector3<f64>, b: &Vector3<f64>) -> Vector3<f64> {
+    let ab = b - a;
+    let len2 = ab.norm_squared();
+    if len2 == 0.0 {
+        return *a;
+    }
+    a + ab * ((p - a).dot(&ab) / len2).clamp(0.0, 1.0)
+}
+
+// Closest points of the segments p1 q1 and p2 q2 (Ericson, Real-Time Collision Detection).
+fn closest_between_segments(
+    p1: &Vector3<f64>,
+    q1: &Vector3<f64>,
+    p2: &Vector3<f64>,
+    q2: &Vector3<f64>,
+) -> (Vector3<f64>, Vector3<f64>) {
+    let d1 = q1 - p1;
+    let d2 = q2 - p2;
+    let r = p1 - p2;
+    let a = d1.norm_squared();
+    let e = d2.norm_squared();
+    let f = d2.dot(&r);
+    let eps = 1e-12;
+    let (s, t) = if a <= eps && e <= eps {
+        (0.0, 0.0)
+    } else if a <= eps {
+        (0.0, (f / e).clamp(0.0, 1.0))
+    } else {
+        let c = d1.dot(&r);
+        if e <= eps {
+            ((-c / a).clamp(0.0, 1.0), 0.0)
+        } else {
+            let b = d1.dot(&d2);
+            let denom = a * e - b * b;
+            let mut s = if denom > eps {
+                ((b * f - c * e) / denom).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let mut t = (b * s + f) / e;
+            if t < 0.0 {
+                t = 0.0;
+                s = (-c / a).clamp(0.0, 1.0);
+            } else if t > 1.0 {
+                t = 1.0;
+                s = ((b - c) / a).clamp(0.0, 1.0);
+            }
+            (s, t)
+        }
+    };
+    (p1 + d1 * s, p2 + d2 * t)
+}
+
+fn sphere_sphere(
+    center_a: &Vector3<f64>,
+    radius_a: f64,
+    center_b: &Vector3<f64>,
+    radius_b: f64,
+) -> Option<ContactPoint> {
+    let d = center_a - center_b;
+    let dist = d.norm();
+    if dist >= radius_a + radius_b {
+        return None;
+    }
+    let normal = if dist > 0.0 { d / dist } else { Vector3::y() };
+    Some(ContactPoint {
+        point_a: center_a - normal * radius_a,
+        point_b: center_b + normal * radius_b,
+        normal,
+    })
+}
+
+// A sphere against a box, a corner is a sphere of radius 0. A center inside the
+// box is pushed out through the closest face.
+fn sphere_box(
+    center: &Vector3<f64>,
+    radius: f64,
+    pose: &Isometry3<f64>,
+    half_extents: &Vector3<f64>,
+) -> Option<ContactPoint> {
+    let p = pose.inverse_transform_point(&Point3::from(*center)).coords;
+    let mut q = p.zip_map(half_extents, |x, h| x.clamp(-h, h));
+    let local_normal = if q != p {
+        let d = p - q;
+        let dist = d.norm();
+        if dist >= radius {
+            return None;
+        }
+        d / dist
+    } else {
+        let i = (half_extents - p.abs()).imin();
+        let mut n = Vector3::zeros();
+        n[i] = if p[i] < 0.0 { -1.0 } else { 1.0 };
+        q[i] = n[i] * half_extents[i];
+        n
+    };
+    let normal = pose.rotation * local_normal;
+    Some(ContactPoint {
+        point_a: center - normal * radius,
+        point_b: (pose * Point3::from(q)).coords,
+        normal,
+    })
+}
+
+// Point of the segment closest to the box, by alternating projections.
+fn segment_point_near_box(
+    a: &Vector3<f64>,
+    b: &Vector3<f64>,
+    pose: &Isometry3<f64>,
+    half_extents: &Vector3<f64>,
+) -> Vector3<f64> {
+    let mut p = closest_on_segment(&pose.translation.vector, a, b);
+    for _ in 0..4 {
+        let local = pose.inverse_transform_point(&Point3::from(p)).coords;
+        let clamped = local.zip_map(half_extents, |x, h| x.clamp(-h, h));
+        if clamped == local {
+            break;
+        }
+        p = closest_on_segment(&(pose * Point3::from(clamped)).coords, a, b);
+    }
+    p
+}
+
+// Keeps the part of the polygon with sign * p[axis] <= limit, in the local frame of a box.
+fn clip_polygon(polygon: &[Vector3<f64>], axis: usize, sign: f64, limit: f64) -> Vec<Vector3<f64>> {
+    let mut out = Vec::with_capacity(polygon.len() + 1);
+    for (k, p) in polygon.iter().enumerate() {
+        let q = &polygon[(k + 1) % polygon.len()];
+        let (dp, dq) = (sign * p[axis] - limit, sign * q[axis] - limit);
+        if dp <= 0.0 {
+            out.push(*p);
+        }
+        // only a strict crossing adds a point, a vertex on the limit is kept once
+        if (dp < 0.0 && dq > 0.0) || (dp > 0.0 && dq < 0.0) {
+            out.push(p + (q - p) * (dp / (dp - dq)));
+        }
+    }
+    out
+}
+
+// Face k of the reference box facing normal against the incident box, the
+// incident face clipped to the reference face. Contacts have a on the incident box.
+fn face_contacts(
+    ref_pose: &Isometry3<f64>,
+    ref_half: &Vector3<f64>,
+    k: usize,
+    normal: &Vector3<f64>,
+    inc_pose: &Isometry3<f64>,
+    inc_half: &Vector3<f64>,
+    out: &mut Vec<ContactPoint>,
+) {
+    // incident face, the one most against the normal
+    let inc_axes: [Vector3<f64>; 3] =
+        std::array::from_fn(|i| inc_pose.rotation * Vector3::ith(i, 1.0));
+    let m = (0..3)
+        .max_by(|&i, &j| {
+            inc_axes[i]
+                .dot(normal)
+                .abs()
+                .total_cmp(&inc_axes[j].dot(normal).abs())
+        })
+        .unwrap();
+    let s = -inc_axes[m].dot(normal).signum();
+    let (u, v) = ((m + 1) % 3, (m + 2) % 3);
+    let center = inc_pose.translation.vector + inc_axes[m] * (s * inc_half[m]);
+    let mut polygon: Vec<Vector3<f64>> = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+        .iter()
+        .map(|&(a, b)| {
+            let p = center + inc_axes[u] * (a * inc_half[u]) + inc_axes[v] * (b * inc_half[v]);
+            ref_pose.inverse_transform_point(&Point3::from(p)).coords
+        })
+        .collect();
+
+    for axis in [(k + 1) % 3, (k + 2) % 3] {
+        for sign in [-1.0, 1.0] {
+            polygon = clip_polygon(&polygon, axis, sign, ref_half[axis]);
+        }
+    }
+    let sign_k = ref_pose.rotation.inverse_transform_vector(normal)[k].signum();
+    for q in polygon {
+        if ref_half[k] - sign_k * q[k] > 0.0 {
+            let mut on_face = q;
+            on_face[k] = sign_k * ref_half[k];
+            out.push(ContactPoint {
+                point_a: (ref_pose * Point3::from(q)).coords,
+                point_b: (ref_pose * Point3::from(on_face)).coords,
+                normal: *normal,
+            });
+        }
+    }
+}
+
+// Separating axis test over the face normals and edge cross products, then the
+// clipped incident face for a face axis or the closest points of two edges.
+fn box_box(
+    pose_a: &Isometry3<f64>,
+    half_a: &Vector3<f64>,
+    pose_b: &Isometry3<f64>,
+    half_b: &Vector3<f64>,
+    out: &mut Vec<ContactPoint>,
+) {
+    let axes_a: [Vector3<f64>; 3] = std::array::from_fn(|i| pose_a.rotation * Vector3::ith(i, 1.0));
+    let axes_b: [Vector3<f64>; 3] = std::array::from_fn(|i| pose_b.rotation * Vector3::ith(i, 1.0));
+    let d = pose_a.translation.vector - pose_b.translation.vector;
+    let radius = |axes: &[Vector3<f64>; 3], half: &Vector3<f64>, n: &Vector3<f64>| {
+        (0..3).map(|i| half[i] * axes[i].dot(n).abs()).sum::<f64>()
+    };
+    // overlap along n, turned to point from b to a
+    let overlap = |n: Vector3<f64>| {
+        let n = if n.dot(&d) < 0.0 { -n } else { n };
+        (
+            radius(&axes_a, half_a, &n) + radius(&axes_b, half_b, &n) - n.dot(&d),
+            n,
+        )
+    };
+
+    // faces 0..3 of a, 3..6 of b
+    let mut face = (f64::MAX, Vector3::zeros(), 0);
+    for k in 0..6 {
+        let axis = if k < 3 { axes_a[k] } else { axes_b[k - 3] };
+        let (depth, n) = overlap(axis);
+        if depth < 0.0 {
+            return;
+        }
+        if depth < face.0 {
+            face = (depth, n, k);
+        }
+    }
+    let mut edge = (f64::MAX, Vector3::zeros(), 0, 0);
+    for (i, axis_a) in axes_a.iter().enumerate() {
+        for (j, axis_b) in axes_b.iter().enumerate() {
+            // parallel edges are covered by the faces
+            let Some(axis) = axis_a.cross(axis_b).try_normalize(1e-6) else {
+                continue;
+            };
+            let (depth, n) = overlap(axis);
+            if depth < 0.0 {
+                return;
+            }
+            if depth < edge.0 {
+                edge = (depth, n, i, j);
+            }
+        }
+    }
+
+    // faces give more stable contacts, edges only when clearly shallower
+    if edge.0 + 1e-3 < 0.95 * face.0 {
+        let (_, n, i, j) = edge;
+        let edge_of = |pose: &Isometry3<f64>,
+                       axes: &[Vector3<f64>; 3],
+                       half: &Vector3<f64>,
+                       k: usize,
+                       towards: Vector3<f64>| {
+            let mut c = pose.translation.vector;
+            for l in 0..3 {
+                if l != k {
+                    c += axes[l] * (half[l] * axes[l].dot(&towards).signum());
+                }
+            }
+            (c - axes[k] * half[k], c + axes[k] * half[k])
+        };
+        let (p1, q1) = edge_of(pose_a, &axes_a, half_a, i, -n);
+        let (p2, q2) = edge_of(pose_b, &axes_b, half_b, j, n);
+        let (point_a, point_b) = closest_between_segments(&p1, &q1, &p2, &q2);
+        out.push(ContactPoint {
+            point_a,
+            point_b,
+            normal: n,
+        });
+    } else if face.2 < 3 {
+        let first = out.len();
+        face_contacts(pose_a, half_a, face.2, &-face.1, pose_b, half_b, out);
+        for contact in &mut out[first..] {
+            *contact = contact.flipped();
+        }
+    } else {
+        face_contacts(pose_b, half_b, face.2 - 3, &face.1, pose_a, half_a, out);
+    }
+}
+
+// Contacts of a shape with the half space normal·x >= offset, one per sphere of a
+// capsule and per corner of a box.
+pub fn plane_contacts(
+    shape: &Shape,
+    pose: &Isometry3<f64>,
+    normal: &Vector3<f64>,
+    offset: f64,
+    out: &mut Vec<ContactPoint>,
+) {
+    let mut add = |center: &Vector3<f64>, radius: f64| {
+        let point_a = center - normal * radius;
+        let depth = offset - normal.dot(&point_a);
+        if depth > 0.0 {
+            out.push(ContactPoint {
+                point_a,
+                point_b: point_a + normal * depth,
+                normal: *normal,
+            });
+        }
+    };
+    match *shape {
+        Shape::Sphere { radius } => add(&pose.translation.vector, radius),
+        Shape::Capsule {
+            radius,
+            half_height,
+        } => {
+            for end in capsule_segment(pose, half_height) {
+                add(&end, radius);
+            }
+        }
+        Shape::Box { half_extents } => {
+            for corner in box_corners(pose, &half_extents) {
+                add(&corner, 0.0);
+            }
+        }
+    }
+}
+
+// Contacts between two shapes.
+pub fn shape_contacts(
+    shape_a: &Shape,
+    pose_a: &Isometry3<f64>,
+    shape_b: &Shape,
+    pose_b: &Isometry3<f64>,
+    out: &mut Vec<ContactPoint>,
+) {
+    let center_a = pose_a.translation.vector;
+    let center_b = pose_b.translation.vector;
+    match (*shape_a, *shape_b) {
+        (Shape::Sphere { radius: ra }, Shape::Sphere { radius: rb }) => {
+            out.extend(sphere_sphere(&center_a, ra, &center_b, rb));
+        }
+        (
+            Shape::Sphere { radius: ra },
+            Shape::Capsule {
+                radius: rb,
+                half_height,
+            },
+        ) => {
+            let [p, q] = capsule_segment(pose_b, half_height);
+            let c = closest_on_segment(&center_a, &p, &q);
+            out.extend(sphere_sphere(&center_a, ra, &c, rb));
+        }
+        (
+            Shape::Capsule {
+                radius: ra,
+                half_height: ha,
+            },
+            Shape::Capsule {
+                radius: rb,
+                half_height: hb,
+            },
+        ) => {
+            let [p1, q1] = capsule_segment(pose_a, ha);
+            let [p2, q2] = capsule_segment(pose_b, hb);
+            let (c1, c2) = closest_between_segments(&p1, &q1, &p2, &q2);
+            out.extend(sphere_sphere(&c1, ra, &c2, rb));
+        }
+        (Shape::Sphere { radius }, Shape::Box { half_extents }) => {
+            out.extend(sphere_box(&center_a, radius, pose_b, &half_extents));
+        }
+        (
+            Shape::Capsule {
+                radius,
+                half_height,
+            },
+            Shape::Box { half_extents },
+        ) => {
+            // both ends and the point closest to the box, a lying capsule gets two
+            let [p, q] = capsule_segment(pose_a, half_height);
+            let c = segment_point_near_box(&p, &q, pose_b, &half_extents);
+            for center in [p, q, c] {
+                out.extend(sphere_box(&center, radius, pose_b, &half_extents));
+            }
+        }
+        (Shape::Box { half_extents: ha }, Shape::Box { half_extents: hb }) => {
+            box_box(pose_a, &ha, pose_b, &hb, out);
+        }
+        // the remaining pairs are the ones above with a and b swapped
+        _ => {
+            let first = out.len();
+            shape_contacts(shape_b, pose_b, shape_a, pose_a, out);
+            for contact in &mut out[first..] {
+                *contact = contact.flipped();
+            }
+        }
+    }
+}
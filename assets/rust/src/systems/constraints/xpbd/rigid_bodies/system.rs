@@ -0,0 +1,541 @@
+use super::body::RigidBody;
+use super::joint::{Joint, JointKind};
+use super::shape::{self, ContactPoint};
+use super::system_parameters::SystemParameters;
+use crate::systems::collisions::broadphase::aabb_tree::{Aabb, AabbTree};
+use crate::utils::recorder::{self, InputEvent, Recordable};
+use nalgebra::{Isometry3, Point3, Unit, Vector3};
+
+// of the spring pulling the grabbed point to the mouse
+const GRAB_COMPLIANCE: f64 = 1e-6;
+
+// Contact solved in the current substep, kept for the velocity pass.
+struct Contact {
+    body_a: usize,
+    // None for the walls
+    body_b: Option<usize>,
+    // contact points in the body frames, point b in world space for the walls
+    local_a: Vector3<f64>,
+    local_b: Vector3<f64>,
+    normal: Vector3<f64>,
+    depth: f64,
+    // relative normal velocity before the solve, for restitution
+    vn: f64,
+    // normal Lagrange multiplier summed over the sweeps, bounds static friction
+    lambda_n: f64,
+}
+
+// Gauss-Seidel sweeps over the contacts. The order alternates, a manifold point
+// that is always solved first tilts resting stacks.
+const NUM_SWEEPS: usize = 2;
+
+fn sweep_order(sweep: usize, len: usize) -> impl Iterator<Item = usize> {
+    let backward = !sweep.is_multiple_of(2);
+    (0..len).map(move |k| if backward { len - 1 - k } else { k })
+}
+
+pub struct System {
+    pub par: SystemParameters,
+    pub bodies: Vec<RigidBody>,
+    pub joints: Vec<Joint>,
+    orig_poses: Vec<Isometry3<f64>>,
+    contacts: Vec<Contact>,
+    contact_points: Vec<ContactPoint>,
+    tree: AabbTree<3>,
+    // tree proxy of every body
+    proxies: Vec<usize>,
+    overlaps: Vec<usize>,
+    grab_id: i32,
+    // grabbed point in the body frame
+    grab_local: Vector3<f64>,
+    grab_pos: Vector3<f64>,
+}
+
+impl System {
+    pub fn new(par: SystemParameters) -> Self {
+        System {
+            par,
+            bodies: Vec::new(),
+            joints: Vec::new(),
+            orig_poses: Vec::new(),
+            contacts: Vec::new(),
+            contact_points: Vec::new(),
+            tree: AabbTree::new(0.1),
+            proxies: Vec::new(),
+            overlaps: Vec::new(),
+            grab_id: -1,
+            grab_local: Vector3::zeros(),
+            grab_pos: Vector3::zeros(),
+        }
+    }
+
+    pub fn add_body(&mut self, body: RigidBody) -> usize {
+        let id = self.bodies.len();
+        self.proxies
+            .push(self.tree.insert(Self::aabb(&body, 0.0), id));
+        self.orig_poses.push(body.pose);
+        self.bodies.push(body);
+        id
+    }
+
+    pub fn add_joint(&mut self, joint: Joint) {
+        self.joints.push(joint);
+    }
+
+    pub fn reset(&mut self) {
+        for (body, pose) in self.bodies.iter_mut().zip(&self.orig_poses) {
+            body.pose = *pose;
+            body.prev_pose = *pose;
+            body.vel = Vector3::zeros();
+            body.omega = Vector3::zeros();
+        }
+        self.contacts.clear();
+        self.contact_points.clear();
+        self.grab_id = -1;
+        self.grab_local = Vector3::zeros();
+        self.grab_pos = Vector3::zeros();
+    }
+
+    // bounding box of the body and its motion in the next dt
+    fn aabb(body: &RigidBody, dt: f64) -> Aabb<3> {
+        let r = body.shape.bounding_radius() + body.vel.norm() * dt;
+        Aabb::from_sphere(&body.position(), r)
+    }
+
+    fn pose(&self, body: Option<usize>) -> Isometry3<f64> {
+        body.map_or(Isometry3::identity(), |i| self.bodies[i].pose)
+    }
+
+    fn prev_pose(&self, body: Option<usize>) -> Isometry3<f64> {
+        body.map_or(Isometry3::identity(), |i| self.bodies[i].prev_pose)
+    }
+
+    fn velocity_at(&self, body: Option<usize>, p: &Vector3<f64>) -> Vector3<f64> {
+        body.map_or(Vector3::zeros(), |i| self.bodies[i].velocity_at(p))
+    }
+
+    fn pair_inverse_mass(
+        &self,
+        a: Option<usize>,
+        b: Option<usize>,
+        normal: &Vector3<f64>,
+        points: Option<[Vector3<f64>; 2]>,
+    ) -> f64 {
+        let w = |body: Option<usize>, k: usize| {
+            body.map_or(0.0, |i| {
+                self.bodies[i].inverse_mass(normal, points.as_ref().map(|p| &p[k]))
+            })
+        };
+        w(a, 0) + w(b, 1)
+    }
+
+    // Moves a along corr and b against it, at the world points or, without points,
+    // by rotation only. alpha is the compliance over dt², zero at velocity level.
+    // Returns the magnitude of the Lagrange multiplier update.
+    fn apply_pair_correction(
+        &mut self,
+        a: Option<usize>,
+        b: Option<usize>,
+        corr: &Vector3<f64>,
+        alpha: f64,
+        points: Option<[Vector3<f64>; 2]>,
+        velocity_level: bool,
+    ) -> f64 {
+        let Some((normal, c)) = Unit::try_new_and_get(*corr, 1e-12) else {
+            return 0.0;
+        };
+        let w = self.pair_inverse_mass(a, b, &normal, points);
+        if w == 0.0 {
+            return 0.0;
+        }
+        let lambda = c / (w + alpha);
+        let impulse = normal.into_inner() * lambda;
+        if let Some(i) = a {
+            let pos = points.as_ref().map(|p| &p[0]);
+            self.bodies[i].apply_correction(&impulse, pos, velocity_level);
+        }
+        if let Some(i) = b {
+            let pos = points.as_ref().map(|p| &p[1]);
+            self.bodies[i].apply_correction(&-impulse, pos, velocity_level);
+        }
+        lambda
+    }
+
+    fn solve_joints(&mut self, dt: f64) {
+        for k in 0..self.joints.len() {
+            let Joint {
+                kind,
+                body_a,
+                body_b,
+                frame_a,
+                frame_b,
+                compliance,
+            } = self.joints[k].clone();
+            let alpha = compliance / (dt * dt);
+
+            // orientation first, rotating a towards b
+            let rot_a = self.pose(body_a).rotation * frame_a.rotation;
+            let rot_b = self.pose(body_b).rotation * frame_b.rotation;
+            let corr = match kind {
+                JointKind::Ball => Vector3::zeros(),
+                JointKind::Hinge => (rot_a * Vector3::z()).cross(&(rot_b * Vector3::z())),
+                JointKind::Fixed => {
+                    let dq = rot_b * rot_a.inverse();
+                    let omega = dq.quaternion().imag() * 2.0;
+                    if dq.w >= 0.0 { omega } else { -omega }
+                }
+            };
+            self.apply_pair_correction(body_a, body_b, &corr, alpha, None, false);
+
+            let p_a = (self.pose(body_a) * frame_a * Point3::origin()).coords;
+            let p_b = (self.pose(body_b) * frame_b * Point3::origin()).coords;
+            self.apply_pair_correction(
+                body_a,
+                body_b,
+                &(p_b - p_a),
+                alpha,
+                Some([p_a, p_b]),
+                false,
+            );
+        }
+    }
+
+    fn solve_grab(&mut self, dt: f64) {
+        if self.grab_id < 0 {
+            return;
+        }
+        let id = self.grab_id as usize;
+        let p = self.bodies[id].world_point(&self.grab_local);
+        let alpha = GRAB_COMPLIANCE / (dt * dt);
+        self.apply_pair_correction(
+            Some(id),
+            None,
+            &(self.grab_pos - p),
+            alpha,
+            Some([p, self.grab_pos]),
+            false,
+        );
+    }
+
+    fn connected(&self, a: usize, b: usize) -> bool {
+        self.joints.iter().any(|joint| {
+            (joint.body_a == Some(a) && joint.body_b == Some(b))
+                || (joint.body_a == Some(b) && joint.body_b == Some(a))
+        })
+    }
+
+    // Contact points in the body frames, point b in world space for the walls, so
+    // that corrections earlier in the substep move them along with the bodies.
+    fn anchor(&self, a: usize, b: Option<usize>, cp: &ContactPoint) -> [Vector3<f64>; 2] {
+        let local_a = self.bodies[a].local_point(&cp.point_a);
+        let local_b = match b {
+            Some(i) => self.bodies[i].local_point(&cp.point_b),
+            None => cp.point_b,
+        };
+        [local_a, local_b]
+    }
+
+    // World points and depth of a contact at the current poses.
+    fn measure(
+        &self,
+        a: usize,
+        b: Option<usize>,
+        [local_a, local_b]: [Vector3<f64>; 2],
+        n: &Vector3<f64>,
+    ) -> ([Vector3<f64>; 2], f64) {
+        let p_a = self.bodies[a].world_point(&local_a);
+        let p_b = (self.pose(b) * Point3::from(local_b)).coords;
+        ([p_a, p_b], (p_b - p_a).dot(n))
+    }
+
+    // Static friction undoes the tangential motion of the contact points in this
+    // substep while it stays inside the friction cone.
+    fn solve_static_friction(
+        &mut self,
+        a: usize,
+        b: Option<usize>,
+        [local_a, local_b]: [Vector3<f64>; 2],
+        n: Vector3<f64>,
+        lambda_n: f64,
+    ) {
+        let point =
+            |pose: Isometry3<f64>, local: &Vector3<f64>| (pose * Point3::from(*local)).coords;
+        let p_a = point(self.pose(Some(a)), &local_a);
+        let p_b = point(self.pose(b), &local_b);
+        let dp = (p_a - point(self.prev_pose(Some(a)), &local_a))
+            - (p_b - point(self.prev_pose(b), &local_b));
+        let dp_t = dp - n * n.dot(&dp);
+        if let Some(t) = dp_t.try_normalize(1e-12) {
+            let lambda_t = dp_t.norm() / self.pair_inverse_mass(Some(a), b, &t, Some([p_a, p_b]));
+            if lambda_t < self.par.static_friction * lambda_n {
+                self.apply_pair_correction(Some(a), b, &-dp_t, 0.0, Some([p_a, p_b]), false);
+            }
+        }
+    }
+
+    // Pushes the points of a pair out at the current poses. The contacts that were
+    // solved are kept for the friction and velocity passes.
+    fn solve_pair(&mut self, a: usize, b: Option<usize>, points: &[ContactPoint]) {
+        let mut pair: Vec<Contact> = points
+            .iter()
+            .map(|cp| {
+                let [local_a, local_b] = self.anchor(a, b, cp);
+                Contact {
+                    body_a: a,
+                    body_b: b,
+                    local_a,
+                    local_b,
+                    normal: cp.normal,
+                    depth: 0.0,
+                    vn: 0.0,
+                    lambda_n: 0.0,
+                }
+            })
+            .collect();
+        for sweep in 0..NUM_SWEEPS {
+            for k in sweep_order(sweep, pair.len()) {
+                let c = &mut pair[k];
+                let n = c.normal;
+                let ([p_a, p_b], depth) = self.measure(a, b, [c.local_a, c.local_b], &n);
+                if depth <= 0.0 {
+                    continue;
+                }
+                // depth and velocity before the first correction
+                if c.lambda_n == 0.0 {
+                    c.depth = depth;
+                    c.vn = n.dot(&(self.velocity_at(Some(a), &p_a) - self.velocity_at(b, &p_b)));
+                }
+                let corr = n * depth;
+                c.lambda_n +=
+                    self.apply_pair_correction(Some(a), b, &corr, 0.0, Some([p_a, p_b]), false);
+            }
+        }
+        self.contacts
+            .extend(pair.into_iter().filter(|c| c.lambda_n > 0.0));
+    }
+
+    fn solve_contacts(&mut self, dt: f64) {
+        self.contacts.clear();
+        let (lo, hi) = self.par.world_bounds;
+        let mut points = std::mem::take(&mut self.contact_points);
+        let mut overlaps = std::mem::take(&mut self.overlaps);
+        for a in 0..self.bodies.len() {
+            let aabb = Self::aabb(&self.bodies[a], dt);
+            self.tree.update(self.proxies[a], aabb);
+        }
+        for a in 0..self.bodies.len() {
+            if self.bodies[a].is_static() {
+                continue;
+            }
+            points.clear();
+            for i in 0..3 {
+                let mut normal = Vector3::zeros();
+                normal[i] = 1.0;
+                let (shape, pose) = (&self.bodies[a].shape, &self.bodies[a].pose);
+                shape::plane_contacts(shape, pose, &normal, lo[i], &mut points);
+                shape::plane_contacts(shape, pose, &-normal, -hi[i], &mut points);
+            }
+            self.solve_pair(a, None, &points);
+
+            let aabb = *self.tree.fat_aabb(self.proxies[a]);
+            self.tree.query_overlap(&aabb, &mut overlaps);
+            for &b in &overlaps {
+                // every dynamic pair once, static bodies only against dynamic ones
+                if b == a || (b < a && !self.bodies[b].is_static()) || self.connected(a, b) {
+                    continue;
+                }
+                points.clear();
+                let (body_a, body_b) = (&self.bodies[a], &self.bodies[b]);
+                shape::shape_contacts(
+                    &body_a.shape,
+                    &body_a.pose,
+                    &body_b.shape,
+                    &body_b.pose,
+                    &mut points,
+                );
+                self.solve_pair(a, Some(b), &points);
+            }
+        }
+        self.contact_points = points;
+        self.overlaps = overlaps;
+        // static friction once every pair is pushed out
+        for sweep in 0..NUM_SWEEPS {
+            for k in sweep_order(sweep, self.contacts.len()) {
+                let c = &self.contacts[k];
+                let (a, b, n) = (c.body_a, c.body_b, c.normal);
+                self.solve_static_friction(a, b, [c.local_a, c.local_b], n, c.lambda_n);
+            }
+        }
+    }
+
+    // Dynamic friction and restitution on the velocities of the contact points.
+    fn solve_contact_velocities(&mut self, dt: f64) {
+        let contacts = std::mem::take(&mut self.contacts);
+        for sweep in 0..NUM_SWEEPS {
+            for c in sweep_order(sweep, contacts.len()).map(|k| &contacts[k]) {
+                let a = Some(c.body_a);
+                let p_a = self.bodies[c.body_a].world_point(&c.local_a);
+                let p_b = (self.pose(c.body_b) * Point3::from(c.local_b)).coords;
+                let v = self.velocity_at(a, &p_a) - self.velocity_at(c.body_b, &p_b);
+                let vn = c.normal.dot(&v);
+                let vt = v - c.normal * vn;
+                let vt_len = vt.norm();
+                let mut dv = Vector3::zeros();
+                // the friction bound holds for the whole pass, not per sweep
+                if vt_len > 0.0 && sweep == 0 {
+                    dv -= vt * ((self.par.dynamic_friction * c.depth / dt).min(vt_len) / vt_len);
+                }
+                // resting contacts do not bounce
+                let e = if c.vn.abs() > 2.0 * self.par.gravity.norm() * dt {
+                    self.par.restitution
+                } else {
+                    0.0
+                };
+                dv += c.normal * (-vn + (-e * c.vn).max(0.0));
+                self.apply_pair_correction(a, c.body_b, &dv, 0.0, Some([p_a, p_b]), true);
+            }
+        }
+        self.contacts = contacts;
+    }
+
+    pub fn substep(&mut self) {
+        let dt = self.par.time_step / self.par.num_substeps as f64;
+        let gravity = self.par.gravity;
+        for body in &mut self.bodies {
+            body.integrate(&gravity, dt);
+        }
+        self.solve_joints(dt);
+        self.solve_grab(dt);
+        self.solve_contacts(dt);
+        for body in &mut self.bodies {
+            body.update_velocities(dt);
+        }
+        self.solve_contact_velocities(dt);
+    }
+
+    pub fn simulate(&mut self) {
+        for _ in 0..self.par.num_substeps {
+            self.substep();
+        }
+    }
+
+    // the dynamic body with the surface closest to p
+    fn find_closest(&self, p: Vector3<f64>) -> i32 {
+        self.bodies
+            .iter()
+            .enumerate()
+            .filter(|(_, body)| !body.is_static())
+            .map(|(i, body)| (i, body.shape.distance(&body.local_point(&p))))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(-1, |(i, _)| i as i32)
+    }
+    pub fn init_grab(&mut self, p: Vector3<f64>) {
+        self.grab_id = self.find_closest(p);
+        if self.grab_id >= 0 {
+            self.grab_local = self.bodies[self.grab_id as usize].local_point(&p);
+        }
+    }
+    pub fn move_grab(&mut self, p: Vector3<f64>) {
+        self.grab_pos = p;
+    }
+    pub fn release_grab(&mut self) {
+        self.grab_id = -1;
+    }
+}
+
+impl Recordable for System {
+    fn num_substeps(&self) -> usize {
+        self.par.num_substeps
+    }
+
+    fn substep(&mut self) {
+        System::substep(self);
+    }
+
+    fn save_state(&self, out: &mut Vec<f64>) {
+        for body in &self.bodies {
+            let t = body.pose.translation.vector;
+            let q = body.pose.rotation;
+            let (v, w) = (body.vel, body.omega);
+            out.extend_from_slice(&[
+                t.x, t.y, t.z, q.w, q.i, q.j, q.k, v.x, v.y, v.z, w.x, w.y, w.z,
+            ]);
+        }
+    }
+
+    fn load_state(&mut self, state: &[f64]) -> std::io::Result<()> {
+        recorder::check_state_len(state, 13 * self.bodies.len())?;
+        for (body, s) in self.bodies.iter_mut().zip(state.chunks_exact(13)) {
+            body.pose = Isometry3::from_parts(
+                nalgebra::Translation3::new(s[0], s[1], s[2]),
+                nalgebra::UnitQuaternion::new_normalize(nalgebra::Quaternion::new(
+                    s[3], s[4], s[5], s[6],
+                )),
+            );
+            body.prev_pose = body.pose;
+            body.vel = Vector3::new(s[7], s[8], s[9]);
+            body.omega = Vector3::new(s[10], s[11], s[12]);
+        }
+        Ok(())
+    }
+
+    fn apply_input(&mut self, event: &InputEvent) {
+        match *event {
+            InputEvent::InitGrab(p) => self.init_grab(p),
+            InputEvent::MoveGrab(p) => self.move_grab(p),
+            InputEvent::ReleaseGrab => self.release_grab(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::constraints::xpbd::rigid_bodies::shape::Shape;
+
+    #[test]
+    fn box_stack_rests() {
+        let mut system = System::new(SystemParameters::default());
+        let cube = Shape::Box {
+            half_extents: Vector3::repeat(0.5),
+        };
+        for k in 0..3 {
+            let pose = Isometry3::translation(0.0, 0.5 + k as f64, 0.0);
+            system.add_body(RigidBody::new(cube, pose, 1.0));
+        }
+        for _ in 0..120 {
+            system.simulate();
+        }
+        for (k, body) in system.bodies.iter().enumerate() {
+            let p = body.position();
+            assert!(p.xz().norm() < 1e-4, "box {} drifted to {}", k, p);
+            assert!((p.y - 0.5 - k as f64).abs() < 1e-4, "box {} at {}", k, p);
+            assert!(body.omega.norm() < 1e-4, "box {} spins", k);
+        }
+    }
+
+    #[test]
+    fn reset_releases_the_grab() {
+        let mut system = System::new(SystemParameters::default());
+        let cube = Shape::Box {
+            half_extents: Vector3::repeat(0.5),
+        };
+        system.add_body(RigidBody::new(
+            cube,
+            Isometry3::translation(0.0, 0.5, 0.0),
+            1.0,
+        ));
+        system.init_grab(Vector3::new(0.0, 1.0, 0.0));
+        system.move_grab(Vector3::new(0.0, 3.0, 0.0));
+        for _ in 0..10 {
+            system.simulate();
+        }
+        assert!(system.bodies[0].position().y > 0.6);
+        system.reset();
+        for _ in 0..10 {
+            system.simulate();
+        }
+        let p = system.bodies[0].position();
+        assert!((p.y - 0.5).abs() < 1e-3, "box at {}", p);
+    }
+}
@@ -0,0 +1,30 @@
+use nalgebra::Vector3;
+
+#[derive(Debug, Clone)]
+pub struct SystemParameters {
+    pub time_step: f64,
+    pub num_substeps: usize,
+    // of the bodies added by the interface
+    pub density: f64,
+    // Coulomb friction and restitution of all contacts
+    pub static_friction: f64,
+    pub dynamic_friction: f64,
+    pub restitution: f64,
+    pub world_bounds: (Vector3<f64>, Vector3<f64>),
+    pub gravity: Vector3<f64>,
+}
+
+impl Default for SystemParameters {
+    fn default() -> Self {
+        SystemParameters {
+            time_step: 0.1 / 60.0,
+            num_substeps: 20,
+            density: 1.0,
+            static_friction: 0.6,
+            dynamic_friction: 0.4,
+            restitution: 0.2,
+            world_bounds: (Vector3::new(-5.0, 0.0, -5.0), Vector3::new(5.0, 10.0, 5.0)),
+            gravity: Vector3::new(0.0, -100.0, 0.0),
+        }
+    }
+}
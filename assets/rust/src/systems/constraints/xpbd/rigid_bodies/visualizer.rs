@@ -0,0 +1,175 @@
+use super::body::RigidBody;
+use super::shape::Shape;
+use nalgebra::Isometry3;
+use three_d::prelude::*;
+use three_d::{
+    AmbientLight, Camera, Context, CpuMaterial, CpuMesh, Cull, FrameInput, Geometry, Gm, Mat4,
+    Mesh, PhysicalMaterial, Positions, Quat, SpotLight, Srgba, Window,
+};
+
+pub struct Visualizer {
+    pub camera: Camera,
+    pub plane: Gm<Mesh, PhysicalMaterial>,
+    // one object per body
+    pub bodies: Vec<Gm<Mesh, PhysicalMaterial>>,
+    ambient: AmbientLight,
+    spot_light: SpotLight,
+    context: Context,
+}
+
+impl Visualizer {
+    pub fn new(
+        window: &Window,
+        bodies: &[RigidBody],
+        target: &three_d::Vector3<f32>,
+        scene_radius: f32,
+    ) -> Self {
+        let context = window.gl();
+
+        let camera = Camera::new_perspective(
+            window.viewport(),
+            target + (scene_radius * three_d::vec3(0.6, 0.3, 1.0).normalize()),
+            *target,
+            three_d::vec3(0.0, 1.0, 0.0),
+            three_d::degrees(45.0),
+            0.1,
+            1000.0,
+        );
+
+        //light
+        let ambient = AmbientLight::new(&context, 0.5, Srgba::WHITE);
+        let spot_light = three_d::SpotLight::new(
+            &context,
+            5.0,
+            Srgba::WHITE,
+            three_d::vec3(8.0, 8.0, 0.0),
+            three_d::vec3(-1.0, -1.0, 0.0),
+            three_d::degrees(50.0),
+            three_d::Attenuation::default(),
+        );
+
+        let mut cpu_plane = CpuMesh::square();
+        cpu_plane
+            .transform(
+                three_d::Mat4::from_translation(three_d::vec3(0.0, -0.01, 0.0))
+                    * three_d::Mat4::from_scale(5.0)
+                    * three_d::Mat4::from_angle_x(three_d::degrees(-90.0)),
+            )
+            .unwrap();
+        let plane = three_d::Gm::new(
+            Mesh::new(&context, &cpu_plane),
+            PhysicalMaterial::new_opaque(
+                &context,
+                &CpuMaterial {
+                    albedo: Srgba::new_opaque(200, 200, 250),
+                    ..Default::default()
+                },
+            ),
+        );
+
+        let mut vis = Visualizer {
+            camera,
+            plane,
+            bodies: Vec::new(),
+            ambient,
+            spot_light,
+            context,
+        };
+        for body in bodies {
+            vis.add_body(body);
+        }
+        vis.update(bodies);
+        vis
+    }
+
+    pub fn add_body(&mut self, body: &RigidBody) {
+        let albedo = if body.is_static() {
+            Srgba::new_opaque(110, 130, 160)
+        } else {
+            Srgba::new_opaque(200, 90, 80)
+        };
+        let mut material = PhysicalMaterial::new_opaque(
+            &self.context,
+            &CpuMaterial {
+                albedo,
+                roughness: 0.6,
+                metallic: 0.0,
+                ..Default::default()
+            },
+        );
+        material.render_states.cull = Cull::Back;
+        let mut object = Gm::new(
+            Mesh::new(&self.context, &Self::shape_mesh(&body.shape)),
+            material,
+        );
+        object.set_transformation(Self::to_mat4(&body.pose));
+        self.bodies.push(object);
+    }
+
+    // Mesh of a shape in the body frame.
+    fn shape_mesh(shape: &Shape) -> CpuMesh {
+        match shape {
+            Shape::Sphere { radius } => {
+                let mut sphere = CpuMesh::sphere(16);
+                sphere.transform(Mat4::from_scale(*radius as f32)).unwrap();
+                sphere
+            }
+            Shape::Capsule {
+                radius,
+                half_height,
+            } => {
+                // a sphere with the poles along y and the halves pulled apart,
+                // odd subdivisions leave no vertex on the equator
+                let mut capsule = CpuMesh::sphere(15);
+                capsule
+                    .transform(
+                        Mat4::from_scale(*radius as f32)
+                            * Mat4::from_angle_x(three_d::degrees(-90.0)),
+                    )
+                    .unwrap();
+                if let Positions::F32(positions) = &mut capsule.positions {
+                    for p in positions.iter_mut() {
+                        p.y += (*half_height as f32).copysign(p.y);
+                    }
+                }
+                capsule
+            }
+            Shape::Box { half_extents } => {
+                let mut cube = CpuMesh::cube();
+                cube.transform(Mat4::from_nonuniform_scale(
+                    half_extents.x as f32,
+                    half_extents.y as f32,
+                    half_extents.z as f32,
+                ))
+                .unwrap();
+                cube
+            }
+        }
+    }
+
+    fn to_mat4(transform: &Isometry3<f64>) -> Mat4 {
+        let t = transform.translation.vector;
+        let q = transform.rotation;
+        Mat4::from_translation(three_d::vec3(t.x as f32, t.y as f32, t.z as f32))
+            * Mat4::from(Quat::new(q.w as f32, q.i as f32, q.j as f32, q.k as f32))
+    }
+
+    pub fn update(&mut self, bodies: &[RigidBody]) {
+        for (object, body) in self.bodies.iter_mut().zip(bodies) {
+            object.set_transformation(Self::to_mat4(&body.pose));
+        }
+        // shadow
+        let objects = self.bodies.iter().map(|object| object as &dyn Geometry);
+        self.spot_light.generate_shadow_map(2048, objects);
+    }
+
+    pub fn render(&mut self, frame_input: &FrameInput) {
+        let lights: [&dyn three_d::Light; 2] = [&self.ambient, &self.spot_light];
+        let screen = frame_input.screen();
+        screen.clear(three_d::ClearState::color_and_depth(
+            1.0, 1.0, 1.0, 1.0, 1.0,
+        ));
+        screen.render(&self.camera, &self.bodies, &lights);
+        screen.render(&self.camera, &self.plane, &lights);
+    }
+}
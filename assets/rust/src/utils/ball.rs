@@ -1,5 +1,6 @@
+use crate::utils::integrator::Integrator;
 use nalgebra::{Vector2, Vector3};
-use rand::random;
+use rand::Rng;
 
 pub struct Ball {
     pub pos: Vector2<f64>,
@@ -10,9 +11,9 @@ pub struct Ball {
 }
 
 impl Ball {
-    pub fn update(&mut self, dt: f64) {
-        self.vel += self.acel * dt;
-        self.pos += self.vel * dt;
+    pub fn update(&mut self, dt: f64, integrator: Integrator) {
+        let acel = self.acel;
+        integrator.step(&mut self.pos, &mut self.vel, dt, |_, _| acel);
     }
 
     pub fn apply_gravity(&mut self, g: f64) {
@@ -21,21 +22,22 @@ impl Ball {
 }
 
 pub fn get_random_ball(
+    rng: &mut impl Rng,
     width: u32,
     height: u32,
     mass_min: f64,
     mass_max: f64,
     average_radius: f64,
 ) -> Ball {
-    let mass = random::<f64>() * (mass_max - mass_min) + mass_min;
+    let mass = rng.r#gen::<f64>() * (mass_max - mass_min) + mass_min;
     Ball {
         pos: Vector2::new(
-            random::<f64>() * (width as f64),
-            random::<f64>() * (height as f64),
+            rng.r#gen::<f64>() * (width as f64),
+            rng.r#gen::<f64>() * (height as f64),
         ),
         vel: Vector2::new(
-            (random::<f64>() - 0.5) * 100.0,
-            (random::<f64>() - 0.5) * 100.0,
+            (rng.r#gen::<f64>() - 0.5) * 100.0,
+            (rng.r#gen::<f64>() - 0.5) * 100.0,
         ),
         acel: Vector2::new(0.0, 0.0),
         mass,
@@ -63,6 +65,19 @@ pub fn wall_collision_2d(ball: &mut Ball, width: f64, height: f64, restitution:
     }
 }
 
+// Inward normals of the floor, left, right and top walls with how deep the ball is
+// in each of them, negative when apart.
+pub fn wall_penetrations_2d(ball: &Ball, width: f64, height: f64) -> [(Vector2<f64>, f64); 4] {
+    let (p, r) = (ball.pos, ball.radius);
+    [
+        (Vector2::new(0.0, 1.0), r - p.y),
+        (Vector2::new(1.0, 0.0), r - p.x),
+        (Vector2::new(-1.0, 0.0), p.x + r - width),
+        (Vector2::new(0.0, -1.0), p.y + r - height),
+    ]
+}
+
+#[allow(unused_variables)]
 pub fn wall_collision_3d(
     pos: &mut Vector3<f64>,
     vel: &mut Vector3<f64>,
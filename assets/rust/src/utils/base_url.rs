@@ -5,7 +5,8 @@ use wasm_bindgen::prelude::*;
 
 // Глобальная переменная с потокобезопасным доступом дефолтное значение src/assets
 // static BASE_URL: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
-static BASE_URL: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(Some("src/assets".to_string())));
+static BASE_URL: Lazy<Mutex<Option<String>>> =
+    Lazy::new(|| Mutex::new(Some("src/assets".to_string())));
 // Экспортируемая функция для установки значения глобальной переменной
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
@@ -1,4 +1,4 @@
-use rand::random;
+use rand::Rng;
 
 pub fn hsv_to_rgba(h: f32, s: f32, v: f32) -> (u8, u8, u8, u8) {
     let c = v * s;
@@ -27,6 +27,6 @@ pub fn hsv_to_rgba(h: f32, s: f32, v: f32) -> (u8, u8, u8, u8) {
     )
 }
 
-pub fn random_color_hsv(max_h: f32, s: f32, v: f32) -> (u8, u8, u8, u8) {
-    hsv_to_rgba(random::<f32>() * max_h, s, v)
+pub fn random_color_hsv(rng: &mut impl Rng, max_h: f32, s: f32, v: f32) -> (u8, u8, u8, u8) {
+    hsv_to_rgba(rng.r#gen::<f32>() * max_h, s, v)
 }
@@ -0,0 +1,7 @@
+// Messages for the user, the browser console on wasm and stderr on native.
+pub fn log(message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    web_sys::console::log_1(&message.into());
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("{}", message);
+}
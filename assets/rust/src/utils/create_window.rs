@@ -29,7 +29,7 @@ pub fn create_window(canvas_id: &str) -> Window {
         let width = canvas.width();
         let height = canvas.height();
         window_settings.max_size = Some((width, height));
-       
+
         window_settings.canvas = Some(canvas);
         let window = Window::new(window_settings).unwrap();
         window
@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+// samples kept per series, older ones are dropped
+const CAPACITY: usize = 1000;
+
+// Rolling time series of named quantities, one sample per simulated frame.
+pub struct Diagnostics {
+    pub names: Vec<&'static str>,
+    // the plots are drawn
+    pub show: bool,
+    pub path: String,
+    times: VecDeque<f64>,
+    // one series per name
+    values: Vec<VecDeque<f64>>,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+impl Diagnostics {
+    pub fn new(names: &[&'static str]) -> Self {
+        Diagnostics {
+            names: names.to_vec(),
+            show: false,
+            path: "diagnostics.csv".to_string(),
+            times: VecDeque::with_capacity(CAPACITY),
+            values: vec![VecDeque::with_capacity(CAPACITY); names.len()],
+        }
+    }
+
+    // no quantities to report
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.times.clear();
+        for series in &mut self.values {
+            series.clear();
+        }
+    }
+
+    // values in the order of the names
+    pub fn push(&mut self, time: f64, values: &[f64]) {
+        if self.times.len() == CAPACITY {
+            self.times.pop_front();
+            for series in &mut self.values {
+                series.pop_front();
+            }
+        }
+        self.times.push_back(time);
+        for (series, &value) in self.values.iter_mut().zip(values) {
+            series.push_back(value);
+        }
+    }
+
+    pub fn series(&self, k: usize) -> &VecDeque<f64> {
+        &self.values[k]
+    }
+
+    // a time column followed by one column per name
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("time");
+        for name in &self.names {
+            csv.push(',');
+            csv.push_str(name);
+        }
+        csv.push('\n');
+        for (i, time) in self.times.iter().enumerate() {
+            write!(csv, "{}", time).unwrap();
+            for series in &self.values {
+                write!(csv, ",{}", series[i]).unwrap();
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+
+    pub fn save_csv(&self) -> std::io::Result<()> {
+        std::fs::write(&self.path, self.to_csv())
+    }
+}
@@ -0,0 +1,60 @@
+// Greedy coloring of elements (tets, edges, ...) so that no two elements of one
+// color share a vertex. Returns the element indexes of every color.
+pub fn color_elements<const N: usize>(elements: &[[usize; N]]) -> Vec<Vec<usize>> {
+    let num_vertices = elements.iter().flatten().max().map_or(0, |&v| v + 1);
+    // colors already used around every vertex
+    let mut vertex_colors: Vec<Vec<usize>> = vec![Vec::new(); num_vertices];
+    let mut colors: Vec<Vec<usize>> = Vec::new();
+    let mut used = Vec::new();
+    for (elem_nr, elem) in elements.iter().enumerate() {
+        used.clear();
+        used.resize(colors.len() + 1, false);
+        for &v in elem {
+            for &color in &vertex_colors[v] {
+                used[color] = true;
+            }
+        }
+        let color = used.iter().position(|&u| !u).unwrap();
+        if color == colors.len() {
+            colors.push(Vec::new());
+        }
+        colors[color].push(elem_nr);
+        for &v in elem {
+            vertex_colors[v].push(color);
+        }
+    }
+    colors
+}
+
+// Greedy coloring of the vertices so that the vertices of one element never share
+// a color. Returns the vertex indexes of every color.
+pub fn color_vertices<const N: usize>(
+    num_vertices: usize,
+    elements: &[[usize; N]],
+) -> Vec<Vec<usize>> {
+    let mut neighbors: Vec<Vec<usize>> = vec![Vec::new(); num_vertices];
+    for elem in elements {
+        for &a in elem {
+            neighbors[a].extend(elem.iter().filter(|&&b| b != a));
+        }
+    }
+    let mut vertex_color = vec![usize::MAX; num_vertices];
+    let mut colors: Vec<Vec<usize>> = Vec::new();
+    let mut used = Vec::new();
+    for v in 0..num_vertices {
+        used.clear();
+        used.resize(colors.len() + 1, false);
+        for &n in &neighbors[v] {
+            if vertex_color[n] != usize::MAX {
+                used[vertex_color[n]] = true;
+            }
+        }
+        let color = used.iter().position(|&u| !u).unwrap();
+        if color == colors.len() {
+            colors.push(Vec::new());
+        }
+        colors[color].push(v);
+        vertex_color[v] = color;
+    }
+    colors
+}
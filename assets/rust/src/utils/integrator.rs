@@ -0,0 +1,136 @@
+use std::ops::{Add, Mul};
+
+// Time integration of x'' = a(x, v).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrator {
+    // position from the old velocity, then the velocity
+    ExplicitEuler,
+    // velocity first, the position from the new velocity
+    SymplecticEuler,
+    VelocityVerlet,
+    // midpoint
+    Rk2,
+    Rk4,
+}
+
+impl Integrator {
+    pub const ALL: [Integrator; 5] = [
+        Integrator::ExplicitEuler,
+        Integrator::SymplecticEuler,
+        Integrator::VelocityVerlet,
+        Integrator::Rk2,
+        Integrator::Rk4,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Integrator::ExplicitEuler => "explicit Euler",
+            Integrator::SymplecticEuler => "symplectic Euler",
+            Integrator::VelocityVerlet => "velocity Verlet",
+            Integrator::Rk2 => "RK2",
+            Integrator::Rk4 => "RK4",
+        }
+    }
+
+    // Advances pos and vel by dt, for a single vector or the stacked state of a
+    // whole system.
+    pub fn step<V>(&self, pos: &mut V, vel: &mut V, dt: f64, mut accel: impl FnMut(&V, &V) -> V)
+    where
+        V: Clone + Add<Output = V> + Mul<f64, Output = V>,
+    {
+        let (x, v) = (pos.clone(), vel.clone());
+        match self {
+            Integrator::ExplicitEuler => {
+                let a = accel(&x, &v);
+                *pos = x + v.clone() * dt;
+                *vel = v + a * dt;
+            }
+            Integrator::SymplecticEuler => {
+                let a = accel(&x, &v);
+                *vel = v + a * dt;
+                *pos = x + vel.clone() * dt;
+            }
+            Integrator::VelocityVerlet => {
+                // a velocity dependent acceleration is taken at the half step velocity
+                let a = accel(&x, &v);
+                let v_half = v + a * (0.5 * dt);
+                *pos = x + v_half.clone() * dt;
+                *vel = v_half.clone() + accel(pos, &v_half) * (0.5 * dt);
+            }
+            Integrator::Rk2 => {
+                let a1 = accel(&x, &v);
+                let x_mid = x.clone() + v.clone() * (0.5 * dt);
+                let v_mid = v.clone() + a1 * (0.5 * dt);
+                let a2 = accel(&x_mid, &v_mid);
+                *pos = x + v_mid * dt;
+                *vel = v + a2 * dt;
+            }
+            Integrator::Rk4 => {
+                let half = |y: &V, k: &V| y.clone() + k.clone() * (0.5 * dt);
+                let (k1x, k1v) = (v.clone(), accel(&x, &v));
+                let (x2, v2) = (half(&x, &k1x), half(&v, &k1v));
+                let k2v = accel(&x2, &v2);
+                let (x3, v3) = (half(&x, &v2), half(&v, &k2v));
+                let k3v = accel(&x3, &v3);
+                let (x4, v4) = (x.clone() + v3.clone() * dt, v.clone() + k3v.clone() * dt);
+                let k4v = accel(&x4, &v4);
+                *pos = x + (k1x + v2 * 2.0 + v3 * 2.0 + v4) * (dt / 6.0);
+                *vel = v + (k1v + k2v * 2.0 + k3v * 2.0 + k4v) * (dt / 6.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector1;
+
+    // x'' = -x from x = 1 at rest, the energy after t and the error against cos t
+    fn oscillate(integrator: Integrator, dt: f64, t: f64) -> (f64, f64) {
+        let (mut x, mut v) = (Vector1::new(1.0), Vector1::new(0.0));
+        let steps = (t / dt).round() as usize;
+        for _ in 0..steps {
+            integrator.step(&mut x, &mut v, dt, |x, _| -x);
+        }
+        let energy = 0.5 * (x.x * x.x + v.x * v.x);
+        (energy, (x.x - t.cos()).abs())
+    }
+
+    #[test]
+    fn oscillator_energy() {
+        let energy = |integrator| oscillate(integrator, 0.1, 100.0).0;
+        assert!(energy(Integrator::ExplicitEuler) > 10.0);
+        for integrator in [Integrator::SymplecticEuler, Integrator::VelocityVerlet] {
+            assert!(
+                (energy(integrator) - 0.5).abs() < 0.05,
+                "{}",
+                integrator.name()
+            );
+        }
+        assert!(energy(Integrator::Rk2) > 0.5);
+        assert!(energy(Integrator::Rk4) < 0.5);
+    }
+
+    #[test]
+    fn oscillator_order() {
+        // halving dt divides the error by 2 to the order
+        for (integrator, order) in [
+            (Integrator::ExplicitEuler, 1),
+            (Integrator::VelocityVerlet, 2),
+            (Integrator::Rk2, 2),
+            (Integrator::Rk4, 4),
+        ] {
+            let coarse = oscillate(integrator, 0.01, 1.0).1;
+            let fine = oscillate(integrator, 0.005, 1.0).1;
+            let ratio = coarse / fine;
+            let expected = 2f64.powi(order);
+            assert!(
+                (ratio / expected - 1.0).abs() < 0.2,
+                "{} ratio {}",
+                integrator.name(),
+                ratio
+            );
+        }
+    }
+}
@@ -1,7 +1,13 @@
 pub mod ball;
+pub mod base_url;
 pub mod colors;
+pub mod console;
 pub mod create_window;
+pub mod diagnostics;
+pub mod graph_coloring;
+pub mod integrator;
 pub mod parameters;
+pub mod recorder;
+pub mod sparse_cholesky;
 pub mod tetra_mesh;
 pub mod ui;
-pub mod base_url;
\ No newline at end of file
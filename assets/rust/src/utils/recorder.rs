@@ -0,0 +1,431 @@
+use crate::utils::console;
+use crate::utils::ui::BaseState;
+use nalgebra::Vector3;
+use std::io::{Error, ErrorKind};
+
+const MAGIC: &[u8; 4] = b"GPRC";
+const VERSION: u8 = 1;
+
+pub trait Recordable {
+    fn num_substeps(&self) -> usize;
+    fn substep(&mut self);
+    // appends the full simulation state as a flat list of numbers
+    fn save_state(&self, out: &mut Vec<f64>);
+    // fails for a state saved from a different scene
+    fn load_state(&mut self, state: &[f64]) -> std::io::Result<()>;
+    // recorded user input, ignored by systems that take none
+    fn apply_input(&mut self, _event: &InputEvent) {}
+}
+
+// Error for a state of the wrong size, expected is the size of the current scene.
+pub fn check_state_len(state: &[f64], expected: usize) -> std::io::Result<()> {
+    if state.len() != expected {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "recorded state has {} values, the scene has {}",
+                state.len(),
+                expected
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputEvent {
+    InitGrab(Vector3<f64>),
+    MoveGrab(Vector3<f64>),
+    ReleaseGrab,
+}
+
+// Per-substep state history plus user inputs, tagged with the substep they precede.
+pub struct Recorder {
+    recording: bool,
+    state_len: usize,
+    states: Vec<f64>,
+    inputs: Vec<(usize, InputEvent)>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder {
+            recording: false,
+            state_len: 0,
+            states: Vec::new(),
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.state_len = 0;
+        self.states.clear();
+        self.inputs.clear();
+    }
+
+    // number of recorded substeps
+    pub fn len(&self) -> usize {
+        self.states.len().checked_div(self.state_len).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn inputs(&self) -> &[(usize, InputEvent)] {
+        &self.inputs
+    }
+
+    pub fn state(&self, substep: usize) -> &[f64] {
+        let start = substep * self.state_len;
+        &self.states[start..start + self.state_len]
+    }
+
+    pub fn record(&mut self, sys: &impl Recordable) {
+        let start = self.states.len();
+        sys.save_state(&mut self.states);
+        let len = self.states.len() - start;
+        if self.state_len != len {
+            // the system was rebuilt with a different size, start over
+            let state = self.states.split_off(start);
+            self.clear();
+            self.states = state;
+            self.state_len = len;
+        }
+    }
+
+    pub fn record_input(&mut self, event: InputEvent) {
+        if self.recording {
+            self.inputs.push((self.len(), event));
+        }
+    }
+
+    pub fn step(&mut self, sys: &mut impl Recordable) {
+        for _ in 0..sys.num_substeps() {
+            sys.substep();
+            self.record(sys);
+        }
+    }
+
+    // Loads the state after substep together with the grab that was active then.
+    pub fn restore(&self, substep: usize, sys: &mut impl Recordable) -> std::io::Result<()> {
+        if substep >= self.len() {
+            return Ok(());
+        }
+        // substep the grab started before, the grabbed point and the latest target
+        let mut grab = None;
+        for &(at, event) in self.inputs.iter().take_while(|(at, _)| *at <= substep) {
+            match event {
+                InputEvent::InitGrab(p) => grab = Some((at, p, p)),
+                InputEvent::MoveGrab(p) => {
+                    if let Some((_, _, target)) = &mut grab {
+                        *target = p;
+                    }
+                }
+                InputEvent::ReleaseGrab => grab = None,
+            }
+        }
+        sys.apply_input(&InputEvent::ReleaseGrab);
+        if let Some((at, point, target)) = grab {
+            // the point is picked in the state it was picked from
+            sys.load_state(self.state(at.saturating_sub(1)))?;
+            sys.apply_input(&InputEvent::InitGrab(point));
+            sys.apply_input(&InputEvent::MoveGrab(target));
+        }
+        sys.load_state(self.state(substep))
+    }
+
+    // Advances the system according to the replay controls in base.
+    // Returns true if the system state changed.
+    pub fn update(&mut self, sys: &mut impl Recordable, base: &mut BaseState) -> bool {
+        let replay = &mut base.replay;
+        if replay.start_recording {
+            replay.start_recording = false;
+            self.clear();
+        }
+        self.recording = replay.recording;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            if replay.save {
+                replay.save = false;
+                if let Err(e) = self.save(&replay.path) {
+                    console::log(&format!("failed to save {}: {}", replay.path, e));
+                }
+            }
+            if replay.load {
+                replay.load = false;
+                let mut state = Vec::new();
+                sys.save_state(&mut state);
+                match Recorder::load(&replay.path) {
+                    Ok(recorder) if recorder.is_empty() || recorder.state_len == state.len() => {
+                        *self = recorder;
+                    }
+                    Ok(recorder) => console::log(&format!(
+                        "failed to load {}: recorded states have {} values, the scene has {}",
+                        replay.path,
+                        recorder.state_len,
+                        state.len()
+                    )),
+                    Err(e) => console::log(&format!("failed to load {}: {}", replay.path, e)),
+                }
+            }
+        }
+        replay.len = self.len();
+
+        if replay.replaying {
+            replay.position = replay.position.min(self.len().saturating_sub(1));
+            if let Err(e) = self.restore(replay.position, sys) {
+                console::log(&format!("failed to replay: {}", e));
+                replay.replaying = false;
+            }
+            true
+        } else if !base.stop {
+            if self.recording {
+                self.step(sys);
+            } else {
+                for _ in 0..sys.num_substeps() {
+                    sys.substep();
+                }
+            }
+            replay.len = self.len();
+            replay.position = self.len().saturating_sub(1);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(21 + 8 * self.states.len() + 33 * self.inputs.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(self.state_len as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        for x in &self.states {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        for (substep, event) in &self.inputs {
+            bytes.extend_from_slice(&(*substep as u32).to_le_bytes());
+            let (tag, p) = match event {
+                InputEvent::InitGrab(p) => (0u8, Some(p)),
+                InputEvent::MoveGrab(p) => (1u8, Some(p)),
+                InputEvent::ReleaseGrab => (2u8, None),
+            };
+            bytes.push(tag);
+            if let Some(p) = p {
+                for x in p.iter() {
+                    bytes.extend_from_slice(&x.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<Self> {
+        let mut reader = ByteReader { bytes, pos: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a recording"));
+        }
+        let version = reader.take(1)?[0];
+        if version != VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported recording version {}", version),
+            ));
+        }
+        let state_len = reader.u32()? as usize;
+        let num_substeps = reader.u32()? as usize;
+        let num_inputs = reader.u32()? as usize;
+
+        // the counts are checked against the file size before anything is allocated
+        let num_values = state_len
+            .checked_mul(num_substeps)
+            .filter(|n| {
+                n.checked_mul(8)
+                    .is_some_and(|len| len <= reader.remaining())
+            })
+            .ok_or_else(|| invalid_data("recording is shorter than its header"))?;
+        let mut states = Vec::with_capacity(num_values);
+        for _ in 0..num_values {
+            states.push(reader.f64()?);
+        }
+        // every input takes at least a substep and a tag
+        if num_inputs
+            .checked_mul(5)
+            .is_none_or(|len| len > reader.remaining())
+        {
+            return Err(invalid_data("recording is shorter than its header"));
+        }
+        let mut inputs = Vec::with_capacity(num_inputs);
+        for _ in 0..num_inputs {
+            let substep = reader.u32()? as usize;
+            let event = match reader.take(1)?[0] {
+                0 => InputEvent::InitGrab(reader.vector3()?),
+                1 => InputEvent::MoveGrab(reader.vector3()?),
+                2 => InputEvent::ReleaseGrab,
+                tag => return Err(invalid_data(&format!("unknown input tag {}", tag))),
+            };
+            inputs.push((substep, event));
+        }
+        Ok(Recorder {
+            recording: false,
+            state_len,
+            states,
+            inputs,
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl ByteReader<'_> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> std::io::Result<&[u8]> {
+        if self.pos + n > self.bytes.len() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "recording is truncated",
+            ));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> std::io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> std::io::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn vector3(&mut self) -> std::io::Result<Vector3<f64>> {
+        Ok(Vector3::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a point falling one unit per substep, grabbing pins it to the target
+    #[derive(Default)]
+    struct Point {
+        x: f64,
+        grab: Option<f64>,
+    }
+
+    impl Recordable for Point {
+        fn num_substeps(&self) -> usize {
+            2
+        }
+
+        fn substep(&mut self) {
+            self.x = self.grab.unwrap_or(self.x - 1.0);
+        }
+
+        fn save_state(&self, out: &mut Vec<f64>) {
+            out.push(self.x);
+        }
+
+        fn load_state(&mut self, state: &[f64]) -> std::io::Result<()> {
+            check_state_len(state, 1)?;
+            self.x = state[0];
+            Ok(())
+        }
+
+        fn apply_input(&mut self, event: &InputEvent) {
+            match *event {
+                InputEvent::InitGrab(p) | InputEvent::MoveGrab(p) => self.grab = Some(p.x),
+                InputEvent::ReleaseGrab => self.grab = None,
+            }
+        }
+    }
+
+    fn recording() -> Recorder {
+        let mut recorder = Recorder::new();
+        recorder.recording = true;
+        let mut point = Point::default();
+        recorder.step(&mut point);
+        let event = InputEvent::InitGrab(Vector3::new(5.0, 0.0, 0.0));
+        point.apply_input(&event);
+        recorder.record_input(event);
+        recorder.step(&mut point);
+        recorder
+    }
+
+    #[test]
+    fn round_trip() {
+        let recorder = recording();
+        let loaded = Recorder::from_bytes(&recorder.to_bytes()).unwrap();
+        assert_eq!(loaded.len(), 4);
+        assert_eq!(loaded.states, recorder.states);
+        assert_eq!(loaded.inputs(), recorder.inputs());
+    }
+
+    #[test]
+    fn corrupt_headers_are_errors() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        for count in [u32::MAX, u32::MAX, u32::MAX] {
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+        let e = Recorder::from_bytes(&bytes).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        let mut bytes = recording().to_bytes();
+        bytes[13..17].copy_from_slice(&u32::MAX.to_le_bytes());
+        let e = Recorder::from_bytes(&bytes).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        bytes.truncate(20);
+        assert!(Recorder::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn restore_replays_the_grab() {
+        let recorder = recording();
+        let mut point = Point::default();
+        recorder.restore(1, &mut point).unwrap();
+        assert_eq!((point.x, point.grab), (-2.0, None));
+        recorder.restore(3, &mut point).unwrap();
+        assert_eq!((point.x, point.grab), (5.0, Some(5.0)));
+        point.substep();
+        assert_eq!(point.x, 5.0);
+        recorder.restore(0, &mut point).unwrap();
+        assert_eq!(point.grab, None);
+    }
+
+    #[test]
+    fn state_of_another_scene_is_an_error() {
+        let mut point = Point::default();
+        assert!(point.load_state(&[1.0, 2.0]).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+// Cholesky factorization A = L Lᵀ of a sparse symmetric positive definite matrix in
+// envelope (skyline) storage. The rows are reordered by reverse Cuthill-McKee to keep
+// the envelope, and with it the fill-in, small. Factor once, solve many times.
+pub struct SparseCholesky {
+    // perm[new] = old
+    perm: Vec<usize>,
+    // first stored column of every row of L, in the new order
+    first: Vec<usize>,
+    // offset of every row in values, row i holds columns first[i]..=i
+    start: Vec<usize>,
+    values: Vec<f64>,
+}
+
+impl SparseCholesky {
+    // Entries are (row, col, value), an off-diagonal entry stands for both A[row][col]
+    // and A[col][row] and entries at the same position are summed. None if the matrix
+    // is not positive definite.
+    pub fn new(n: usize, entries: &[(usize, usize, f64)]) -> Option<Self> {
+        let perm = Self::reverse_cuthill_mckee(n, entries);
+        let mut inv_perm = vec![0; n];
+        for (new, &old) in perm.iter().enumerate() {
+            inv_perm[old] = new;
+        }
+        // lower triangle position in the new order
+        let lower = |r: usize, c: usize| {
+            let (r, c) = (inv_perm[r], inv_perm[c]);
+            (r.max(c), r.min(c))
+        };
+
+        let mut first: Vec<usize> = (0..n).collect();
+        for &(r, c, _) in entries {
+            let (r, c) = lower(r, c);
+            first[r] = first[r].min(c);
+        }
+        let mut start = Vec::with_capacity(n + 1);
+        start.push(0);
+        for i in 0..n {
+            start.push(start[i] + i - first[i] + 1);
+        }
+        let mut values = vec![0.0; start[n]];
+        for &(r, c, v) in entries {
+            let (r, c) = lower(r, c);
+            values[start[r] + c - first[r]] += v;
+        }
+
+        let mut chol = SparseCholesky {
+            perm,
+            first,
+            start,
+            values,
+        };
+        if chol.factor() { Some(chol) } else { None }
+    }
+
+    // Bandwidth reducing order, breadth first from a low degree node of every component,
+    // neighbors by increasing degree, then reversed.
+    fn reverse_cuthill_mckee(n: usize, entries: &[(usize, usize, f64)]) -> Vec<usize> {
+        let mut neighbors: Vec<Vec<usize>> = vec![Vec::new(); n];
+        for &(r, c, _) in entries {
+            if r != c {
+                neighbors[r].push(c);
+                neighbors[c].push(r);
+            }
+        }
+        for list in &mut neighbors {
+            list.sort_unstable();
+            list.dedup();
+        }
+        let degree: Vec<usize> = neighbors.iter().map(|list| list.len()).collect();
+        for list in &mut neighbors {
+            list.sort_by_key(|&v| degree[v]);
+        }
+
+        let mut nodes: Vec<usize> = (0..n).collect();
+        nodes.sort_by_key(|&v| degree[v]);
+        let mut visited = vec![false; n];
+        let mut order = Vec::with_capacity(n);
+        for &root in &nodes {
+            if visited[root] {
+                continue;
+            }
+            visited[root] = true;
+            let mut head = order.len();
+            order.push(root);
+            while head < order.len() {
+                let v = order[head];
+                head += 1;
+                for &w in &neighbors[v] {
+                    if !visited[w] {
+                        visited[w] = true;
+                        order.push(w);
+                    }
+                }
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    fn at(&self, row: usize, col: usize) -> f64 {
+        self.values[self.start[row] + col - self.first[row]]
+    }
+
+    // Row by row, L[i][j] = (A[i][j] - Σ_k L[i][k] L[j][k]) / L[j][j].
+    fn factor(&mut self) -> bool {
+        let n = self.first.len();
+        for i in 0..n {
+            for j in self.first[i]..=i {
+                let k0 = self.first[i].max(self.first[j]);
+                let mut s = self.at(i, j);
+                for k in k0..j {
+                    s -= self.at(i, k) * self.at(j, k);
+                }
+                let value = if j < i {
+                    s / self.at(j, j)
+                } else if s > 0.0 {
+                    s.sqrt()
+                } else {
+                    return false;
+                };
+                let idx = self.start[i] + j - self.first[i];
+                self.values[idx] = value;
+            }
+        }
+        true
+    }
+
+    pub fn size(&self) -> usize {
+        self.first.len()
+    }
+
+    // Solves A x = b in place.
+    pub fn solve(&self, b: &mut [f64]) {
+        let n = self.size();
+        let mut x: Vec<f64> = self.perm.iter().map(|&old| b[old]).collect();
+        // L y = b
+        for i in 0..n {
+            let (row, diag) = self.row(i);
+            let s: f64 = row
+                .iter()
+                .zip(&x[self.first[i]..i])
+                .map(|(l, x)| l * x)
+                .sum();
+            x[i] = (x[i] - s) / diag;
+        }
+        // Lᵀ x = y
+        for i in (0..n).rev() {
+            let (row, diag) = self.row(i);
+            x[i] /= diag;
+            let xi = x[i];
+            for (xk, l) in x[self.first[i]..i].iter_mut().zip(row) {
+                *xk -= l * xi;
+            }
+        }
+        for (new, &old) in self.perm.iter().enumerate() {
+            b[old] = x[new];
+        }
+    }
+
+    // off-diagonal entries and the diagonal of row i of L
+    fn row(&self, i: usize) -> (&[f64], f64) {
+        let row = &self.values[self.start[i]..self.start[i + 1]];
+        (&row[..row.len() - 1], row[row.len() - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{DMatrix, DVector};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn solve_matches_dense() {
+        let n = 40;
+        let mut rng = StdRng::seed_from_u64(3);
+        // a random sparse graph Laplacian, shifted to be positive definite
+        let mut entries = Vec::new();
+        let mut dense = DMatrix::zeros(n, n);
+        for i in 0..n {
+            entries.push((i, i, 0.5));
+            dense[(i, i)] += 0.5;
+        }
+        for _ in 0..3 * n {
+            let (r, c) = (rng.gen_range(0..n), rng.gen_range(0..n));
+            if r == c {
+                continue;
+            }
+            let w = rng.gen_range(0.1..2.0);
+            entries.extend([(r, r, w), (c, c, w), (r, c, -w)]);
+            dense[(r, r)] += w;
+            dense[(c, c)] += w;
+            dense[(r, c)] -= w;
+            dense[(c, r)] -= w;
+        }
+        let b = DVector::from_fn(n, |_, _| rng.gen_range(-1.0..1.0));
+
+        let expected = dense.cholesky().unwrap().solve(&b);
+        let mut x = b.as_slice().to_vec();
+        SparseCholesky::new(n, &entries).unwrap().solve(&mut x);
+        for (x, e) in x.iter().zip(expected.iter()) {
+            assert!((x - e).abs() < 1e-9, "{} != {}", x, e);
+        }
+    }
+
+    #[test]
+    fn indefinite_is_none() {
+        let entries = [(0, 0, 1.0), (1, 1, 1.0), (0, 1, 2.0)];
+        assert!(SparseCholesky::new(2, &entries).is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use super::{MeshError, TetraMesh, parse_error, parse_word};
+use nalgebra::Vector3;
+use std::collections::HashMap;
+
+const TRIANGLE: usize = 2;
+const TETRAHEDRON: usize = 4;
+const TETRAHEDRON_10: usize = 11;
+
+struct Lines<'a> {
+    lines: Vec<(usize, Vec<&'a str>)>,
+    pos: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Self {
+        let lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.split_whitespace().collect::<Vec<_>>()))
+            .filter(|(_, words)| !words.is_empty())
+            .collect();
+        Lines { lines, pos: 0 }
+    }
+
+    fn next(&mut self, what: &str) -> Result<(usize, &[&'a str]), MeshError> {
+        let last_line = self.lines.last().map_or(1, |l| l.0);
+        let (line, words) = self.lines.get(self.pos).ok_or_else(|| {
+            parse_error(
+                last_line,
+                format!("unexpected end of file, expected {}", what),
+            )
+        })?;
+        self.pos += 1;
+        Ok((*line, words))
+    }
+
+    fn expect(&mut self, keyword: &str) -> Result<(), MeshError> {
+        let (line, words) = self.next(keyword)?;
+        if words[0] != keyword {
+            return Err(parse_error(
+                line,
+                format!("expected {}, got {}", keyword, words[0]),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct MshData {
+    positions: Vec<Vector3<f64>>,
+    node_ids: HashMap<usize, usize>,
+    tetr_indexes: Vec<[usize; 4]>,
+    tet_lines: Vec<usize>,
+}
+
+impl MshData {
+    fn node(&self, tag: usize, line: usize) -> Result<usize, MeshError> {
+        self.node_ids
+            .get(&tag)
+            .copied()
+            .ok_or_else(|| parse_error(line, format!("unknown node {}", tag)))
+    }
+
+    fn add_node(
+        &mut self,
+        tag: usize,
+        words: &[&str],
+        first: usize,
+        line: usize,
+    ) -> Result<(), MeshError> {
+        let x = parse_word(words, first, line, "x coordinate")?;
+        let y = parse_word(words, first + 1, line, "y coordinate")?;
+        let z = parse_word(words, first + 2, line, "z coordinate")?;
+        if self.node_ids.insert(tag, self.positions.len()).is_some() {
+            return Err(parse_error(line, format!("duplicate node {}", tag)));
+        }
+        self.positions.push(Vector3::new(x, y, z));
+        Ok(())
+    }
+
+    fn add_element(
+        &mut self,
+        element_type: usize,
+        words: &[&str],
+        first: usize,
+        line: usize,
+    ) -> Result<(), MeshError> {
+        match element_type {
+            TETRAHEDRON | TETRAHEDRON_10 => {
+                let mut tet = [0; 4];
+                for (k, id) in tet.iter_mut().enumerate() {
+                    *id = self.node(parse_word(words, first + k, line, "node tag")?, line)?;
+                }
+                self.tetr_indexes.push(tet);
+                self.tet_lines.push(line);
+            }
+            TRIANGLE => {
+                // only checked, the surface is always rebuilt from the tetrahedra
+                for k in 0..3 {
+                    self.node(parse_word(words, first + k, line, "node tag")?, line)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl TetraMesh {
+    // Gmsh ASCII .msh, format versions 2.x and 4.1, 4.0 is not read. Only
+    // tetrahedra are used, other elements are skipped.
+    pub fn from_msh(text: &str) -> Result<Self, MeshError> {
+        let mut lines = Lines::new(text);
+        lines.expect("$MeshFormat")?;
+        let (line, words) = lines.next("mesh format")?;
+        let version: f64 = parse_word(words, 0, line, "version")?;
+        let file_type: usize = parse_word(words, 1, line, "file type")?;
+        if file_type != 0 {
+            return Err(parse_error(line, "binary .msh files are not supported"));
+        }
+        let v4 = if (2.0..3.0).contains(&version) {
+            false
+        } else if version == 4.1 {
+            true
+        } else {
+            return Err(parse_error(
+                line,
+                format!(
+                    "unsupported .msh version {}, only 2.x and 4.1 are read",
+                    version
+                ),
+            ));
+        };
+        lines.expect("$EndMeshFormat")?;
+
+        let mut data = MshData::default();
+        while lines.pos < lines.lines.len() {
+            let (line, words) = lines.next("section")?;
+            match words[0] {
+                "$Nodes" if v4 => read_nodes_v4(&mut lines, &mut data)?,
+                "$Nodes" => read_nodes_v2(&mut lines, &mut data)?,
+                "$Elements" if v4 => read_elements_v4(&mut lines, &mut data)?,
+                "$Elements" => read_elements_v2(&mut lines, &mut data)?,
+                section if section.starts_with("$End") => {
+                    return Err(parse_error(line, format!("unexpected {}", section)));
+                }
+                section if section.starts_with('$') => {
+                    // skip unknown sections
+                    let end = format!("$End{}", &section[1..]);
+                    while lines.next(&end)?.1[0] != end {}
+                }
+                _ => return Err(parse_error(line, format!("unexpected '{}'", words[0]))),
+            }
+        }
+
+        let MshData {
+            positions,
+            mut tetr_indexes,
+            tet_lines,
+            ..
+        } = data;
+        Self::validate(&positions, &mut tetr_indexes, &tet_lines, false)?;
+        Ok(Self::from_tets(positions, tetr_indexes))
+    }
+}
+
+fn read_nodes_v2(lines: &mut Lines, data: &mut MshData) -> Result<(), MeshError> {
+    let (line, words) = lines.next("number of nodes")?;
+    let num_nodes: usize = parse_word(words, 0, line, "number of nodes")?;
+    for _ in 0..num_nodes {
+        let (line, words) = lines.next("node")?;
+        let tag = parse_word(words, 0, line, "node tag")?;
+        data.add_node(tag, words, 1, line)?;
+    }
+    lines.expect("$EndNodes")
+}
+
+fn read_elements_v2(lines: &mut Lines, data: &mut MshData) -> Result<(), MeshError> {
+    let (line, words) = lines.next("number of elements")?;
+    let num_elements: usize = parse_word(words, 0, line, "number of elements")?;
+    for _ in 0..num_elements {
+        // elm-number elm-type number-of-tags < tag > ... node-number-list
+        let (line, words) = lines.next("element")?;
+        let element_type: usize = parse_word(words, 1, line, "element type")?;
+        let num_tags: usize = parse_word(words, 2, line, "number of tags")?;
+        data.add_element(element_type, words, 3 + num_tags, line)?;
+    }
+    lines.expect("$EndElements")
+}
+
+fn read_nodes_v4(lines: &mut Lines, data: &mut MshData) -> Result<(), MeshError> {
+    // numEntityBlocks numNodes minNodeTag maxNodeTag
+    let (line, words) = lines.next("nodes header")?;
+    let num_blocks: usize = parse_word(words, 0, line, "number of entity blocks")?;
+    for _ in 0..num_blocks {
+        // entityDim entityTag parametric numNodesInBlock
+        let (line, words) = lines.next("node block")?;
+        let num_nodes: usize = parse_word(words, 3, line, "number of nodes in block")?;
+        let mut tags = Vec::new();
+        for _ in 0..num_nodes {
+            let (line, words) = lines.next("node tag")?;
+            tags.push(parse_word::<usize>(words, 0, line, "node tag")?);
+        }
+        // x y z [u [v [w]]]
+        for tag in tags {
+            let (line, words) = lines.next("node coordinates")?;
+            data.add_node(tag, words, 0, line)?;
+        }
+    }
+    lines.expect("$EndNodes")
+}
+
+fn read_elements_v4(lines: &mut Lines, data: &mut MshData) -> Result<(), MeshError> {
+    // numEntityBlocks numElements minElementTag maxElementTag
+    let (line, words) = lines.next("elements header")?;
+    let num_blocks: usize = parse_word(words, 0, line, "number of entity blocks")?;
+    for _ in 0..num_blocks {
+        // entityDim entityTag elementType numElementsInBlock
+        let (line, words) = lines.next("element block")?;
+        let element_type: usize = parse_word(words, 2, line, "element type")?;
+        let num_elements: usize = parse_word(words, 3, line, "number of elements in block")?;
+        for _ in 0..num_elements {
+            // elementTag nodeTag ...
+            let (line, words) = lines.next("element")?;
+            data.add_element(element_type, words, 1, line)?;
+        }
+    }
+    lines.expect("$EndElements")
+}
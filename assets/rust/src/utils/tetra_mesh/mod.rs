@@ -0,0 +1,448 @@
+mod gmsh;
+mod obj;
+mod tetgen;
+mod tetrahedralize;
+
+pub use tetrahedralize::TriangleSurface;
+pub(crate) use tetrahedralize::{CUBE_TETS, check_closed, winding_number};
+
+use nalgebra::{Matrix3, Vector3};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+pub struct TetraMesh {
+    pub positions: Vec<Vector3<f64>>,
+    pub tetr_indexes: Vec<[usize; 4]>,
+    // unique edges, two indexes per edge
+    pub edge_indexes: Vec<usize>,
+    // boundary triangles (faces of exactly one tet), wound counterclockwise seen from outside
+    pub surface_indexes: Vec<[usize; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshError {
+    Load(String),
+    Parse {
+        line: usize,
+        message: String,
+    },
+    Inverted {
+        line: usize,
+        tet: usize,
+        volume: f64,
+    },
+    Degenerate {
+        line: usize,
+        tet: usize,
+        volume: f64,
+    },
+    NotClosed {
+        edge: (usize, usize),
+    },
+    Empty {
+        resolution: usize,
+    },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshError::Load(message) => write!(f, "failed to load mesh: {}", message),
+            MeshError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            MeshError::Inverted { line, tet, volume } => write!(
+                f,
+                "line {}: tetrahedron {} is inverted (volume {:e})",
+                line, tet, volume
+            ),
+            MeshError::Degenerate { line, tet, volume } => write!(
+                f,
+                "line {}: tetrahedron {} is degenerate (volume {:e})",
+                line, tet, volume
+            ),
+            MeshError::NotClosed { edge } => {
+                write!(f, "surface is not closed at edge {}-{}", edge.0, edge.1)
+            }
+            MeshError::Empty { resolution } => write!(
+                f,
+                "no grid cell inside the surface at resolution {}",
+                resolution
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+pub(crate) fn parse_error(line: usize, message: impl Into<String>) -> MeshError {
+    MeshError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+// Non-empty lines with '#' comments stripped, numbered from 1.
+pub(crate) fn data_lines(text: &str) -> impl Iterator<Item = (usize, Vec<&str>)> {
+    text.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.split('#').next().unwrap_or("");
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            None
+        } else {
+            Some((i + 1, words))
+        }
+    })
+}
+
+pub(crate) fn parse_word<T: std::str::FromStr>(
+    words: &[&str],
+    i: usize,
+    line: usize,
+    what: &str,
+) -> Result<T, MeshError> {
+    let word = words
+        .get(i)
+        .ok_or_else(|| parse_error(line, format!("missing {}", what)))?;
+    word.parse::<T>()
+        .map_err(|_| parse_error(line, format!("invalid {} '{}'", what, word)))
+}
+
+// Signed volume, positive when p3 is on the side of (p1 - p0) x (p2 - p0).
+pub fn tet_volume(positions: &[Vector3<f64>], tet: &[usize; 4]) -> f64 {
+    let p0 = positions[tet[0]];
+    let m = Matrix3::from_columns(&[
+        positions[tet[1]] - p0,
+        positions[tet[2]] - p0,
+        positions[tet[3]] - p0,
+    ]);
+    m.determinant() / 6.0
+}
+
+fn is_degenerate(positions: &[Vector3<f64>], tet: &[usize; 4], volume: f64) -> bool {
+    let mut max_edge: f64 = 0.0;
+    for i in 0..4 {
+        for j in i + 1..4 {
+            max_edge = max_edge.max((positions[tet[i]] - positions[tet[j]]).norm());
+        }
+    }
+    volume.abs() <= 1e-10 * max_edge.powi(3)
+}
+
+impl TetraMesh {
+    // Loads a mesh by extension: .obj, .msh, or a TetGen .node/.ele pair
+    // (either file name, the .face file is optional).
+    pub async fn new(path_to_assets: &str, file_name: &str) -> Result<Self, MeshError> {
+        let (stem, ext) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
+        match ext {
+            "obj" => Self::from_obj(&load_text(path_to_assets, file_name).await?),
+            "msh" => Self::from_msh(&load_text(path_to_assets, file_name).await?),
+            "node" | "ele" => {
+                let node = load_text(path_to_assets, &format!("{}.node", stem)).await?;
+                let ele = load_text(path_to_assets, &format!("{}.ele", stem)).await?;
+                let face = load_text(path_to_assets, &format!("{}.face", stem))
+                    .await
+                    .ok();
+                Self::from_tetgen(&node, &ele, face.as_deref())
+            }
+            _ => Err(MeshError::Load(format!(
+                "unknown tetrahedral mesh format '{}'",
+                file_name
+            ))),
+        }
+    }
+
+    // Loads a closed triangle surface OBJ and fills it with tetrahedra,
+    // see tetrahedralize for the meaning of resolution.
+    pub async fn from_surface(
+        path_to_assets: &str,
+        file_name: &str,
+        resolution: usize,
+    ) -> Result<Self, MeshError> {
+        let text = load_text(path_to_assets, file_name).await?;
+        Self::tetrahedralize(&Self::surface_from_obj(&text)?, resolution)
+    }
+
+    fn from_tets(positions: Vec<Vector3<f64>>, tetr_indexes: Vec<[usize; 4]>) -> Self {
+        let edge_indexes = Self::unique_edges(&tetr_indexes);
+        let surface_indexes = Self::boundary_surface(&positions, &tetr_indexes);
+        TetraMesh {
+            positions,
+            tetr_indexes,
+            edge_indexes,
+            surface_indexes,
+        }
+    }
+
+    fn unique_edges(tetr_indexes: &[[usize; 4]]) -> Vec<usize> {
+        const TET_EDGES: [[usize; 2]; 6] = [[0, 1], [0, 2], [0, 3], [1, 2], [1, 3], [2, 3]];
+        let mut seen = HashSet::new();
+        let mut edge_indexes = Vec::new();
+        for tet in tetr_indexes {
+            // Создание рёбер
+            for [a, b] in TET_EDGES {
+                let (i1, i2) = (tet[a], tet[b]);
+                if seen.insert((i1.min(i2), i1.max(i2))) {
+                    edge_indexes.push(i1);
+                    edge_indexes.push(i2);
+                }
+            }
+        }
+        edge_indexes
+    }
+
+    pub(crate) fn boundary_surface(
+        positions: &[Vector3<f64>],
+        tetr_indexes: &[[usize; 4]],
+    ) -> Vec<[usize; 3]> {
+        // face -> opposite vertex, interior faces are seen twice and removed
+        const TET_FACES: [[usize; 4]; 4] = [[1, 2, 3, 0], [0, 2, 3, 1], [0, 1, 3, 2], [0, 1, 2, 3]];
+        let mut faces: HashMap<[usize; 3], Option<([usize; 3], usize)>> = HashMap::new();
+        let mut order = Vec::new();
+        for tet in tetr_indexes {
+            for [a, b, c, d] in TET_FACES {
+                let face = [tet[a], tet[b], tet[c]];
+                let mut key = face;
+                key.sort_unstable();
+                match faces.entry(key) {
+                    Entry::Vacant(e) => {
+                        e.insert(Some((face, tet[d])));
+                        order.push(key);
+                    }
+                    Entry::Occupied(mut e) => {
+                        e.insert(None);
+                    }
+                }
+            }
+        }
+
+        order
+            .iter()
+            .filter_map(|key| faces[key])
+            .map(|([a, b, c], opposite)| {
+                let n = (positions[b] - positions[a]).cross(&(positions[c] - positions[a]));
+                if n.dot(&(positions[opposite] - positions[a])) > 0.0 {
+                    [a, c, b]
+                } else {
+                    [a, b, c]
+                }
+            })
+            .collect()
+    }
+
+    // Checks every tetrahedron, tet_lines gives the source line of each one.
+    // With drop_degenerate, zero volume tets are removed instead of rejected and
+    // their number is returned.
+    fn validate(
+        positions: &[Vector3<f64>],
+        tets: &mut Vec<[usize; 4]>,
+        tet_lines: &[usize],
+        drop_degenerate: bool,
+    ) -> Result<usize, MeshError> {
+        let mut valid = Vec::with_capacity(tets.len());
+        for (i, tet) in tets.iter().enumerate() {
+            let volume = tet_volume(positions, tet);
+            if is_degenerate(positions, tet, volume) {
+                if drop_degenerate {
+                    continue;
+                }
+                return Err(MeshError::Degenerate {
+                    line: tet_lines[i],
+                    tet: i,
+                    volume,
+                });
+            }
+            if volume < 0.0 {
+                return Err(MeshError::Inverted {
+                    line: tet_lines[i],
+                    tet: i,
+                    volume,
+                });
+            }
+            valid.push(*tet);
+        }
+        let dropped = tets.len() - valid.len();
+        *tets = valid;
+        Ok(dropped)
+    }
+}
+
+pub(crate) async fn load_text(path_to_assets: &str, file_name: &str) -> Result<String, MeshError> {
+    let path = format!("{}/{}", path_to_assets, file_name);
+    let asset = three_d_asset::io::load_async(&[path])
+        .await
+        .map_err(|e| MeshError::Load(e.to_string()))?;
+    let asset_data: &[u8] = asset
+        .get(file_name)
+        .map_err(|e| MeshError::Load(e.to_string()))?;
+    String::from_utf8(asset_data.to_vec())
+        .map_err(|_| MeshError::Load(format!("{} is not valid utf-8", file_name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE: &str = "4 3 0 0\n1 0 0 0\n2 1 0 0\n3 0 1 0\n4 0 0 1\n";
+
+    fn parse_line(result: Result<TetraMesh, MeshError>) -> usize {
+        match result {
+            Err(MeshError::Parse { line, .. }) => line,
+            Err(e) => panic!("expected a parse error, got {}", e),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn tetgen_single_tet() {
+        let mesh = TetraMesh::from_tetgen(NODE, "1 4 0\n1 1 2 3 4\n", None).unwrap();
+        assert_eq!(mesh.tetr_indexes, vec![[0, 1, 2, 3]]);
+        assert_eq!(mesh.surface_indexes.len(), 4);
+        assert_eq!(mesh.edge_indexes.len(), 12);
+    }
+
+    #[test]
+    fn tetgen_huge_counts_are_errors() {
+        let huge = format!("{} 3 0 0\n", usize::MAX);
+        assert_eq!(parse_line(TetraMesh::from_tetgen(&huge, "", None)), 1);
+        let ele = format!("{} 4 0\n1 1 2 3 4\n", usize::MAX);
+        assert_eq!(parse_line(TetraMesh::from_tetgen(NODE, &ele, None)), 2);
+    }
+
+    #[test]
+    fn tetgen_errors_have_lines() {
+        let node = "4 3 0 0\n1 0 0 0\n2 1 0 0\n3 0 x 0\n4 0 0 1\n";
+        assert_eq!(parse_line(TetraMesh::from_tetgen(node, "", None)), 4);
+        let ele = "# comment\n1 4 0\n1 1 2 3 5\n";
+        assert_eq!(parse_line(TetraMesh::from_tetgen(NODE, ele, None)), 3);
+        assert_eq!(parse_line(TetraMesh::from_tetgen("4 2 0 0\n", "", None)), 1);
+    }
+
+    #[test]
+    fn tetgen_rejects_bad_tets() {
+        let inverted = TetraMesh::from_tetgen(NODE, "1 4 0\n1 2 1 3 4\n", None);
+        assert!(matches!(inverted, Err(MeshError::Inverted { line: 2, .. })));
+        let flat = "4 3 0 0\n1 0 0 0\n2 1 0 0\n3 0 1 0\n4 1 1 0\n";
+        let degenerate = TetraMesh::from_tetgen(flat, "1 4 0\n1 1 2 3 4\n", None);
+        assert!(matches!(
+            degenerate,
+            Err(MeshError::Degenerate { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn msh_errors_have_lines() {
+        let truncated = "$MeshFormat\n2.2 0 8\n$EndMeshFormat\n$Nodes\n4\n1 0 0 0\n";
+        assert_eq!(parse_line(TetraMesh::from_msh(truncated)), 6);
+        let binary = "$MeshFormat\n4.1 1 8\n$EndMeshFormat\n";
+        assert_eq!(parse_line(TetraMesh::from_msh(binary)), 2);
+        let unknown_node = "$MeshFormat\n2.2 0 8\n$EndMeshFormat\n$Nodes\n1\n1 0 0 0\n\
+                            $EndNodes\n$Elements\n1\n1 4 0 1 2 3 4\n$EndElements\n";
+        assert_eq!(parse_line(TetraMesh::from_msh(unknown_node)), 10);
+        let huge = format!(
+            "$MeshFormat\n4.1 0 8\n$EndMeshFormat\n$Nodes\n1 1 1 1\n3 1 0 {}\n",
+            usize::MAX
+        );
+        assert_eq!(parse_line(TetraMesh::from_msh(&huge)), 6);
+    }
+
+    // two tets sharing a face as Gmsh 4.1 writes them, with a skipped
+    // $Entities section, a surface triangle and the nodes split in two blocks
+    const MSH_41: &str = "$MeshFormat\n4.1 0 8\n$EndMeshFormat\n\
+                          $Entities\n0 0 1 1\n1 0 0 0 1 1 0 0 0\n1 0 0 -1 1 1 1 0 0\n$EndEntities\n\
+                          $Nodes\n2 5 1 5\n\
+                          2 1 0 3\n1\n2\n3\n0 0 0\n1 0 0\n0 1 0\n\
+                          3 1 0 2\n4\n5\n0 0 1\n0 0 -1\n\
+                          $EndNodes\n\
+                          $Elements\n2 3 1 3\n\
+                          2 1 2 1\n1 1 2 3\n\
+                          3 1 4 2\n2 1 2 3 4\n3 1 3 2 5\n\
+                          $EndElements\n";
+
+    #[test]
+    fn msh_41_loads() {
+        let mesh = TetraMesh::from_msh(MSH_41).unwrap();
+        assert_eq!(mesh.positions.len(), 5);
+        assert_eq!(mesh.positions[4], Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(mesh.tetr_indexes, vec![[0, 1, 2, 3], [0, 2, 1, 4]]);
+        // the shared face is inside
+        assert_eq!(mesh.surface_indexes.len(), 6);
+        check_closed(&mesh.surface_indexes).unwrap();
+
+        let v40 = MSH_41.replacen("4.1", "4.0", 1);
+        let error = TetraMesh::from_msh(&v40).err().unwrap();
+        assert_eq!(parse_line(Err(error.clone())), 2);
+        assert!(error.to_string().contains("4.1"), "{}", error);
+    }
+
+    #[test]
+    fn obj_errors_have_lines_and_slivers_are_dropped() {
+        let vertices = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nv 1 1 0\n";
+        let out_of_range = format!("{}f 1 2 3 6\n", vertices);
+        assert_eq!(parse_line(TetraMesh::from_obj(&out_of_range)), 6);
+        let triangle = format!("{}f 1 2 3\n", vertices);
+        assert_eq!(parse_line(TetraMesh::from_obj(&triangle)), 6);
+        let sliver = format!("{}f 1 2 3 4\nf 1 2 3 5\n", vertices);
+        let mesh = TetraMesh::from_obj(&sliver).unwrap();
+        assert_eq!(mesh.tetr_indexes, vec![[0, 1, 2, 3]]);
+    }
+
+    // unit cube, quads wound counterclockwise seen from outside
+    const CUBE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\n\
+                        f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 4 8 7 3\nf 1 5 8 4\nf 2 3 7 6\n";
+
+    #[test]
+    fn tetrahedralized_cube() {
+        let surface = TetraMesh::surface_from_obj(CUBE).unwrap();
+        let mut mesh = TetraMesh::tetrahedralize(&surface, 4).unwrap();
+        assert_eq!(mesh.tetr_indexes.len(), 6 * 4 * 4 * 4);
+        let volume: f64 = mesh
+            .tetr_indexes
+            .iter()
+            .map(|tet| tet_volume(&mesh.positions, tet))
+            .sum();
+        assert!((volume - 1.0).abs() < 1e-9, "volume {}", volume);
+        let lines = vec![0; mesh.tetr_indexes.len()];
+        let dropped = TetraMesh::validate(&mesh.positions, &mut mesh.tetr_indexes, &lines, false);
+        assert_eq!(dropped, Ok(0));
+
+        // closed, and enclosing a positive volume only when wound outward
+        check_closed(&mesh.surface_indexes).unwrap();
+        let enclosed: f64 = mesh
+            .surface_indexes
+            .iter()
+            .map(|&[a, b, c]| {
+                let p = &mesh.positions;
+                p[a].dot(&p[b].cross(&p[c])) / 6.0
+            })
+            .sum();
+        assert!((enclosed - 1.0).abs() < 1e-9, "enclosed {}", enclosed);
+    }
+
+    #[test]
+    fn open_surface_is_rejected() {
+        let mut surface = TetraMesh::surface_from_obj(CUBE).unwrap();
+        surface.triangles.pop();
+        assert!(matches!(
+            check_closed(&surface.triangles),
+            Err(MeshError::NotClosed { .. })
+        ));
+        assert!(matches!(
+            TetraMesh::tetrahedralize(&surface, 4),
+            Err(MeshError::NotClosed { .. })
+        ));
+    }
+
+    #[test]
+    fn sphere_asset_fills() {
+        let text = include_str!("../../assets/sphere.obj");
+        let surface = TetraMesh::surface_from_obj(text).unwrap();
+        let mesh = TetraMesh::tetrahedralize(&surface, 8).unwrap();
+        let volume: f64 = mesh
+            .tetr_indexes
+            .iter()
+            .map(|tet| tet_volume(&mesh.positions, tet))
+            .sum();
+        let sphere = 4.0 / 3.0 * std::f64::consts::PI;
+        assert!((volume / sphere - 1.0).abs() < 0.2, "volume {}", volume);
+    }
+}
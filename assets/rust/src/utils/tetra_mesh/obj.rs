@@ -0,0 +1,55 @@
+use super::{MeshError, TetraMesh, data_lines, parse_error, parse_word};
+use crate::utils::console;
+use nalgebra::Vector3;
+
+impl TetraMesh {
+    // OBJ with one `f` record of four vertices per tetrahedron, as exported by
+    // Blender. The exporter emits zero volume slivers, those are dropped and counted
+    // in the log.
+    pub fn from_obj(text: &str) -> Result<Self, MeshError> {
+        let mut positions: Vec<Vector3<f64>> = Vec::new();
+        let mut tetr_indexes: Vec<[usize; 4]> = Vec::new();
+        let mut tet_lines: Vec<usize> = Vec::new();
+
+        for (line, words) in data_lines(text) {
+            match words[0] {
+                "v" => {
+                    // Чтение позиции вершины
+                    let x = parse_word(&words, 1, line, "x coordinate")?;
+                    let y = parse_word(&words, 2, line, "y coordinate")?;
+                    let z = parse_word(&words, 3, line, "z coordinate")?;
+                    positions.push(Vector3::new(x, y, z));
+                }
+                "f" => {
+                    // Чтение индексов вершин тетраэдра
+                    if words.len() != 5 {
+                        return Err(parse_error(
+                            line,
+                            format!("expected 4 vertices per face, got {}", words.len() - 1),
+                        ));
+                    }
+                    let mut tet = [0; 4];
+                    for (k, word) in words[1..].iter().enumerate() {
+                        let index = word.split('/').next().unwrap_or("");
+                        let index: usize = parse_word(&[index], 0, line, "vertex index")?;
+                        if index == 0 || index > positions.len() {
+                            return Err(parse_error(
+                                line,
+                                format!("vertex index {} out of range", index),
+                            ));
+                        }
+                        tet[k] = index - 1;
+                    }
+                    tetr_indexes.push(tet);
+                    tet_lines.push(line);
+                }
+                _ => {}
+            }
+        }
+        let dropped = Self::validate(&positions, &mut tetr_indexes, &tet_lines, true)?;
+        if dropped > 0 {
+            console::log(&format!("dropped {} degenerate tetrahedra", dropped));
+        }
+        Ok(Self::from_tets(positions, tetr_indexes))
+    }
+}
@@ -0,0 +1,129 @@
+use super::{MeshError, TetraMesh, data_lines, parse_error, parse_word};
+use nalgebra::Vector3;
+
+// Maps a file index to a position index, TetGen numbers from 0 or 1.
+fn node_index(
+    words: &[&str],
+    i: usize,
+    line: usize,
+    first_index: usize,
+    num_nodes: usize,
+) -> Result<usize, MeshError> {
+    let index: usize = parse_word(words, i, line, "node index")?;
+    if index < first_index || index - first_index >= num_nodes {
+        return Err(parse_error(
+            line,
+            format!("node index {} out of range", index),
+        ));
+    }
+    Ok(index - first_index)
+}
+
+fn header<'a>(
+    lines: &mut impl Iterator<Item = (usize, Vec<&'a str>)>,
+    file: &str,
+) -> Result<(usize, Vec<&'a str>), MeshError> {
+    lines
+        .next()
+        .ok_or_else(|| parse_error(1, format!("empty .{} file", file)))
+}
+
+impl TetraMesh {
+    // TetGen .node/.ele files, the .face file with boundary triangles is optional.
+    // Only the four corner nodes of quadratic tetrahedra are used.
+    pub fn from_tetgen(node: &str, ele: &str, face: Option<&str>) -> Result<Self, MeshError> {
+        // .node: <# of points> <dimension (3)> <# of attributes> <boundary markers (0 or 1)>
+        let mut lines = data_lines(node);
+        let (line, words) = header(&mut lines, "node")?;
+        let num_nodes: usize = parse_word(&words, 0, line, "number of nodes")?;
+        let dim: usize = parse_word(&words, 1, line, "dimension")?;
+        if dim != 3 {
+            return Err(parse_error(
+                line,
+                format!("expected dimension 3, got {}", dim),
+            ));
+        }
+
+        // the counts are not trusted for allocation, a corrupt header must not abort
+        let mut positions = Vec::new();
+        let mut first_index = 0;
+        for (line, words) in lines.by_ref().take(num_nodes) {
+            let index: usize = parse_word(&words, 0, line, "node index")?;
+            if positions.is_empty() {
+                first_index = index;
+            }
+            if index != first_index + positions.len() {
+                return Err(parse_error(
+                    line,
+                    format!(
+                        "expected node {}, got {}",
+                        first_index + positions.len(),
+                        index
+                    ),
+                ));
+            }
+            let x = parse_word(&words, 1, line, "x coordinate")?;
+            let y = parse_word(&words, 2, line, "y coordinate")?;
+            let z = parse_word(&words, 3, line, "z coordinate")?;
+            positions.push(Vector3::new(x, y, z));
+        }
+        if positions.len() != num_nodes {
+            return Err(parse_error(
+                node.lines().count(),
+                format!("expected {} nodes, got {}", num_nodes, positions.len()),
+            ));
+        }
+
+        // .ele: <# of tetrahedra> <nodes per tet. (4 or 10)> <region attribute (0 or 1)>
+        let mut lines = data_lines(ele);
+        let (line, words) = header(&mut lines, "ele")?;
+        let num_tets: usize = parse_word(&words, 0, line, "number of tetrahedra")?;
+        let nodes_per_tet: usize = parse_word(&words, 1, line, "nodes per tetrahedron")?;
+        if nodes_per_tet != 4 && nodes_per_tet != 10 {
+            return Err(parse_error(
+                line,
+                format!(
+                    "expected 4 or 10 nodes per tetrahedron, got {}",
+                    nodes_per_tet
+                ),
+            ));
+        }
+
+        let mut tetr_indexes = Vec::new();
+        let mut tet_lines = Vec::new();
+        for (line, words) in lines.by_ref().take(num_tets) {
+            let mut tet = [0; 4];
+            for (k, id) in tet.iter_mut().enumerate() {
+                *id = node_index(&words, k + 1, line, first_index, num_nodes)?;
+            }
+            tetr_indexes.push(tet);
+            tet_lines.push(line);
+        }
+        if tetr_indexes.len() != num_tets {
+            return Err(parse_error(
+                ele.lines().count(),
+                format!(
+                    "expected {} tetrahedra, got {}",
+                    num_tets,
+                    tetr_indexes.len()
+                ),
+            ));
+        }
+
+        // .face: <# of faces> <boundary marker (0 or 1)>
+        // only checked, the surface is always rebuilt from the tetrahedra
+        if let Some(face) = face {
+            let mut lines = data_lines(face);
+            let (line, words) = header(&mut lines, "face")?;
+            let num_faces: usize = parse_word(&words, 0, line, "number of faces")?;
+            for (line, words) in lines.take(num_faces) {
+                for k in 0..3 {
+                    node_index(&words, k + 1, line, first_index, num_nodes)?;
+                }
+            }
+        }
+
+        Self::validate(&positions, &mut tetr_indexes, &tet_lines, false)?;
+        Ok(Self::from_tets(positions, tetr_indexes))
+    }
+}
@@ -0,0 +1,166 @@
+use super::{MeshError, TetraMesh, data_lines, parse_error, parse_word, tet_volume};
+use nalgebra::Vector3;
+use std::collections::HashMap;
+
+// Kuhn subdivision of a cube into 6 tets around the 0-7 diagonal, corner bits are x, y, z.
+// Every cube is split the same way, so the faces of neighbouring cubes match.
+pub(crate) const CUBE_TETS: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 1, 5, 7],
+    [0, 2, 3, 7],
+    [0, 2, 6, 7],
+    [0, 4, 5, 7],
+    [0, 4, 6, 7],
+];
+
+pub struct TriangleSurface {
+    pub positions: Vec<Vector3<f64>>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+// Generalized winding number of a closed surface around p, ~1 inside and ~0 outside.
+pub(crate) fn winding_number(
+    positions: &[Vector3<f64>],
+    triangles: &[[usize; 3]],
+    p: &Vector3<f64>,
+) -> f64 {
+    let mut solid_angle = 0.0;
+    for &[i, j, k] in triangles {
+        let a = positions[i] - p;
+        let b = positions[j] - p;
+        let c = positions[k] - p;
+        let (la, lb, lc) = (a.norm(), b.norm(), c.norm());
+        let det = a.dot(&b.cross(&c));
+        let div = la * lb * lc + a.dot(&b) * lc + b.dot(&c) * la + c.dot(&a) * lb;
+        solid_angle += 2.0 * det.atan2(div);
+    }
+    solid_angle / (4.0 * std::f64::consts::PI)
+}
+
+// Every edge of a closed surface is used once in each direction.
+pub(crate) fn check_closed(triangles: &[[usize; 3]]) -> Result<(), MeshError> {
+    let mut edges: HashMap<(usize, usize), i32> = HashMap::new();
+    for tri in triangles {
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            *edges.entry((a.min(b), a.max(b))).or_default() += if a < b { 1 } else { -1 };
+        }
+    }
+    match edges.iter().find(|(_, count)| **count != 0) {
+        Some((&edge, _)) => Err(MeshError::NotClosed { edge }),
+        None => Ok(()),
+    }
+}
+
+impl TetraMesh {
+    // Triangle surface OBJ, polygons are split into fans. Texture and normal
+    // indexes are ignored.
+    pub fn surface_from_obj(text: &str) -> Result<TriangleSurface, MeshError> {
+        let mut positions: Vec<Vector3<f64>> = Vec::new();
+        let mut triangles: Vec<[usize; 3]> = Vec::new();
+        for (line, words) in data_lines(text) {
+            match words[0] {
+                "v" => {
+                    let x = parse_word(&words, 1, line, "x coordinate")?;
+                    let y = parse_word(&words, 2, line, "y coordinate")?;
+                    let z = parse_word(&words, 3, line, "z coordinate")?;
+                    positions.push(Vector3::new(x, y, z));
+                }
+                "f" => {
+                    if words.len() < 4 {
+                        return Err(parse_error(
+                            line,
+                            format!(
+                                "expected at least 3 vertices per face, got {}",
+                                words.len() - 1
+                            ),
+                        ));
+                    }
+                    let mut face = Vec::with_capacity(words.len() - 1);
+                    for word in &words[1..] {
+                        let index = word.split('/').next().unwrap_or("");
+                        let index: usize = parse_word(&[index], 0, line, "vertex index")?;
+                        if index == 0 || index > positions.len() {
+                            return Err(parse_error(
+                                line,
+                                format!("vertex index {} out of range", index),
+                            ));
+                        }
+                        face.push(index - 1);
+                    }
+                    for k in 1..face.len() - 1 {
+                        triangles.push([face[0], face[k], face[k + 1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(TriangleSurface {
+            positions,
+            triangles,
+        })
+    }
+
+    // Fills a closed triangle surface with tetrahedra on a regular grid.
+    // resolution is the number of cells along the longest side of the bounding
+    // box, cells with the center inside the surface are split into 6 tets.
+    pub fn tetrahedralize(surface: &TriangleSurface, resolution: usize) -> Result<Self, MeshError> {
+        let TriangleSurface {
+            positions,
+            triangles,
+        } = surface;
+        let resolution = resolution.max(1);
+        if triangles.is_empty() {
+            return Err(MeshError::Empty { resolution });
+        }
+        check_closed(triangles)?;
+        let mut min = Vector3::repeat(f64::MAX);
+        let mut max = Vector3::repeat(f64::MIN);
+        for tri in triangles {
+            for &i in tri {
+                min = min.inf(&positions[i]);
+                max = max.sup(&positions[i]);
+            }
+        }
+        let size = (max - min).max() / resolution as f64;
+        if size <= 0.0 {
+            return Err(MeshError::Empty { resolution });
+        }
+        let dims = ((max - min) / size).map(|x| (x.ceil() as usize).max(1));
+        let cell_pos =
+            |x: usize, y: usize, z: usize| min + Vector3::new(x as f64, y as f64, z as f64) * size;
+
+        let mut grid_ids: HashMap<[usize; 3], usize> = HashMap::new();
+        let mut tet_positions = Vec::new();
+        let mut tetr_indexes = Vec::new();
+        for z in 0..dims.z {
+            for y in 0..dims.y {
+                for x in 0..dims.x {
+                    let center = cell_pos(x, y, z) + Vector3::repeat(0.5 * size);
+                    if winding_number(positions, triangles, &center) < 0.5 {
+                        continue;
+                    }
+                    let mut corners = [0; 8];
+                    for (bits, id) in corners.iter_mut().enumerate() {
+                        let key = [x + (bits & 1), y + ((bits >> 1) & 1), z + ((bits >> 2) & 1)];
+                        *id = *grid_ids.entry(key).or_insert_with(|| {
+                            tet_positions.push(cell_pos(key[0], key[1], key[2]));
+                            tet_positions.len() - 1
+                        });
+                    }
+                    for local in CUBE_TETS {
+                        let mut tet = local.map(|k| corners[k]);
+                        if tet_volume(&tet_positions, &tet) < 0.0 {
+                            tet.swap(2, 3);
+                        }
+                        tetr_indexes.push(tet);
+                    }
+                }
+            }
+        }
+        if tetr_indexes.is_empty() {
+            return Err(MeshError::Empty { resolution });
+        }
+        Ok(Self::from_tets(tet_positions, tetr_indexes))
+    }
+}
@@ -1,22 +1,65 @@
+use crate::utils::diagnostics::Diagnostics;
 use three_d::egui::*;
-use three_d::{egui::Color32, FrameInput, GUI};
+use three_d::{FrameInput, GUI, egui::Color32};
 
 pub static BACKGROUND: Color32 = Color32::from_rgba_premultiplied(200, 200, 250, 150);
 pub static BUTTON_BACKGROUND: Color32 = Color32::from_rgba_premultiplied(150, 150, 200, 255);
 pub static TEXT_COLOR: Color32 = Color32::from_rgb(0, 0, 0);
+pub static PLOT_COLOR: Color32 = Color32::from_rgb(140, 40, 40);
+
+pub struct ReplayState {
+    pub recording: bool,
+    pub start_recording: bool,
+    pub replaying: bool,
+    // current substep in the recording and the number of recorded substeps
+    pub position: usize,
+    pub len: usize,
+    pub save: bool,
+    pub load: bool,
+    pub path: String,
+}
+impl ReplayState {
+    pub fn new() -> Self {
+        Self {
+            recording: false,
+            start_recording: false,
+            replaying: false,
+            position: 0,
+            len: 0,
+            save: false,
+            load: false,
+            path: "recording.bin".to_string(),
+        }
+    }
+}
+impl Default for ReplayState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 pub struct BaseState {
     // functor for the system that reset system
     pub reset: bool,
     pub stop: bool,
     pub fps: f64,
+    pub replay: ReplayState,
+    pub diagnostics: Diagnostics,
+}
+impl Default for BaseState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl BaseState {
     pub fn new() -> Self {
         Self {
             reset: false,
             stop: false,
             fps: 0.0,
+            replay: ReplayState::new(),
+            diagnostics: Diagnostics::default(),
         }
     }
 }
@@ -25,6 +68,12 @@ pub struct FPSCounter {
     fps: f64,
     time: f64,
 }
+impl Default for FPSCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FPSCounter {
     pub fn new() -> Self {
         Self {
@@ -60,6 +109,9 @@ pub fn gui_logic(
                     ui.horizontal(|ui| {
                         gui_logic_horizontal(ui, state, horizontal_ui);
                     });
+                    if state.diagnostics.show {
+                        diagnostics_ui(ui, &state.diagnostics);
+                    }
                     vertical_ui(ui, state);
                 });
         },
@@ -97,6 +149,110 @@ fn gui_logic_horizontal(
                         .color(TEXT_COLOR);
                     ui.label(fps_text);
                 });
+            diagnostics_buttons(ui, &mut state.diagnostics);
+            replay_ui(ui, &mut state.replay);
             horizontal_ui(ui, state);
         });
 }
+
+fn diagnostics_buttons(ui: &mut three_d::egui::Ui, diagnostics: &mut Diagnostics) {
+    if diagnostics.is_empty() {
+        return;
+    }
+    let button = |text: &str| {
+        Button::new(RichText::new(text).color(TEXT_COLOR).strong()).fill(BUTTON_BACKGROUND)
+    };
+    let plots_text = if diagnostics.show {
+        "Hide plots"
+    } else {
+        "Plots"
+    };
+    if ui.add(button(plots_text)).clicked() {
+        diagnostics.show = !diagnostics.show;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if ui.add(button("CSV")).clicked()
+        && let Err(e) = diagnostics.save_csv()
+    {
+        crate::utils::console::log(&format!("failed to save {}: {}", diagnostics.path, e));
+    }
+}
+
+// One rolling plot per quantity, scaled to its range, with the latest value.
+fn diagnostics_ui(ui: &mut three_d::egui::Ui, diagnostics: &Diagnostics) {
+    Frame::none()
+        .fill(BACKGROUND)
+        .rounding(Rounding::same(3.0))
+        .inner_margin(Margin::symmetric(2.0, 2.0))
+        .show(ui, |ui| {
+            for (k, name) in diagnostics.names.iter().enumerate() {
+                let series = diagnostics.series(k);
+                ui.horizontal(|ui| {
+                    let (response, painter) =
+                        ui.allocate_painter(vec2(240.0, 40.0), Sense::hover());
+                    let rect = response.rect;
+                    painter.rect_filled(rect, Rounding::same(2.0), Color32::WHITE);
+                    let lo = series.iter().copied().fold(f64::MAX, f64::min);
+                    let hi = series.iter().copied().fold(f64::MIN, f64::max);
+                    let span = (hi - lo).max(1e-12);
+                    let dx = rect.width() / (series.len().max(2) - 1) as f32;
+                    let points = series
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &v)| {
+                            let y = ((v - lo) / span) as f32;
+                            pos2(
+                                rect.left() + i as f32 * dx,
+                                rect.bottom() - y * rect.height(),
+                            )
+                        })
+                        .collect();
+                    painter.add(Shape::line(points, Stroke::new(1.5, PLOT_COLOR)));
+                    let last = series.back().copied().unwrap_or(0.0);
+                    ui.label(
+                        RichText::new(format!("{}: {:.4e}", name, last))
+                            .strong()
+                            .color(TEXT_COLOR),
+                    );
+                });
+            }
+        });
+}
+
+fn replay_ui(ui: &mut three_d::egui::Ui, replay: &mut ReplayState) {
+    let button = |text: &str| {
+        Button::new(RichText::new(text).color(TEXT_COLOR).strong()).fill(BUTTON_BACKGROUND)
+    };
+    let rec_text = if replay.recording { "Stop rec" } else { "Rec" };
+    if ui.add(button(rec_text)).clicked() {
+        replay.recording = !replay.recording;
+        replay.start_recording = replay.recording;
+        replay.replaying = false;
+    }
+    // a recording can be loaded into a fresh session
+    #[cfg(not(target_arch = "wasm32"))]
+    if ui.add(button("Load")).clicked() {
+        replay.load = true;
+    }
+    if replay.len == 0 {
+        return;
+    }
+    let replay_text = if replay.replaying { "Live" } else { "Replay" };
+    if ui.add(button(replay_text)).clicked() {
+        replay.replaying = !replay.replaying;
+        replay.recording = false;
+    }
+    if replay.replaying {
+        if ui.add(button("<")).clicked() {
+            replay.position = replay.position.saturating_sub(1);
+        }
+        ui.add(Slider::new(&mut replay.position, 0..=replay.len - 1).show_value(false));
+        if ui.add(button(">")).clicked() {
+            replay.position = (replay.position + 1).min(replay.len - 1);
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if ui.add(button("Save")).clicked() {
+        replay.save = true;
+    }
+}